@@ -0,0 +1,278 @@
+//! The accounts steps run as.
+//!
+//! Package managers installing into a home directory (rustup, brew, gem, ...) and those updating a
+//! project must not run as root: they leave files owned by root behind, and some refuse to run at
+//! all. When qud itself runs as root, their unprivileged steps run as the user who started qud
+//! through sudo or doas, or as the configured user, with that user's home and environment.
+//!
+//! With [`Config::all_users`], the user-level package managers of every [login user](login_users)
+//! are updated as well, each as its user.
+
+use crate::conf::Config;
+use crate::managers::Scope;
+use crate::self_up::perm::is_elevated;
+use serde::{Deserialize, Serialize};
+use std::env;
+use std::fs;
+use std::ops::RangeInclusive;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// A user account.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Account {
+    pub name: String,
+    pub uid: u32,
+    pub gid: u32,
+    pub home: PathBuf,
+    pub shell: String,
+}
+
+impl Account {
+    /// Looks up the account called `name` in the user database.
+    #[must_use]
+    pub fn lookup(name: &str) -> Option<Account> {
+        platform::lookup(name)
+    }
+
+    /// Makes `cmd` run as this account, with its groups, `HOME`, `USER`, `LOGNAME`, `SHELL` and runtime
+    /// directory. Other XDG directories are unset, since they belong to the current user.
+    pub fn apply(&self, cmd: &mut Command) {
+        platform::switch(cmd, self);
+        cmd.env("HOME", &self.home)
+            .env("USER", &self.name)
+            .env("LOGNAME", &self.name)
+            .env("SHELL", &self.shell);
+        for var in [
+            "XDG_CONFIG_HOME",
+            "XDG_CACHE_HOME",
+            "XDG_DATA_HOME",
+            "XDG_STATE_HOME",
+        ] {
+            cmd.env_remove(var);
+        }
+        let runtime_dir = PathBuf::from(format!("/run/user/{}", self.uid));
+        if runtime_dir.is_dir() {
+            cmd.env("XDG_RUNTIME_DIR", runtime_dir);
+        } else {
+            cmd.env_remove("XDG_RUNTIME_DIR");
+        }
+    }
+}
+
+/// The account the unprivileged steps of user-level and project package managers run as: if qud
+/// runs as root, the configured user, or else the one who started qud through sudo or doas.
+/// `None` if they run as qud itself.
+///
+/// # Errors
+/// Returns a message if the user does not exist.
+pub fn owner(config: &Config) -> Result<Option<Account>, String> {
+    if cfg!(target_os = "windows") || !is_elevated() {
+        return Ok(None);
+    }
+    let name = config.user.clone().or_else(|| {
+        ["SUDO_USER", "DOAS_USER"]
+            .iter()
+            .find_map(|var| env::var(var).ok().filter(|name| !name.is_empty()))
+    });
+    match name {
+        None => Ok(None),
+        Some(name) if name == "root" => Ok(None),
+        Some(name) => Account::lookup(&name).map(Some).ok_or_else(|| {
+            format!("Cannot run user-level package managers as {name}: no such user.")
+        }),
+    }
+}
+
+/// The accounts of people rather than services: those with a UID in `uids` (or, if `allow` is
+/// set, named in it), a login shell and a home directory. Root is left out.
+#[must_use]
+pub fn login_users(allow: Option<&[String]>, uids: &RangeInclusive<u32>) -> Vec<Account> {
+    platform::all()
+        .into_iter()
+        .filter(|account| account.uid != 0)
+        .filter(|account| match allow {
+            Some(names) => names.contains(&account.name),
+            None => uids.contains(&account.uid),
+        })
+        .filter(|account| {
+            let shell = Path::new(&account.shell).file_name();
+            !shell.is_some_and(|shell| shell == "nologin" || shell == "false")
+        })
+        .filter(|account| account.home.is_dir())
+        .collect()
+}
+
+/// The UIDs of login users: `UID_MIN` to `UID_MAX` from `/etc/login.defs`, or 1000 to 60000.
+#[must_use]
+pub fn default_uids() -> RangeInclusive<u32> {
+    let defs = fs::read_to_string("/etc/login.defs").unwrap_or_default();
+    let value = |key: &str| {
+        defs.lines().find_map(|line| {
+            let mut fields = line.split_whitespace();
+            (fields.next() == Some(key)).then(|| fields.next()?.parse::<u32>().ok())?
+        })
+    };
+    value("UID_MIN").unwrap_or(1000)..=value("UID_MAX").unwrap_or(60000)
+}
+
+/// As whom and where the unprivileged steps of a package manager with `scope` run, given the
+/// [`owner`] and the working directory: user-level package managers run in the owner's home (or
+/// `/` if it has none, since the working directory of root may be closed to them), project
+/// package managers in `dir`.
+#[must_use]
+pub fn placement<'a>(
+    scope: Scope,
+    owner: Option<&'a Account>,
+    dir: &'a Path,
+) -> (Option<&'a Account>, &'a Path) {
+    match (scope, owner) {
+        (Scope::System, _) | (_, None) => (None, dir),
+        (Scope::User, Some(owner)) if owner.home.is_dir() => (Some(owner), &owner.home),
+        (Scope::User, Some(owner)) => (Some(owner), Path::new("/")),
+        (Scope::Project, Some(owner)) => (Some(owner), dir),
+    }
+}
+
+#[cfg(any(
+    target_os = "linux",
+    target_vendor = "apple",
+    target_os = "freebsd",
+    target_os = "dragonfly",
+    target_os = "openbsd",
+    target_os = "netbsd"
+))]
+mod platform {
+    use super::Account;
+    use std::ffi::{CStr, CString};
+    use std::io;
+    use std::os::raw::{c_char, c_int};
+    use std::os::unix::process::CommandExt;
+    use std::path::PathBuf;
+    use std::process::Command;
+    use std::{mem, ptr};
+
+    /// Copies the entry `passwd` points to, which is only valid until the next lookup.
+    fn account(passwd: *const libc::passwd) -> Option<Account> {
+        let passwd = unsafe { passwd.as_ref()? };
+        let text = |field: *const c_char| {
+            if field.is_null() {
+                String::new()
+            } else {
+                unsafe { CStr::from_ptr(field) }
+                    .to_string_lossy()
+                    .into_owned()
+            }
+        };
+        Some(Account {
+            name: text(passwd.pw_name),
+            uid: passwd.pw_uid,
+            gid: passwd.pw_gid,
+            home: PathBuf::from(text(passwd.pw_dir)),
+            shell: text(passwd.pw_shell),
+        })
+    }
+
+    pub fn lookup(name: &str) -> Option<Account> {
+        let name = CString::new(name).ok()?;
+        let mut buf: Vec<c_char> = vec![0; 1024];
+        loop {
+            let mut passwd: libc::passwd = unsafe { mem::zeroed() };
+            let mut found = ptr::null_mut();
+            let err = unsafe {
+                libc::getpwnam_r(
+                    name.as_ptr(),
+                    &mut passwd,
+                    buf.as_mut_ptr(),
+                    buf.len(),
+                    &mut found,
+                )
+            };
+            match err {
+                libc::ERANGE if buf.len() < 1 << 20 => buf.resize(buf.len() * 2, 0),
+                0 => return account(found),
+                _ => return None,
+            }
+        }
+    }
+
+    pub fn all() -> Vec<Account> {
+        let mut accounts = Vec::new();
+        unsafe {
+            libc::setpwent();
+            while let Some(account) = account(libc::getpwent()) {
+                accounts.push(account);
+            }
+            libc::endpwent();
+        }
+        accounts
+    }
+
+    /// The groups of `account`: its primary group and those listing it as a member.
+    fn groups(account: &Account) -> Vec<libc::gid_t> {
+        let Ok(name) = CString::new(account.name.as_str()) else {
+            return vec![account.gid];
+        };
+        let mut groups: Vec<libc::gid_t> = vec![0; 64];
+        while groups.len() <= 1 << 16 {
+            let mut count = c_int::try_from(groups.len()).unwrap_or(c_int::MAX);
+            let found = unsafe {
+                libc::getgrouplist(
+                    name.as_ptr(),
+                    account.gid as _,
+                    groups.as_mut_ptr().cast(),
+                    &mut count,
+                )
+            };
+            if found >= 0 {
+                groups.truncate(usize::try_from(count).unwrap_or(0));
+                return groups;
+            }
+            // Linux reports the number needed, other systems leave `count` alone.
+            let needed = usize::try_from(count).unwrap_or(0).max(groups.len() * 2);
+            groups.resize(needed, 0);
+        }
+        vec![account.gid]
+    }
+
+    /// Makes `cmd` switch to the user and groups of `account`. `Command::uid` would drop root's
+    /// supplementary groups without setting the account's, and `pre_exec` runs after it, so all
+    /// of them are set here, groups first while the process is still privileged.
+    pub fn switch(cmd: &mut Command, account: &Account) {
+        let (uid, gid, groups) = (account.uid, account.gid, groups(account));
+        unsafe {
+            cmd.pre_exec(move || {
+                if libc::setgroups(groups.len() as _, groups.as_ptr()) != 0
+                    || libc::setgid(gid) != 0
+                    || libc::setuid(uid) != 0
+                {
+                    return Err(io::Error::last_os_error());
+                }
+                Ok(())
+            });
+        }
+    }
+}
+
+#[cfg(not(any(
+    target_os = "linux",
+    target_vendor = "apple",
+    target_os = "freebsd",
+    target_os = "dragonfly",
+    target_os = "openbsd",
+    target_os = "netbsd"
+)))]
+mod platform {
+    use super::Account;
+    use std::process::Command;
+
+    pub fn lookup(_name: &str) -> Option<Account> {
+        None
+    }
+
+    pub fn all() -> Vec<Account> {
+        Vec::new()
+    }
+
+    pub fn switch(_cmd: &mut Command, _account: &Account) {}
+}
@@ -0,0 +1,483 @@
+use colored::Colorize;
+use pico_args::Arguments;
+use qud::conf::{Config, FailurePolicy, HookField, Hooks, TimeoutField, Timeouts};
+use qud::history::Filter;
+use qud::self_up;
+use qud::summary::ExitCode;
+use std::collections::HashMap;
+use std::path::PathBuf;
+
+/// What qud was asked to do.
+pub enum Action {
+    /// Plan and run updates.
+    Update,
+    /// Plan updates and save the plan instead of running it.
+    Plan { output: PathBuf },
+    /// Run a previously saved plan.
+    Apply { plan: PathBuf },
+    /// List pending updates without applying them.
+    Outdated,
+    /// List recorded runs, or show one of them.
+    History {
+        filter: Filter,
+        show: Option<String>,
+    },
+}
+
+/// Parses the command line into an [`Action`] and a [`Config`], layered over configuration files
+/// and the environment. Handles `--help`, `--version` and `--self-update` directly and exits afterwards.
+#[allow(clippy::too_many_lines)]
+pub fn parse_args() -> (Action, Config) {
+    let mut pargs = Arguments::from_env();
+
+    // Help, version, and self updating.
+    if pargs.contains(["-h", "--help"]) {
+        print_help();
+        std::process::exit(0);
+    }
+    if pargs.contains(["-V", "--version"]) {
+        println!("qud v1.5.1");
+        std::process::exit(0);
+    }
+
+    let subcommand = pargs.subcommand().unwrap_or(None);
+    let mut action = match subcommand.as_deref() {
+        None => Action::Update,
+        // `-o` names the output file here; `--only` still filters.
+        Some("plan") => Action::Plan {
+            output: pargs
+                .opt_value_from_str(["-o", "--output"])
+                .unwrap_or(None)
+                .unwrap_or_else(|| PathBuf::from("-")),
+        },
+        Some("apply") => Action::Apply {
+            plan: PathBuf::new(),
+        },
+        Some("outdated") => Action::Outdated,
+        Some("history") => Action::History {
+            filter: Filter {
+                manager: pargs.opt_value_from_str("--manager").unwrap_or(None),
+                failed: pargs.contains("--failed"),
+                limit: pargs.opt_value_from_str("--limit").unwrap_or_else(|_| {
+                    eprintln!("{} --limit expects a number", "ERR:".red());
+                    ExitCode::ConfigError.exit();
+                }),
+            },
+            show: None,
+        },
+        Some(other) => {
+            eprintln!("{} Unknown command: {other}", "ERR:".red());
+            ExitCode::ConfigError.exit();
+        }
+    };
+
+    let mut builder = Config::builder();
+    builder.config_files(true);
+    if pargs.contains(["-d", "--dry"]) {
+        builder.dry_run(true);
+    }
+    let excl_values: Vec<String> = pargs
+        .values_from_str(["-e", "--excl"])
+        .unwrap_or_else(|_| Vec::new());
+    for excl in excl_values {
+        match Config::parse_exclusion(&excl) {
+            Ok((pm, Some(pkg))) => {
+                builder.exclude_package(&pm, &pkg);
+            }
+            Ok((pm, None)) => {
+                builder.exclude_manager(&pm);
+            }
+            Err(e) => eprintln!("{} {e}", "ERR:".red()),
+        }
+    }
+    if pargs.contains(["-a", "--auto"]) {
+        builder.auto(true);
+    }
+    if pargs.contains("--wait") {
+        builder.wait(true);
+    }
+    if pargs.contains("--all-users") {
+        builder.all_users(true);
+    }
+    if pargs.contains("--no-history") {
+        builder.history(false);
+    }
+    if pargs.contains("--capture-stderr") {
+        builder.capture_stderr(true);
+    }
+    if pargs.contains("--skip-up-to-date") {
+        builder.skip_up_to_date(true);
+    }
+    let noconfirm = pargs.contains(["-n", "--noconfirm"]);
+    if noconfirm {
+        builder.noconfirm(true);
+    }
+    if pargs.contains(["-S", "--self-update"]) {
+        if !self_up::perm::is_elevated() {
+            eprintln!("Program must be run as root to update.");
+            ExitCode::Failure.exit();
+        }
+        println!("Updating qud...");
+        match self_up::self_update(noconfirm) {
+            Ok(true) => {
+                println!("qud updated successfully, exiting.");
+                ExitCode::Success.exit();
+            }
+            Ok(false) => ExitCode::Aborted.exit(),
+            Err(e) => {
+                eprintln!("qud failed to update: {e}, exiting.");
+                ExitCode::Failure.exit();
+            }
+        }
+    }
+    if pargs.contains(["-v", "--verbose"]) {
+        builder.verbose(true);
+    }
+    builder.list(pargs.contains(["-l", "--list"]));
+    let only_values: Vec<String> = pargs
+        .values_from_str(["-o", "--only"])
+        .unwrap_or_else(|_| Vec::new());
+    for pm in only_values {
+        builder.only(&pm);
+    }
+
+    let spec_values: Vec<String> = pargs
+        .values_from_str(["-s", "--spec"])
+        .unwrap_or_else(|_| Vec::new());
+    for spec in spec_values {
+        match Config::parse_spec(&spec) {
+            Ok((pm, path)) => {
+                builder.spec(&pm, path);
+            }
+            Err(e) => eprintln!("{} {e}", "ERR:".red()),
+        }
+    }
+
+    let ext_values: Vec<String> = pargs
+        .values_from_str(["-E", "--ext"])
+        .unwrap_or_else(|_| Vec::new());
+    for ext in ext_values {
+        match Config::parse_ext(&ext) {
+            Ok((pm, flags)) => {
+                builder.ext(&pm, flags);
+            }
+            Err(e) => eprintln!("{} {e}", "ERR:".red()),
+        }
+    }
+
+    if pargs.contains("--fail-fast") {
+        builder.on_failure(FailurePolicy::FailFast);
+    }
+    if pargs.contains("--keep-going") {
+        builder.on_failure(FailurePolicy::KeepGoing);
+    }
+    let on_failure_values: Vec<String> = pargs
+        .values_from_str("--on-failure")
+        .unwrap_or_else(|_| Vec::new());
+    for on_failure in on_failure_values {
+        match Config::parse_on_failure(&on_failure) {
+            Ok((Some(pm), policy)) => {
+                builder.manager_on_failure(&pm, policy);
+            }
+            Ok((None, policy)) => {
+                builder.on_failure(policy);
+            }
+            Err(e) => eprintln!("{} {e}", "ERR:".red()),
+        }
+    }
+
+    let mut timeouts = Timeouts::default();
+    let mut manager_timeouts: HashMap<String, Timeouts> = HashMap::new();
+    let limits: [(&str, TimeoutField); 3] = [
+        ("--timeout", |t| &mut t.step),
+        ("--manager-timeout", |t| &mut t.manager),
+        ("--stall-timeout", |t| &mut t.stall),
+    ];
+    for (flag, limit) in limits {
+        let values: Vec<String> = pargs.values_from_str(flag).unwrap_or_else(|_| Vec::new());
+        for value in values {
+            match Config::parse_timeout(&value) {
+                Ok((Some(pm), duration)) => {
+                    *limit(manager_timeouts.entry(pm).or_default()) = Some(duration);
+                }
+                Ok((None, duration)) => *limit(&mut timeouts) = Some(duration),
+                Err(e) => {
+                    eprintln!("{} {e}", "ERR:".red());
+                    ExitCode::ConfigError.exit();
+                }
+            }
+        }
+    }
+    builder.timeouts(timeouts);
+    for (pm, timeouts) in &manager_timeouts {
+        builder.manager_timeouts(pm, *timeouts);
+    }
+
+    let mut hooks = Hooks::default();
+    let mut manager_hooks: HashMap<String, Hooks> = HashMap::new();
+    let lists: [(&str, HookField); 2] = [
+        ("--pre-hook", |h| &mut h.pre),
+        ("--post-hook", |h| &mut h.post),
+    ];
+    for (flag, list) in lists {
+        let values: Vec<String> = pargs.values_from_str(flag).unwrap_or_else(|_| Vec::new());
+        for value in values {
+            match Config::parse_hook(&value) {
+                Ok((Some(pm), command)) => list(manager_hooks.entry(pm).or_default()).push(command),
+                Ok((None, command)) => list(&mut hooks).push(command),
+                Err(e) => {
+                    eprintln!("{} {e}", "ERR:".red());
+                    ExitCode::ConfigError.exit();
+                }
+            }
+        }
+    }
+    builder.hooks(hooks);
+    for (pm, hooks) in manager_hooks {
+        builder.manager_hooks(&pm, hooks);
+    }
+
+    let retry_values: Vec<String> = pargs
+        .values_from_str("--retries")
+        .unwrap_or_else(|_| Vec::new());
+    for retries in retry_values {
+        match Config::parse_retries(&retries) {
+            Ok((Some(pm), n)) => {
+                builder.manager_retries(&pm, n);
+            }
+            Ok((None, n)) => {
+                builder.retries(n);
+            }
+            Err(e) => {
+                eprintln!("{} {e}", "ERR:".red());
+                ExitCode::ConfigError.exit();
+            }
+        }
+    }
+    let snapshot: Option<String> = pargs.opt_value_from_str("--snapshot").unwrap_or(None);
+    if let Some(snapshot) = snapshot {
+        match Config::parse_snapshot(&snapshot) {
+            Ok(snapshot) => {
+                builder.snapshot(snapshot);
+            }
+            Err(e) => {
+                eprintln!("{} {e}", "ERR:".red());
+                ExitCode::ConfigError.exit();
+            }
+        }
+    }
+    let retry_delay: Option<String> = pargs.opt_value_from_str("--retry-delay").unwrap_or(None);
+    if let Some(delay) = retry_delay {
+        match Config::parse_duration(&delay) {
+            Ok(delay) => {
+                builder.retry_delay(delay);
+            }
+            Err(e) => {
+                eprintln!("{} {e}", "ERR:".red());
+                ExitCode::ConfigError.exit();
+            }
+        }
+    }
+    let lock_timeout: Option<String> = pargs.opt_value_from_str("--lock-timeout").unwrap_or(None);
+    if let Some(timeout) = lock_timeout {
+        match Config::parse_duration(&timeout) {
+            Ok(timeout) => {
+                builder.lock_timeout(timeout);
+            }
+            Err(e) => {
+                eprintln!("{} {e}", "ERR:".red());
+                ExitCode::ConfigError.exit();
+            }
+        }
+    }
+
+    let jobs: Option<String> = pargs.opt_value_from_str(["-j", "--jobs"]).unwrap_or(None);
+    if let Some(jobs) = jobs {
+        match Config::parse_jobs(&jobs) {
+            Ok(jobs) => {
+                builder.jobs(jobs);
+            }
+            Err(e) => {
+                eprintln!("{} {e}", "ERR:".red());
+                ExitCode::ConfigError.exit();
+            }
+        }
+    }
+
+    let escalation: Option<String> = pargs.opt_value_from_str("--escalation").unwrap_or(None);
+    if let Some(escalation) = escalation {
+        match escalation.parse() {
+            Ok(escalation) => {
+                builder.escalation(escalation);
+            }
+            Err(e) => {
+                eprintln!("{} {e}", "ERR:".red());
+                ExitCode::ConfigError.exit();
+            }
+        }
+    }
+
+    let user: Option<String> = pargs.opt_value_from_str("--user").unwrap_or(None);
+    if let Some(user) = user {
+        builder.user(user);
+    }
+    let users: Vec<String> = pargs
+        .values_from_str("--users")
+        .unwrap_or_else(|_| Vec::new());
+    for user in users {
+        builder.allow_user(&user);
+    }
+    let uids: Option<String> = pargs.opt_value_from_str("--uid-range").unwrap_or(None);
+    if let Some(uids) = uids {
+        match Config::parse_uid_range(&uids) {
+            Ok(uids) => {
+                builder.uid_range(uids);
+            }
+            Err(e) => {
+                eprintln!("{} {e}", "ERR:".red());
+                ExitCode::ConfigError.exit();
+            }
+        }
+    }
+
+    let format: Option<String> = pargs.opt_value_from_str("--format").unwrap_or(None);
+    if let Some(format) = format {
+        match format.parse() {
+            Ok(format) => {
+                builder.format(format);
+            }
+            Err(e) => {
+                eprintln!("{} {e}", "ERR:".red());
+                ExitCode::ConfigError.exit();
+            }
+        }
+    }
+
+    if pargs.clone().contains(["-O", "--ord"]) {
+        let ord_value: Option<String> = pargs.opt_value_from_str(["-O", "--ord"]).unwrap_or(None);
+        builder.order(Config::parse_ord(ord_value.as_deref().unwrap_or_default()));
+    }
+
+    // Print error and exit for unrecognized arguments.
+    let mut remaining = pargs.finish();
+    if let Action::Apply { plan } = &mut action {
+        if remaining.len() != 1 {
+            eprintln!("{} Usage: qud apply <plan.json>", "ERR:".red());
+            ExitCode::ConfigError.exit();
+        }
+        *plan = PathBuf::from(remaining.remove(0));
+    }
+    if let Action::History { show, .. } = &mut action {
+        if remaining.first().is_some_and(|arg| arg == "show") {
+            if remaining.len() != 2 {
+                eprintln!("{} Usage: qud history show <id>", "ERR:".red());
+                ExitCode::ConfigError.exit();
+            }
+            *show = remaining.pop().and_then(|id| id.into_string().ok());
+            remaining.clear();
+        }
+    }
+    if !remaining.is_empty() {
+        eprintln!("{} Unrecognized arguments: {:?}", "ERR:".red(), remaining);
+        ExitCode::ConfigError.exit();
+    }
+
+    match builder.build() {
+        Ok(config) => (action, config),
+        Err(e) => {
+            eprintln!("{} {e}", "ERR:".red());
+            ExitCode::ConfigError.exit();
+        }
+    }
+}
+
+fn print_help() {
+    println!(
+        r#"qud v1.5.1
+
+Usage:
+  qud [options]
+  qud plan [-o <plan.json>] [options]
+  qud apply [--dry] [--auto | --noconfirm] <plan.json>
+  qud outdated [options]
+  qud history [--manager <pm>] [--failed] [--limit <n>]
+  qud history show <id>
+
+Commands:
+  plan                Compute the update commands and save them as JSON (to stdout without -o) instead of running them.
+  apply               Run exactly the commands of a saved plan. Refuses if any program it runs changed since.
+  outdated            List pending updates of the selected package managers without applying them.
+  history             List recorded runs, most recent first: only those updating a package manager with --manager,
+                      only failed ones with --failed, at most n with --limit. show <id> prints every step of a run.
+
+Options:
+  --dry, -d           Dry run (print commands instead of executing).
+  --excl, -e <s>      Exclude a package from a manager (format: pm::pkg) or a package manager entirely (format: pm). May be repeated.
+  --auto, -a          Auto mode (use non-interactive flags where available).
+  --verbose, -v       Enable verbose logging.
+  --list, -l          List detected package managers without updating.
+  --only, -o <pm>     Update only the specified package manager (may be repeated).
+  --spec, -s <s>      Override the detected executable for a package manager (format: pm::/path/to/executable). May be repeated.
+  --ext, -E <s>       Pass extra flags to a package manager (format: pm::"<flags>").
+  --ord, -O [s]       Specify the update order. If provided a value (pm1,pm2,...), that order is used for those found; if no value is provided, you'll be prompted to sort.
+  --skip-up-to-date   After refreshing, ask each package manager for pending updates and skip the upgrade if there
+                      are none. Package managers that cannot list pending updates are always upgraded.
+  --snapshot <s>      Take a filesystem snapshot before updating system packages (pacman, apt, dnf, zypper, ...):
+                      snapper[:config], timeshift, btrfs[:subvolume] or lvm:vg/lv. none turns snapshots off.
+  --lock-timeout <s>  Wait this long (default 5m) for a package manager's lock held by another program, e.g.
+                      unattended-upgrades holding dpkg's lock, before failing that package manager.
+  --wait              If another qud run is updating, wait for it to finish instead of exiting.
+  --no-history        Don't record this run in the history (~/.local/state/qud/history).
+  --capture-stderr    Keep the last lines each step writes to stderr for the history and JSON results. Steps then
+                      write to a pipe, so some show no progress bars or colors.
+  --fail-fast         Stop the whole run when a step fails.
+  --keep-going        When a step fails, skip the rest of that package manager and continue with the next (default).
+  --on-failure <s>    Set the failure policy, globally (fail-fast, keep-going or run-all) or for one package
+                      manager (format: pm::policy). run-all runs the remaining steps even if an earlier one failed.
+                      May be repeated.
+  --timeout <s>       Stop a step that runs longer than this (e.g. 30m, 1h30m; plain numbers are seconds),
+                      globally or for one package manager (format: pm::duration). May be repeated.
+  --manager-timeout <s>
+                      Like --timeout, but for all steps of a package manager together.
+  --stall-timeout <s> Like --timeout, but stop a step that prints nothing for this long.
+                      Steps are asked to stop with SIGTERM and killed 10 seconds later.
+  --pre-hook <s>      Run a shell command before the whole run, or before the steps of one package manager
+                      (format: pm::command). If it fails, what it precedes does not run. May be repeated.
+  --post-hook <s>     Like --pre-hook, but run afterwards, also after failures. Hooks receive QUD_HOOK, QUD_MANAGER,
+                      QUD_STATUS, QUD_STEP and QUD_EXIT_CODE in their environment.
+  --retries <s>       Retry failed steps that are safe to repeat (refreshes and some upgrades) up to n times,
+                      globally or for one package manager (format: pm::n). May be repeated.
+  --retry-delay <s>   Wait this long before the first retry (default 5s). The wait doubles with every retry.
+  --jobs, -j <n>      Update up to n package managers at the same time. Package managers that may prompt (all of
+                      them without --auto, and those needing sudo) still run one at a time, first.
+  --escalation <s>    How steps needing root privileges get them: auto (default: sudo, doas, run0 or pkexec,
+                      whichever is installed), sudo, doas, run0, pkexec, runas or none. Nothing is used when qud
+                      already runs as root.
+  --user <name>       When qud runs as root, run user-level package managers (rustup, brew, gem, ...) and project
+                      package managers as this user (default: the user who ran sudo or doas).
+  --all-users         When qud runs as root, also update the user-level package managers (rustup, pipx, brew,
+                      flatpak --user, ...) of every login user, as that user.
+  --users <name>      Only update this user with --all-users (may be repeated).
+  --uid-range <s>     The UIDs of login users for --all-users (default: UID_MIN-UID_MAX of /etc/login.defs).
+  --format <f>        Output format: human (default), json (one document at the end) or ndjson (one event per line).
+                      In the JSON formats, all other output goes to stderr.
+  --help, -h          Show this help screen.
+  --version, -V       Show version information.
+  --self-update, -S   Update qud.
+  --noconfirm, -n     Don't confirm when updating. Does not pass non-interactive flags to package managers.
+
+Exit status:
+  0  All updates succeeded (or there was nothing to do).
+  1  Invalid options, configuration files or environment variables.
+  2  Some package managers failed, others succeeded.
+  3  Every attempted update failed, or qud could not run (e.g. a saved plan was refused).
+  4  Cancelled by the user.
+  100  qud outdated found pending updates.
+
+Configuration:
+  Options are also read from /etc/qud/config.toml, /etc/qud/conf.d/*.toml, ~/.config/qud/config.toml,
+  ~/.config/qud/conf.d/*.toml and QUD_* environment variables (e.g. QUD_EXCL=apt::vim,snap), in that
+  order. Command-line options take precedence over all of them.
+"#
+    );
+}
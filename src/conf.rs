@@ -1,12 +1,20 @@
+use crate::conf_file::{self, ConfigError};
+use crate::escalation::Escalation;
 use crate::helpers::format_list;
-use crate::self_up;
+use crate::managers::{Ctx, CustomManager, Exclusion, PackageManager, Registry};
+use crate::output::Format;
+use crate::say;
+use crate::snapshot::Snapshot;
 use colored::Colorize;
-use pico_args::Arguments;
+use serde::Serialize;
 use std::collections::HashMap;
+use std::ops::RangeInclusive;
 use std::path::PathBuf;
+use std::time::Duration;
 
 /// Determines how to order package manager updates.
-#[derive(Debug)]
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "snake_case")]
 pub enum OrdMode {
     /// The user did not provide an explicit order – ask interactively.
     Interactive,
@@ -14,230 +22,625 @@ pub enum OrdMode {
     Specified(Vec<String>),
 }
 
-/// Holds runtime configuration derived from command-line arguments.
+/// What to do when a step fails.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum FailurePolicy {
+    /// Stop the whole run.
+    FailFast,
+    /// Skip the remaining steps of the failed package manager and continue with the next one.
+    #[default]
+    KeepGoing,
+    /// Run the remaining steps anyway, e.g. upgrade even though refreshing failed.
+    RunAll,
+}
+
+impl std::str::FromStr for FailurePolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<FailurePolicy, String> {
+        match s {
+            "fail-fast" => Ok(FailurePolicy::FailFast),
+            "keep-going" => Ok(FailurePolicy::KeepGoing),
+            "run-all" => Ok(FailurePolicy::RunAll),
+            _ => Err(format!(
+                "Invalid failure policy: {s} (expected fail-fast, keep-going or run-all)"
+            )),
+        }
+    }
+}
+
+/// Limits on how long steps may run. Unset limits do not apply.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize)]
+pub struct Timeouts {
+    /// The longest a single step may run.
+    pub step: Option<Duration>,
+    /// The longest all steps of a package manager may run together.
+    pub manager: Option<Duration>,
+    /// The longest a step may run without printing anything.
+    pub stall: Option<Duration>,
+}
+
+impl Timeouts {
+    /// Fills the limits unset in `self` from `other`.
+    #[must_use]
+    pub fn or(self, other: Timeouts) -> Timeouts {
+        Timeouts {
+            step: self.step.or(other.step),
+            manager: self.manager.or(other.manager),
+            stall: self.stall.or(other.stall),
+        }
+    }
+}
+
+/// Selects one of the [`Timeouts`].
+pub type TimeoutField = fn(&mut Timeouts) -> &mut Option<Duration>;
+
+/// Shell commands run before and after updates.
+#[derive(Debug, Clone, PartialEq, Eq, Default, Serialize)]
+pub struct Hooks {
+    /// Run before; if one fails, what it precedes does not run.
+    pub pre: Vec<String>,
+    /// Run afterwards, also after failures.
+    pub post: Vec<String>,
+}
+
+impl Hooks {
+    /// Fills the lists empty in `self` from `other`.
+    #[must_use]
+    pub fn or(self, other: Hooks) -> Hooks {
+        let pick = |own: Vec<String>, other: Vec<String>| if own.is_empty() { other } else { own };
+        Hooks {
+            pre: pick(self.pre, other.pre),
+            post: pick(self.post, other.post),
+        }
+    }
+}
+
+/// Selects one of the lists of [`Hooks`].
+pub type HookField = fn(&mut Hooks) -> &mut Vec<String>;
+
+/// One source of configuration: a config file, the environment or the command line.
+/// Unset values leave those of lower layers in place.
+#[derive(Default)]
+pub(crate) struct Layer {
+    pub(crate) exclusions: HashMap<String, Vec<String>>,
+    pub(crate) only: Option<Vec<String>>,
+    pub(crate) specs: HashMap<String, PathBuf>,
+    pub(crate) exts: HashMap<String, Vec<String>>,
+    pub(crate) ord: Option<OrdMode>,
+    pub(crate) auto: Option<bool>,
+    pub(crate) noconfirm: Option<bool>,
+    pub(crate) verbose: Option<bool>,
+    pub(crate) dry_run: Option<bool>,
+    pub(crate) skip_up_to_date: Option<bool>,
+    pub(crate) history: Option<bool>,
+    pub(crate) capture_stderr: Option<bool>,
+    pub(crate) wait: Option<bool>,
+    pub(crate) escalation: Option<Escalation>,
+    pub(crate) user: Option<String>,
+    pub(crate) all_users: Option<bool>,
+    pub(crate) users: Option<Vec<String>>,
+    pub(crate) uid_range: Option<RangeInclusive<u32>>,
+    /// `Some(None)` turns snapshots off.
+    pub(crate) snapshot: Option<Option<Snapshot>>,
+    pub(crate) format: Option<Format>,
+    pub(crate) on_failure: Option<FailurePolicy>,
+    pub(crate) jobs: Option<usize>,
+    pub(crate) timeouts: Timeouts,
+    pub(crate) retries: Option<u32>,
+    pub(crate) manager_retries: HashMap<String, u32>,
+    pub(crate) retry_delay: Option<Duration>,
+    pub(crate) lock_timeout: Option<Duration>,
+    pub(crate) manager_timeouts: HashMap<String, Timeouts>,
+    pub(crate) hooks: Hooks,
+    pub(crate) manager_hooks: HashMap<String, Hooks>,
+    pub(crate) failure_policies: HashMap<String, FailurePolicy>,
+    pub(crate) managers: Vec<CustomManager>,
+}
+
+/// Holds runtime configuration derived from config files, the environment and command-line arguments.
 #[allow(clippy::struct_excessive_bools)]
+#[derive(Serialize)]
 pub struct Config {
-    pub(crate) exclusions: HashMap<String, Vec<String>>,
+    /// Excluded packages per package manager. An empty list excludes the package manager entirely.
+    pub exclusions: HashMap<String, Vec<String>>,
     /// If provided, update only these package managers (by name).
-    pub(crate) only: Option<Vec<String>>,
+    pub only: Option<Vec<String>>,
     /// Specifications to override the detected executable for a package manager.
     /// Format: "pm::/path/to/executable"
-    pub(crate) specs: HashMap<String, PathBuf>,
+    pub specs: HashMap<String, PathBuf>,
     /// Auto mode uses non-interactive flags (if available) for each package manager.
-    pub(crate) auto: bool,
-    pub(crate) noconfirm: bool,
+    pub auto: bool,
+    pub noconfirm: bool,
     /// Verbose mode prints extra logging information.
-    pub(crate) verbose: bool,
+    pub verbose: bool,
     /// List mode prints found package managers without performing any updates.
-    pub(crate) list: bool,
-    pub(crate) dry_run: bool,
-    /// Extra flags to pass to package managers. Format: "pm::<flags>"
-    pub(crate) exts: HashMap<String, Vec<String>>,
+    pub list: bool,
+    pub dry_run: bool,
+    /// Check for pending updates after refreshing and skip the upgrades of package managers
+    /// with none.
+    pub skip_up_to_date: bool,
+    /// How to take a filesystem snapshot before system packages are updated.
+    pub snapshot: Option<Snapshot>,
+    /// Record runs in the history.
+    pub history: bool,
+    /// Keep the tail of what each step writes to stderr, for the history and JSON results. Steps
+    /// then write to a pipe instead of the terminal, so some show no progress bars or colors.
+    pub capture_stderr: bool,
+    /// Wait for another qud run to finish instead of refusing to start.
+    pub wait: bool,
+    /// How privileged steps gain their privileges.
+    pub escalation: Escalation,
+    /// The user the steps of user-level and project package managers run as when qud runs as root.
+    /// Defaults to the user who started qud through sudo or doas.
+    pub user: Option<String>,
+    /// When qud runs as root, also update the user-level package managers of every login user, as
+    /// that user.
+    pub all_users: bool,
+    /// If provided, only these users are updated with [`Config::all_users`], whatever their UID.
+    pub users: Option<Vec<String>>,
+    /// The UIDs of login users. Defaults to `UID_MIN` to `UID_MAX` of `/etc/login.defs`.
+    pub uid_range: Option<RangeInclusive<u32>>,
+    /// How progress and results are reported.
+    pub format: Format,
+    /// What to do when a step fails, unless overridden per package manager.
+    pub on_failure: FailurePolicy,
+    /// Failure policies of individual package managers.
+    pub failure_policies: HashMap<String, FailurePolicy>,
+    /// How many non-interactive package managers may update at the same time.
+    pub jobs: usize,
+    /// Time limits, unless overridden per package manager.
+    pub timeouts: Timeouts,
+    /// Time limits of individual package managers.
+    pub manager_timeouts: HashMap<String, Timeouts>,
+    /// How often a failed idempotent step is retried, unless overridden per package manager.
+    pub retries: u32,
+    /// Retries of individual package managers.
+    pub manager_retries: HashMap<String, u32>,
+    /// The wait before the first retry. It doubles with every further retry.
+    pub retry_delay: Duration,
+    /// How long to wait for a package manager's own lock, held by another program, before giving
+    /// up on that package manager.
+    pub lock_timeout: Duration,
+    /// Commands run before and after the whole run.
+    pub hooks: Hooks,
+    /// Commands run before and after the steps of individual package managers.
+    pub manager_hooks: HashMap<String, Hooks>,
+    /// Extra flags to pass to package managers. Format: `pm::<flags>`
+    pub exts: HashMap<String, Vec<String>>,
     /// Optional ordering of updates.
-    pub(crate) ord: Option<OrdMode>,
+    pub ord: Option<OrdMode>,
+    /// Package managers defined in configuration files.
+    pub managers: Vec<CustomManager>,
     //install_mode: bool,
 }
 
 impl Config {
-    #[allow(clippy::too_many_lines)]
-    pub fn parse_args() -> Config {
-        let mut pargs = Arguments::from_env();
-
-        // Help, version, and self updating.
-        if pargs.contains(["-h", "--help"]) {
-            Self::print_help();
-            std::process::exit(0);
-        }
-        if pargs.contains(["-V", "--version"]) {
-            println!("qud v1.5.1");
-            std::process::exit(0);
-        }
-
-        let dry_run = pargs.contains(["-d", "--dry"]);
-        let excl_values: Vec<String> = pargs
-            .values_from_str(["-e", "--excl"])
-            .unwrap_or_else(|_| Vec::new());
-        let mut exclusions = HashMap::new();
-        for excl in excl_values {
-            Self::add_exclusion(&mut exclusions, &excl);
-        }
-        let auto = pargs.contains(["-a", "--auto"]);
-        let noconfirm = pargs.contains(["-n", "--noconfirm"]);
-        if pargs.contains(["-S", "--self-update"]) {
-            if !self_up::perm::is_elevated() {
-                eprintln!("Program must be run as root to update.");
-                std::process::exit(2);
+    /// Starts building a configuration without touching the command line.
+    #[must_use]
+    pub fn builder() -> ConfigBuilder {
+        ConfigBuilder::default()
+    }
+
+    /// Creates a registry of the built-in package managers plus those defined in configuration.
+    #[must_use]
+    pub fn registry(&self) -> Registry {
+        let mut registry = Registry::builtin();
+        for manager in &self.managers {
+            registry.register(Box::new(manager.clone()));
+        }
+        registry
+    }
+
+    /// Applies a configuration layer on top of the current values.
+    /// Exclusions, specs, extra flags and custom managers are replaced per package manager.
+    fn apply(&mut self, layer: Layer) {
+        self.exclusions.extend(layer.exclusions);
+        self.specs.extend(layer.specs);
+        self.exts.extend(layer.exts);
+        if layer.only.is_some() {
+            self.only = layer.only;
+        }
+        if layer.ord.is_some() {
+            self.ord = layer.ord;
+        }
+        self.auto = layer.auto.unwrap_or(self.auto);
+        self.noconfirm = layer.noconfirm.unwrap_or(self.noconfirm);
+        self.verbose = layer.verbose.unwrap_or(self.verbose);
+        self.dry_run = layer.dry_run.unwrap_or(self.dry_run);
+        self.skip_up_to_date = layer.skip_up_to_date.unwrap_or(self.skip_up_to_date);
+        self.history = layer.history.unwrap_or(self.history);
+        self.capture_stderr = layer.capture_stderr.unwrap_or(self.capture_stderr);
+        self.wait = layer.wait.unwrap_or(self.wait);
+        self.escalation = layer.escalation.unwrap_or(self.escalation);
+        if layer.user.is_some() {
+            self.user = layer.user;
+        }
+        self.all_users = layer.all_users.unwrap_or(self.all_users);
+        if layer.users.is_some() {
+            self.users = layer.users;
+        }
+        if layer.uid_range.is_some() {
+            self.uid_range = layer.uid_range;
+        }
+        if let Some(snapshot) = layer.snapshot {
+            self.snapshot = snapshot;
+        }
+        self.format = layer.format.unwrap_or(self.format);
+        self.on_failure = layer.on_failure.unwrap_or(self.on_failure);
+        self.failure_policies.extend(layer.failure_policies);
+        self.jobs = layer.jobs.unwrap_or(self.jobs);
+        self.timeouts = layer.timeouts.or(self.timeouts);
+        self.retries = layer.retries.unwrap_or(self.retries);
+        self.manager_retries.extend(layer.manager_retries);
+        self.retry_delay = layer.retry_delay.unwrap_or(self.retry_delay);
+        self.lock_timeout = layer.lock_timeout.unwrap_or(self.lock_timeout);
+        for (pm, timeouts) in layer.manager_timeouts {
+            let existing = self.manager_timeouts.entry(pm).or_default();
+            *existing = timeouts.or(*existing);
+        }
+        self.hooks = layer.hooks.or(std::mem::take(&mut self.hooks));
+        for (pm, hooks) in layer.manager_hooks {
+            let existing = self.manager_hooks.entry(pm).or_default();
+            *existing = hooks.or(std::mem::take(existing));
+        }
+        for manager in layer.managers {
+            if let Some(existing) = self.managers.iter_mut().find(|m| m.name == manager.name) {
+                *existing = manager;
+            } else {
+                self.managers.push(manager);
             }
-            println!("Updating qud...");
-            match self_up::self_update(noconfirm) {
-                Ok(()) => println!("qud updated successfully, exiting."),
-                Err(e) => eprintln!("qud failed to update: {e}, exiting."),
+        }
+    }
+
+    /// Parses an exclusion of the form "pm::pkg" (a single package) or "pm" (the whole package manager).
+    ///
+    /// # Errors
+    /// Returns a message if the exclusion contains more than one "::".
+    pub fn parse_exclusion(excl: &str) -> Result<(String, Option<String>), String> {
+        if excl.contains("::") {
+            let parts: Vec<&str> = excl.split("::").collect();
+            if parts.len() == 2 {
+                Ok((parts[0].to_string(), Some(parts[1].to_string())))
+            } else {
+                Err(format!("Invalid exclusion format: {excl}"))
             }
-            std::process::exit(-1);
-        }
-        let verbose = pargs.contains(["-v", "--verbose"]);
-        let list = pargs.contains(["-l", "--list"]);
-        let only_values: Vec<String> = pargs
-            .values_from_str(["-o", "--only"])
-            .unwrap_or_else(|_| Vec::new());
-        let only = if only_values.is_empty() {
-            None
         } else {
-            Some(only_values)
-        };
+            Ok((excl.to_string(), None))
+        }
+    }
 
-        let spec_values: Vec<String> = pargs
-            .values_from_str(["-s", "--spec"])
-            .unwrap_or_else(|_| Vec::new());
-        let mut specs = HashMap::new();
-        for spec in spec_values {
-            if spec.contains("::") {
-                let sects: Vec<&str> = spec.split("::").collect();
-                if sects.len() == 2 {
-                    let pm = sects[0].to_string();
-                    let path = PathBuf::from(sects[1]);
-                    specs.insert(pm, path);
-                } else {
-                    eprintln!("{} Invalid spec format: {spec}", "ERR:".red());
-                }
-            } else {
-                eprintln!("{} Invalid spec format: {spec}", "ERR:".red());
+    /// Inserts an exclusion rule into the map.
+    pub(crate) fn add_exclusion(
+        map: &mut HashMap<String, Vec<String>>,
+        excl: &str,
+    ) -> Result<(), String> {
+        match Self::parse_exclusion(excl)? {
+            // If the package manager was already fully excluded (empty vec), keep it that way.
+            (pm, Some(pkg)) => map.entry(pm).or_default().push(pkg),
+            // Full exclusion: mark the package manager as entirely excluded by storing an empty Vec.
+            (pm, None) => {
+                map.insert(pm, Vec::new());
             }
         }
+        Ok(())
+    }
 
-        let ext_values: Vec<String> = pargs
-            .values_from_str(["-E", "--ext"])
-            .unwrap_or_else(|_| Vec::new());
-        let mut exts: HashMap<String, Vec<String>> = HashMap::new();
-        for ext in ext_values {
-            if let Some((pm, flags)) = ext.split_once("::") {
-                let flags_vec: Vec<String> = flags
-                    .split_whitespace()
-                    .map(std::string::ToString::to_string)
-                    .collect();
-                exts.entry(pm.to_string()).or_default().extend(flags_vec);
-            } else {
-                eprintln!("{} Invalid ext format: {ext}", "ERR:".red());
+    /// Parses an executable override of the form "pm::/path/to/executable".
+    ///
+    /// # Errors
+    /// Returns a message if the spec is not of that form.
+    pub fn parse_spec(spec: &str) -> Result<(String, PathBuf), String> {
+        let sects: Vec<&str> = spec.split("::").collect();
+        if sects.len() == 2 {
+            Ok((sects[0].to_string(), PathBuf::from(sects[1])))
+        } else {
+            Err(format!("Invalid spec format: {spec}"))
+        }
+    }
+
+    /// Parses extra flags of the form `pm::<flags>`.
+    ///
+    /// # Errors
+    /// Returns a message if the package manager is missing.
+    pub fn parse_ext(ext: &str) -> Result<(String, Vec<String>), String> {
+        let (pm, flags) = ext
+            .split_once("::")
+            .ok_or_else(|| format!("Invalid ext format: {ext}"))?;
+        let flags_vec: Vec<String> = flags
+            .split_whitespace()
+            .map(std::string::ToString::to_string)
+            .collect();
+        Ok((pm.to_string(), flags_vec))
+    }
+
+    /// Parses a failure policy of the form "policy" (all package managers) or "pm::policy".
+    ///
+    /// # Errors
+    /// Returns a message if the policy is unknown.
+    pub fn parse_on_failure(val: &str) -> Result<(Option<String>, FailurePolicy), String> {
+        match val.split_once("::") {
+            Some((pm, policy)) => Ok((Some(pm.to_string()), policy.parse()?)),
+            None => Ok((None, val.parse()?)),
+        }
+    }
+
+    /// Inserts a failure policy of the form accepted by [`Config::parse_on_failure`] into a layer.
+    pub(crate) fn add_on_failure(layer: &mut Layer, val: &str) -> Result<(), String> {
+        match Self::parse_on_failure(val)? {
+            (Some(pm), policy) => {
+                layer.failure_policies.insert(pm, policy);
             }
+            (None, policy) => layer.on_failure = Some(policy),
         }
+        Ok(())
+    }
 
-        let ord: Option<OrdMode> = if pargs.clone().contains(["-O", "--ord"]) {
-            let ord_value: Option<String> =
-                pargs.opt_value_from_str(["-O", "--ord"]).unwrap_or(None);
+    /// The failure policy of `pm`.
+    #[must_use]
+    pub fn failure_policy(&self, pm: &str) -> FailurePolicy {
+        self.failure_policies
+            .get(pm)
+            .copied()
+            .unwrap_or(self.on_failure)
+    }
 
-            match ord_value {
-                Some(val) if !val.is_empty() => {
-                    let order: Vec<String> = val
-                        .split(',')
-                        .map(|s| s.trim().to_string())
-                        .filter(|s| !s.is_empty())
-                        .collect();
-                    Some(OrdMode::Specified(order))
-                }
-                _ => Some(OrdMode::Interactive),
+    /// Parses a duration such as "90", "90s", "5m", "2h" or "1h30m". Plain numbers are seconds;
+    /// once a unit is used, every number needs one, so "1h30" is rejected rather than read as 1h30s.
+    ///
+    /// # Errors
+    /// Returns a message if the value is not a duration, or too long to represent.
+    pub fn parse_duration(val: &str) -> Result<Duration, String> {
+        let invalid = || format!("Invalid duration: {val}");
+        let mut total: u64 = 0;
+        let mut number = String::new();
+        let mut with_units = false;
+        for ch in val.trim().chars() {
+            if ch.is_ascii_digit() {
+                number.push(ch);
+                continue;
             }
-        } else {
-            None
+            let unit = match ch {
+                's' => 1,
+                'm' => 60,
+                'h' => 3600,
+                _ => return Err(invalid()),
+            };
+            total = number
+                .parse::<u64>()
+                .ok()
+                .and_then(|n| n.checked_mul(unit))
+                .and_then(|secs| total.checked_add(secs))
+                .ok_or_else(invalid)?;
+            number.clear();
+            with_units = true;
+        }
+        match (with_units, number.is_empty()) {
+            (true, true) => Ok(Duration::from_secs(total)),
+            (false, false) => number
+                .parse()
+                .map(Duration::from_secs)
+                .map_err(|_| invalid()),
+            // A number after the last unit, or no number at all.
+            _ => Err(invalid()),
+        }
+    }
+
+    /// Parses a time limit of the form "duration" (all package managers) or "pm::duration".
+    ///
+    /// # Errors
+    /// Returns a message if the duration is invalid.
+    pub fn parse_timeout(val: &str) -> Result<(Option<String>, Duration), String> {
+        match val.split_once("::") {
+            Some((pm, duration)) => Ok((Some(pm.to_string()), Self::parse_duration(duration)?)),
+            None => Ok((None, Self::parse_duration(val)?)),
+        }
+    }
+
+    /// Inserts a time limit of the form accepted by [`Config::parse_timeout`] into a layer.
+    /// `limit` selects which of the [`Timeouts`] it sets.
+    pub(crate) fn add_timeout(
+        layer: &mut Layer,
+        val: &str,
+        limit: TimeoutField,
+    ) -> Result<(), String> {
+        let (pm, duration) = Self::parse_timeout(val)?;
+        let timeouts = match pm {
+            Some(pm) => layer.manager_timeouts.entry(pm).or_default(),
+            None => &mut layer.timeouts,
         };
+        *limit(timeouts) = Some(duration);
+        Ok(())
+    }
 
-        // Print error and exit for unrecognized arguments.
-        let remaining = pargs.finish();
-        if !remaining.is_empty() {
-            eprintln!("{} Unrecognized arguments: {:?}", "ERR:".red(), remaining);
-            std::process::exit(1);
-        }
-
-        Config {
-            exclusions,
-            only,
-            specs,
-            auto,
-            noconfirm,
-            verbose,
-            list,
-            dry_run,
-            exts,
-            ord,
-            //install_mode: false,
+    /// The time limits of `pm`.
+    #[must_use]
+    pub fn timeouts(&self, pm: &str) -> Timeouts {
+        self.manager_timeouts
+            .get(pm)
+            .copied()
+            .unwrap_or_default()
+            .or(self.timeouts)
+    }
+
+    /// Parses a hook of the form "command" (the whole run) or "pm::command" (one package manager).
+    /// A prefix only names a package manager if it is a plain name, so commands such as
+    /// `perl -MFoo::Bar` run around the whole run.
+    ///
+    /// # Errors
+    /// Returns a message if the command is empty.
+    pub fn parse_hook(val: &str) -> Result<(Option<String>, String), String> {
+        let is_name = |pm: &str| {
+            !pm.is_empty()
+                && pm
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'))
+        };
+        let (pm, command) = match val.split_once("::") {
+            Some((pm, command)) if is_name(pm) => (Some(pm.to_string()), command),
+            _ => (None, val),
+        };
+        if command.trim().is_empty() {
+            return Err(format!("Invalid hook: {val} (expected a command)"));
         }
+        Ok((pm, command.to_string()))
     }
 
-    fn print_help() {
-        println!(
-            r#"qud v1.5.1
-
-Usage:
-  qud [options]
-
-Options:
-  --dry, -d           Dry run (print commands instead of executing).
-  --excl, -e <s>      Exclude a package from a manager (format: pm::pkg) or a package manager entirely (format: pm). May be repeated.
-  --auto, -a          Auto mode (use non-interactive flags where available).
-  --verbose, -v       Enable verbose logging.
-  --list, -l          List detected package managers without updating.
-  --only, -o <pm>     Update only the specified package manager (may be repeated).
-  --spec, -s <s>      Override the detected executable for a package manager (format: pm::/path/to/executable). May be repeated.
-  --ext, -E <s>       Pass extra flags to a package manager (format: pm::"<flags>").
-  --ord, -O [s]       Specify the update order. If provided a value (pm1,pm2,...), that order is used for those found; if no value is provided, you'll be prompted to sort.
-  --help, -h          Show this help screen.
-  --version, -V       Show version information.
-  --self-update, -S   Update qud.
-  --noconfirm, -n     Don't confirm when updating. Does not pass non-interactive flags to package managers.
-"#
-        );
+    /// Inserts a hook of the form accepted by [`Config::parse_hook`] into a layer.
+    /// `list` selects which of the [`Hooks`] it is added to.
+    pub(crate) fn add_hook(layer: &mut Layer, val: &str, list: HookField) -> Result<(), String> {
+        let (pm, command) = Self::parse_hook(val)?;
+        let hooks = match pm {
+            Some(pm) => layer.manager_hooks.entry(pm).or_default(),
+            None => &mut layer.hooks,
+        };
+        list(hooks).push(command);
+        Ok(())
     }
 
-    /// Inserts an exclusion rule into the map.
-    fn add_exclusion(map: &mut HashMap<String, Vec<String>>, excl: &str) {
-        if excl.contains("::") {
-            let parts: Vec<&str> = excl.split("::").collect();
-            if parts.len() == 2 {
-                let pm = parts[0].to_string();
-                let pkg = parts[1].to_string();
-                // If the package manager was already fully excluded (empty vec), keep it that way.
-                map.entry(pm).or_default().push(pkg);
-            } else {
-                eprintln!("{} Invalid exclusion format: {excl}", "ERR:".red());
+    /// The hooks of `pm`.
+    #[must_use]
+    pub fn manager_hooks(&self, pm: &str) -> Hooks {
+        self.manager_hooks.get(pm).cloned().unwrap_or_default()
+    }
+
+    /// Parses a number of retries of the form "n" (all package managers) or "pm::n".
+    ///
+    /// # Errors
+    /// Returns a message if the number is invalid.
+    pub fn parse_retries(val: &str) -> Result<(Option<String>, u32), String> {
+        let (pm, n) = match val.split_once("::") {
+            Some((pm, n)) => (Some(pm.to_string()), n),
+            None => (None, val),
+        };
+        let n = n
+            .parse()
+            .map_err(|_| format!("Invalid number of retries: {val}"))?;
+        Ok((pm, n))
+    }
+
+    /// Inserts a number of retries of the form accepted by [`Config::parse_retries`] into a layer.
+    pub(crate) fn add_retries(layer: &mut Layer, val: &str) -> Result<(), String> {
+        match Self::parse_retries(val)? {
+            (Some(pm), n) => {
+                layer.manager_retries.insert(pm, n);
             }
+            (None, n) => layer.retries = Some(n),
+        }
+        Ok(())
+    }
+
+    /// How often failed idempotent steps of `pm` are retried.
+    #[must_use]
+    pub fn retries(&self, pm: &str) -> u32 {
+        self.manager_retries
+            .get(pm)
+            .copied()
+            .unwrap_or(self.retries)
+    }
+
+    /// Parses a snapshot tool such as "snapper", "btrfs:/home" or "lvm:vg0/root". "none" turns
+    /// snapshots off.
+    ///
+    /// # Errors
+    /// Returns a message if the tool is unknown or lacks a required target.
+    pub fn parse_snapshot(val: &str) -> Result<Option<Snapshot>, String> {
+        if val == "none" {
+            Ok(None)
         } else {
-            // Full exclusion: mark the package manager as entirely excluded by storing an empty Vec.
-            map.insert(excl.to_string(), Vec::new());
+            val.parse().map(Some)
         }
     }
 
-    /// Returns extra arguments for the given package manager based on the exclusions map.
-    pub fn get_exclusion_args(&self, pm: &str) -> Vec<String> {
-        let mut args = Vec::new();
-        if let Some(pkgs) = self.exclusions.get(pm) {
-            match pm {
-                "pacman" | "xbps-install" => {
-                    let joined = pkgs.join(", ");
-                    args.push("--ignore".to_string());
-                    args.push(joined);
-                }
-                "yay" => {
-                    for pkg in pkgs {
-                        args.push("--excludepkg".to_string());
-                        args.push(pkg.clone());
-                    }
-                }
-                "dnf" | "yum" | "zypper" => {
-                    for pkg in pkgs {
-                        args.push("--exclude".to_string());
-                        args.push(pkg.clone());
-                    }
+    /// Parses a number of parallel jobs.
+    ///
+    /// # Errors
+    /// Returns a message if the value is not a positive number.
+    pub fn parse_jobs(val: &str) -> Result<usize, String> {
+        match val.parse() {
+            Ok(jobs) if jobs > 0 => Ok(jobs),
+            _ => Err(format!("Invalid number of jobs: {val}")),
+        }
+    }
+
+    /// Parses a range of UIDs (`1000-60000`).
+    ///
+    /// # Errors
+    /// Returns an error if the value is not two numbers separated by `-`, the first not greater.
+    pub fn parse_uid_range(val: &str) -> Result<RangeInclusive<u32>, String> {
+        let invalid = || format!("Invalid UID range: {val} (expected min-max, e.g. 1000-60000)");
+        let (min, max) = val.split_once('-').ok_or_else(invalid)?;
+        let min: u32 = min.trim().parse().map_err(|_| invalid())?;
+        let max: u32 = max.trim().parse().map_err(|_| invalid())?;
+        if min > max {
+            return Err(invalid());
+        }
+        Ok(min..=max)
+    }
+
+    /// Parses an update order. An empty value asks interactively.
+    #[must_use]
+    pub fn parse_ord(val: &str) -> OrdMode {
+        let order: Vec<String> = val
+            .split(',')
+            .map(|s| s.trim().to_string())
+            .filter(|s| !s.is_empty())
+            .collect();
+        if order.is_empty() {
+            OrdMode::Interactive
+        } else {
+            OrdMode::Specified(order)
+        }
+    }
+
+    /// Whether `pm` passes the `--only` filter and is not excluded entirely.
+    #[must_use]
+    pub fn selects(&self, pm: &str) -> bool {
+        if let Some(ref only_list) = self.only {
+            if !only_list.iter().any(|s| s == pm) {
+                if self.verbose {
+                    say!(
+                        "{} Skipping {} because it is not in the --only list.",
+                        "INFO:".blue(),
+                        pm
+                    );
                 }
-                p => {
-                    eprintln!(
-                        "{} {} does not support exclusions (or not yet implemented). The following packages ({}) will still be updated.",
-                        "WARN:".yellow(),
-                        p,
-                        format_list(pkgs)
+                return false;
+            }
+        }
+        if let Some(exclusions) = self.exclusions.get(pm) {
+            if exclusions.is_empty() {
+                if self.verbose {
+                    say!(
+                        "{} Skipping {} because it is fully excluded via --excl",
+                        "INFO:".blue(),
+                        pm
                     );
                 }
+                return false;
             }
         }
-        args
+        true
+    }
+
+    /// How the given package manager leaves out its excluded packages. Warns if it cannot exclude
+    /// packages.
+    pub fn get_exclusion(&self, pm: &dyn PackageManager, ctx: &Ctx) -> Option<Exclusion> {
+        let pkgs = self
+            .exclusions
+            .get(pm.name())
+            .filter(|pkgs| !pkgs.is_empty())?;
+        let exclusion = pm.exclusion(ctx, pkgs);
+        if exclusion.is_none() {
+            eprintln!(
+                "{} {} does not support exclusions (or not yet implemented). The following packages ({}) will still be updated.",
+                "WARN:".yellow(),
+                pm.name(),
+                format_list(pkgs)
+            );
+        }
+        exclusion
     }
 
     /// Returns extra flags for the given package manager passed via --ext.
@@ -245,3 +648,339 @@ Options:
         self.exts.get(pm).cloned().unwrap_or_default()
     }
 }
+
+/// Builds a [`Config`] from values supplied in code, optionally layered over configuration files.
+#[derive(Default)]
+pub struct ConfigBuilder {
+    layer: Layer,
+    list: bool,
+    config_files: bool,
+}
+
+impl ConfigBuilder {
+    /// Excludes a package manager entirely.
+    pub fn exclude_manager(&mut self, pm: &str) -> &mut Self {
+        self.layer.exclusions.insert(pm.to_string(), Vec::new());
+        self
+    }
+
+    /// Excludes a single package of a package manager.
+    pub fn exclude_package(&mut self, pm: &str, pkg: &str) -> &mut Self {
+        self.layer
+            .exclusions
+            .entry(pm.to_string())
+            .or_default()
+            .push(pkg.to_string());
+        self
+    }
+
+    /// Restricts updates to `pm`. May be called repeatedly.
+    pub fn only(&mut self, pm: &str) -> &mut Self {
+        self.layer
+            .only
+            .get_or_insert_with(Vec::new)
+            .push(pm.to_string());
+        self
+    }
+
+    /// Overrides the detected executable of a package manager.
+    pub fn spec(&mut self, pm: &str, path: impl Into<PathBuf>) -> &mut Self {
+        self.layer.specs.insert(pm.to_string(), path.into());
+        self
+    }
+
+    /// Passes extra flags to every command of a package manager.
+    pub fn ext<I, S>(&mut self, pm: &str, flags: I) -> &mut Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.layer
+            .exts
+            .entry(pm.to_string())
+            .or_default()
+            .extend(flags.into_iter().map(Into::into));
+        self
+    }
+
+    pub fn order(&mut self, ord: OrdMode) -> &mut Self {
+        self.layer.ord = Some(ord);
+        self
+    }
+
+    pub fn auto(&mut self, auto: bool) -> &mut Self {
+        self.layer.auto = Some(auto);
+        self
+    }
+
+    pub fn noconfirm(&mut self, noconfirm: bool) -> &mut Self {
+        self.layer.noconfirm = Some(noconfirm);
+        self
+    }
+
+    pub fn verbose(&mut self, verbose: bool) -> &mut Self {
+        self.layer.verbose = Some(verbose);
+        self
+    }
+
+    pub fn dry_run(&mut self, dry_run: bool) -> &mut Self {
+        self.layer.dry_run = Some(dry_run);
+        self
+    }
+
+    pub fn skip_up_to_date(&mut self, skip: bool) -> &mut Self {
+        self.layer.skip_up_to_date = Some(skip);
+        self
+    }
+
+    pub fn history(&mut self, history: bool) -> &mut Self {
+        self.layer.history = Some(history);
+        self
+    }
+
+    pub fn capture_stderr(&mut self, capture: bool) -> &mut Self {
+        self.layer.capture_stderr = Some(capture);
+        self
+    }
+
+    pub fn wait(&mut self, wait: bool) -> &mut Self {
+        self.layer.wait = Some(wait);
+        self
+    }
+
+    pub fn escalation(&mut self, escalation: Escalation) -> &mut Self {
+        self.layer.escalation = Some(escalation);
+        self
+    }
+
+    pub fn user(&mut self, user: String) -> &mut Self {
+        self.layer.user = Some(user);
+        self
+    }
+
+    pub fn all_users(&mut self, all_users: bool) -> &mut Self {
+        self.layer.all_users = Some(all_users);
+        self
+    }
+
+    /// Restricts [`Config::all_users`] to the user `name`. May be called repeatedly.
+    pub fn allow_user(&mut self, name: &str) -> &mut Self {
+        self.layer
+            .users
+            .get_or_insert_with(Vec::new)
+            .push(name.to_string());
+        self
+    }
+
+    pub fn uid_range(&mut self, uids: RangeInclusive<u32>) -> &mut Self {
+        self.layer.uid_range = Some(uids);
+        self
+    }
+
+    /// Takes a snapshot with `snapshot` before system packages are updated, or none.
+    pub fn snapshot(&mut self, snapshot: Option<Snapshot>) -> &mut Self {
+        self.layer.snapshot = Some(snapshot);
+        self
+    }
+
+    pub fn format(&mut self, format: Format) -> &mut Self {
+        self.layer.format = Some(format);
+        self
+    }
+
+    /// Sets what to do when a step fails.
+    pub fn on_failure(&mut self, policy: FailurePolicy) -> &mut Self {
+        self.layer.on_failure = Some(policy);
+        self
+    }
+
+    /// Sets what to do when a step of `pm` fails.
+    pub fn manager_on_failure(&mut self, pm: &str, policy: FailurePolicy) -> &mut Self {
+        self.layer.failure_policies.insert(pm.to_string(), policy);
+        self
+    }
+
+    /// Updates up to `jobs` non-interactive package managers at the same time.
+    pub fn jobs(&mut self, jobs: usize) -> &mut Self {
+        self.layer.jobs = Some(jobs.max(1));
+        self
+    }
+
+    /// Limits how long steps may run.
+    pub fn timeouts(&mut self, timeouts: Timeouts) -> &mut Self {
+        self.layer.timeouts = timeouts;
+        self
+    }
+
+    /// Limits how long steps of `pm` may run.
+    pub fn manager_timeouts(&mut self, pm: &str, timeouts: Timeouts) -> &mut Self {
+        self.layer.manager_timeouts.insert(pm.to_string(), timeouts);
+        self
+    }
+
+    /// Runs commands before and after the whole run.
+    pub fn hooks(&mut self, hooks: Hooks) -> &mut Self {
+        self.layer.hooks = hooks;
+        self
+    }
+
+    /// Runs commands before and after the steps of `pm`.
+    pub fn manager_hooks(&mut self, pm: &str, hooks: Hooks) -> &mut Self {
+        self.layer.manager_hooks.insert(pm.to_string(), hooks);
+        self
+    }
+
+    /// Retries failed idempotent steps up to `retries` times.
+    pub fn retries(&mut self, retries: u32) -> &mut Self {
+        self.layer.retries = Some(retries);
+        self
+    }
+
+    /// Retries failed idempotent steps of `pm` up to `retries` times.
+    pub fn manager_retries(&mut self, pm: &str, retries: u32) -> &mut Self {
+        self.layer.manager_retries.insert(pm.to_string(), retries);
+        self
+    }
+
+    /// Waits `delay` before the first retry, doubling it for every further one.
+    pub fn retry_delay(&mut self, delay: Duration) -> &mut Self {
+        self.layer.retry_delay = Some(delay);
+        self
+    }
+
+    /// Waits up to `timeout` for locks of package managers held by other programs.
+    pub fn lock_timeout(&mut self, timeout: Duration) -> &mut Self {
+        self.layer.lock_timeout = Some(timeout);
+        self
+    }
+
+    pub fn list(&mut self, list: bool) -> &mut Self {
+        self.list = list;
+        self
+    }
+
+    /// Adds or replaces a user-defined package manager.
+    pub fn manager(&mut self, manager: CustomManager) -> &mut Self {
+        self.layer.managers.push(manager);
+        self
+    }
+
+    /// Layers the values set on this builder over configuration files and `QUD_*` variables.
+    pub fn config_files(&mut self, load: bool) -> &mut Self {
+        self.config_files = load;
+        self
+    }
+
+    /// Builds the configuration, leaving the builder empty.
+    ///
+    /// # Errors
+    /// Returns an error if a configuration file or environment variable is invalid.
+    pub fn build(&mut self) -> Result<Config, ConfigError> {
+        let mut layers = if self.config_files {
+            conf_file::load()?
+        } else {
+            Vec::new()
+        };
+        layers.push(std::mem::take(&mut self.layer));
+
+        let mut config = Config {
+            exclusions: HashMap::new(),
+            only: None,
+            specs: HashMap::new(),
+            auto: false,
+            noconfirm: false,
+            verbose: false,
+            list: self.list,
+            dry_run: false,
+            skip_up_to_date: false,
+            snapshot: None,
+            history: true,
+            capture_stderr: false,
+            wait: false,
+            escalation: Escalation::Auto,
+            user: None,
+            all_users: false,
+            users: None,
+            uid_range: None,
+            format: Format::Human,
+            on_failure: FailurePolicy::KeepGoing,
+            failure_policies: HashMap::new(),
+            jobs: 1,
+            timeouts: Timeouts::default(),
+            manager_timeouts: HashMap::new(),
+            retries: 0,
+            manager_retries: HashMap::new(),
+            retry_delay: Duration::from_secs(5),
+            lock_timeout: Duration::from_secs(300),
+            hooks: Hooks::default(),
+            manager_hooks: HashMap::new(),
+            exts: HashMap::new(),
+            ord: None,
+            managers: Vec::new(),
+            //install_mode: false,
+        };
+        for layer in layers {
+            config.apply(layer);
+        }
+        Ok(config)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn secs(val: &str) -> Result<u64, String> {
+        Config::parse_duration(val).map(|d| d.as_secs())
+    }
+
+    #[test]
+    fn parse_duration_units() {
+        assert_eq!(secs("90"), Ok(90));
+        assert_eq!(secs("90s"), Ok(90));
+        assert_eq!(secs("5m"), Ok(300));
+        assert_eq!(secs("2h"), Ok(7200));
+        assert_eq!(secs("1h30m"), Ok(5400));
+        assert_eq!(secs(" 1m30s "), Ok(90));
+    }
+
+    #[test]
+    fn parse_duration_zero() {
+        assert_eq!(secs("0"), Ok(0));
+        assert_eq!(secs("0s"), Ok(0));
+        assert_eq!(secs("0h0m"), Ok(0));
+    }
+
+    #[test]
+    fn parse_duration_rejects_number_after_unit() {
+        assert!(secs("1h30").is_err());
+        assert!(secs("1m5").is_err());
+    }
+
+    #[test]
+    fn parse_duration_rejects_garbage() {
+        for val in ["", " ", "m", "h5m", "5d", "-5", "1.5h", "5 m"] {
+            assert!(secs(val).is_err(), "{val:?} was accepted");
+        }
+    }
+
+    #[test]
+    fn parse_duration_overflow() {
+        assert!(secs("18446744073709551615").is_ok());
+        assert!(secs("18446744073709551616").is_err());
+        assert!(secs("5124095576030432h").is_err());
+        assert!(secs("5124095576030431h1000000s").is_err());
+    }
+
+    #[test]
+    fn parse_timeout_per_manager() {
+        assert_eq!(
+            Config::parse_timeout("emerge::3h"),
+            Ok((Some("emerge".to_string()), Duration::from_secs(10_800)))
+        );
+        assert_eq!(
+            Config::parse_timeout("30m"),
+            Ok((None, Duration::from_secs(1800)))
+        );
+    }
+}
@@ -0,0 +1,449 @@
+//! Configuration files and environment variables.
+//!
+//! Layers are returned from lowest to highest precedence:
+//! `/etc/qud/config.toml`, `/etc/qud/conf.d/*.toml`, `~/.config/qud/config.toml`,
+//! `~/.config/qud/conf.d/*.toml` and finally `QUD_*` environment variables.
+//! The command line is layered on top by `cli::parse_args` of the `qud` binary.
+
+use crate::conf::{Config, HookField, Layer, OrdMode, TimeoutField};
+use crate::escalation::Escalation;
+use crate::managers::CustomManager;
+use crate::output::Format;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::env;
+use std::fmt;
+use std::fs;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use toml::Spanned;
+
+/// An invalid configuration file or environment variable.
+#[derive(Debug)]
+pub struct ConfigError {
+    /// The file or environment variable the error comes from.
+    pub origin: String,
+    /// Line and column of the offending value, if known.
+    pub position: Option<(usize, usize)>,
+    pub message: String,
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.position {
+            Some((line, col)) => write!(f, "{}:{line}:{col}: {}", self.origin, self.message),
+            None => write!(f, "{}: {}", self.origin, self.message),
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+/// The contents of a configuration file. Keys mirror the long command-line options.
+#[derive(Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+struct FileConfig {
+    excl: Option<Vec<Spanned<String>>>,
+    only: Option<Vec<String>>,
+    spec: Option<HashMap<String, PathBuf>>,
+    ext: Option<HashMap<String, Flags>>,
+    ord: Option<Spanned<Order>>,
+    auto: Option<bool>,
+    noconfirm: Option<bool>,
+    verbose: Option<bool>,
+    dry: Option<bool>,
+    skip_up_to_date: Option<bool>,
+    history: Option<bool>,
+    capture_stderr: Option<bool>,
+    wait: Option<bool>,
+    escalation: Option<Spanned<String>>,
+    user: Option<String>,
+    all_users: Option<bool>,
+    users: Option<Vec<String>>,
+    uid_range: Option<Spanned<String>>,
+    snapshot: Option<Spanned<String>>,
+    format: Option<Spanned<String>>,
+    on_failure: Option<Spanned<Flags>>,
+    jobs: Option<Spanned<usize>>,
+    timeout: Option<Spanned<Flags>>,
+    manager_timeout: Option<Spanned<Flags>>,
+    stall_timeout: Option<Spanned<Flags>>,
+    retries: Option<Spanned<Retries>>,
+    retry_delay: Option<Spanned<String>>,
+    lock_timeout: Option<Spanned<String>>,
+    pre_hook: Option<Spanned<Flags>>,
+    post_hook: Option<Spanned<Flags>>,
+    managers: Option<HashMap<String, CustomManager>>,
+}
+
+/// Extra flags, either as a single string split on whitespace or as a list.
+/// Also used for other settings that take one value or a list.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum Flags {
+    Line(String),
+    List(Vec<String>),
+}
+
+impl Flags {
+    /// The values of a setting that takes one value or a list.
+    fn values(self) -> Vec<String> {
+        match self {
+            Flags::Line(line) => vec![line],
+            Flags::List(list) => list,
+        }
+    }
+}
+
+/// Retries, either a number for all package managers or a list in command-line syntax.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum Retries {
+    All(u32),
+    List(Vec<String>),
+}
+
+/// An update order, either a list of package managers or `"interactive"`.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum Order {
+    List(Vec<String>),
+    Mode(String),
+}
+
+/// Loads every configuration file and the environment, lowest precedence first.
+///
+/// # Errors
+/// Returns an error pointing at the file and line of the first invalid value.
+pub fn load() -> Result<Vec<Layer>, ConfigError> {
+    let mut layers = Vec::new();
+    for dir in config_dirs() {
+        for file in config_files(&dir) {
+            layers.push(load_file(&file)?);
+        }
+    }
+    layers.push(from_env()?);
+    Ok(layers)
+}
+
+/// The system and user configuration directories, lowest precedence first.
+fn config_dirs() -> Vec<PathBuf> {
+    let mut dirs = Vec::new();
+    #[cfg(not(target_os = "windows"))]
+    {
+        dirs.push(PathBuf::from("/etc/qud"));
+        if let Some(config_home) = env::var_os("XDG_CONFIG_HOME").filter(|v| !v.is_empty()) {
+            dirs.push(PathBuf::from(config_home).join("qud"));
+        } else if let Some(home) = env::var_os("HOME") {
+            dirs.push(PathBuf::from(home).join(".config").join("qud"));
+        }
+    }
+    #[cfg(target_os = "windows")]
+    {
+        if let Some(program_data) = env::var_os("ProgramData") {
+            dirs.push(PathBuf::from(program_data).join("qud"));
+        }
+        if let Some(app_data) = env::var_os("APPDATA") {
+            dirs.push(PathBuf::from(app_data).join("qud"));
+        }
+    }
+    dirs
+}
+
+/// `config.toml` followed by the drop-ins in `conf.d`, sorted by file name.
+fn config_files(dir: &Path) -> Vec<PathBuf> {
+    let mut files = Vec::new();
+    let main = dir.join("config.toml");
+    if main.is_file() {
+        files.push(main);
+    }
+    if let Ok(entries) = fs::read_dir(dir.join("conf.d")) {
+        let mut drop_ins: Vec<PathBuf> = entries
+            .flatten()
+            .map(|e| e.path())
+            .filter(|p| p.is_file() && p.extension().is_some_and(|ext| ext == "toml"))
+            .collect();
+        drop_ins.sort();
+        files.extend(drop_ins);
+    }
+    files
+}
+
+fn load_file(path: &Path) -> Result<Layer, ConfigError> {
+    let contents = fs::read_to_string(path).map_err(|e| ConfigError {
+        origin: path.display().to_string(),
+        position: None,
+        message: e.to_string(),
+    })?;
+    let at = |span: Option<Range<usize>>, message: String| ConfigError {
+        origin: path.display().to_string(),
+        position: span.map(|s| position(&contents, s.start)),
+        message,
+    };
+    let file: FileConfig =
+        toml::from_str(&contents).map_err(|e| at(e.span(), e.message().to_string()))?;
+
+    let mut layer = Layer {
+        only: file.only,
+        specs: file.spec.unwrap_or_default(),
+        auto: file.auto,
+        noconfirm: file.noconfirm,
+        verbose: file.verbose,
+        dry_run: file.dry,
+        skip_up_to_date: file.skip_up_to_date,
+        history: file.history,
+        capture_stderr: file.capture_stderr,
+        wait: file.wait,
+        all_users: file.all_users,
+        users: file.users,
+        ..Layer::default()
+    };
+    for excl in file.excl.unwrap_or_default() {
+        Config::add_exclusion(&mut layer.exclusions, excl.get_ref())
+            .map_err(|e| at(Some(excl.span()), e))?;
+    }
+    for (pm, flags) in file.ext.unwrap_or_default() {
+        let flags = match flags {
+            Flags::Line(line) => line.split_whitespace().map(ToString::to_string).collect(),
+            Flags::List(list) => list,
+        };
+        layer.exts.insert(pm, flags);
+    }
+    let mut managers: Vec<(String, CustomManager)> =
+        file.managers.unwrap_or_default().into_iter().collect();
+    managers.sort_by(|a, b| a.0.cmp(&b.0));
+    for (name, mut manager) in managers {
+        manager.name = name;
+        manager.check().map_err(|e| at(None, e))?;
+        layer.managers.push(manager);
+    }
+    if let Some(on_failure) = file.on_failure {
+        let span = on_failure.span();
+        for value in on_failure.into_inner().values() {
+            Config::add_on_failure(&mut layer, &value).map_err(|e| at(Some(span.clone()), e))?;
+        }
+    }
+    let timeouts: [(_, TimeoutField); 3] = [
+        (file.timeout, |t| &mut t.step),
+        (file.manager_timeout, |t| &mut t.manager),
+        (file.stall_timeout, |t| &mut t.stall),
+    ];
+    for (values, limit) in timeouts {
+        let Some(values) = values else { continue };
+        let span = values.span();
+        for value in values.into_inner().values() {
+            Config::add_timeout(&mut layer, &value, limit)
+                .map_err(|e| at(Some(span.clone()), e))?;
+        }
+    }
+    let hooks: [(_, HookField); 2] = [
+        (file.pre_hook, |h| &mut h.pre),
+        (file.post_hook, |h| &mut h.post),
+    ];
+    for (values, list) in hooks {
+        let Some(values) = values else { continue };
+        let span = values.span();
+        for value in values.into_inner().values() {
+            Config::add_hook(&mut layer, &value, list).map_err(|e| at(Some(span.clone()), e))?;
+        }
+    }
+    if let Some(retries) = file.retries {
+        let span = retries.span();
+        match retries.into_inner() {
+            Retries::All(n) => layer.retries = Some(n),
+            Retries::List(list) => {
+                for value in list {
+                    Config::add_retries(&mut layer, &value)
+                        .map_err(|e| at(Some(span.clone()), e))?;
+                }
+            }
+        }
+    }
+    if let Some(snapshot) = file.snapshot {
+        let parsed = Config::parse_snapshot(snapshot.get_ref());
+        layer.snapshot = Some(parsed.map_err(|e| at(Some(snapshot.span()), e))?);
+    }
+    if let Some(delay) = file.retry_delay {
+        let parsed = Config::parse_duration(delay.get_ref());
+        layer.retry_delay = Some(parsed.map_err(|e| at(Some(delay.span()), e))?);
+    }
+    if let Some(timeout) = file.lock_timeout {
+        let parsed = Config::parse_duration(timeout.get_ref());
+        layer.lock_timeout = Some(parsed.map_err(|e| at(Some(timeout.span()), e))?);
+    }
+    if let Some(jobs) = file.jobs {
+        if *jobs.get_ref() == 0 {
+            return Err(at(Some(jobs.span()), "expected at least 1 job".to_string()));
+        }
+        layer.jobs = Some(jobs.into_inner());
+    }
+    if let Some(escalation) = file.escalation {
+        let parsed = escalation.get_ref().parse::<Escalation>();
+        layer.escalation = Some(parsed.map_err(|e| at(Some(escalation.span()), e))?);
+    }
+    if let Some(user) = file.user {
+        layer.user = Some(user);
+    }
+    if let Some(uids) = file.uid_range {
+        let parsed = Config::parse_uid_range(uids.get_ref());
+        layer.uid_range = Some(parsed.map_err(|e| at(Some(uids.span()), e))?);
+    }
+    if let Some(format) = file.format {
+        let parsed = format.get_ref().parse::<Format>();
+        layer.format = Some(parsed.map_err(|e| at(Some(format.span()), e))?);
+    }
+    if let Some(ord) = file.ord {
+        let span = ord.span();
+        layer.ord = Some(match ord.into_inner() {
+            Order::List(list) => OrdMode::Specified(list),
+            Order::Mode(mode) if mode == "interactive" => OrdMode::Interactive,
+            Order::Mode(mode) => {
+                let message = format!("expected a list or \"interactive\", found \"{mode}\"");
+                return Err(at(Some(span), message));
+            }
+        });
+    }
+    Ok(layer)
+}
+
+/// Converts a byte offset into a 1-based line and column.
+fn position(contents: &str, offset: usize) -> (usize, usize) {
+    let before = &contents[..offset.min(contents.len())];
+    let line = before.matches('\n').count() + 1;
+    let col = before.len() - before.rfind('\n').map_or(0, |i| i + 1) + 1;
+    (line, col)
+}
+
+/// Reads `QUD_*` environment variables. List values are comma separated and use the
+/// command-line syntax, e.g. `QUD_EXCL=apt::vim,snap`. Hooks are shell commands, which may contain
+/// commas, so they take one per line.
+fn from_env() -> Result<Layer, ConfigError> {
+    let invalid = |var: &str, message: String| ConfigError {
+        origin: format!("environment variable {var}"),
+        position: None,
+        message,
+    };
+    let list = |var: &str| -> Vec<String> {
+        env::var(var)
+            .unwrap_or_default()
+            .split(',')
+            .map(str::trim)
+            .filter(|s| !s.is_empty())
+            .map(ToString::to_string)
+            .collect()
+    };
+    let lines = |var: &str| -> Vec<String> {
+        env::var(var)
+            .unwrap_or_default()
+            .lines()
+            .map(str::trim)
+            .filter(|s| !s.is_empty())
+            .map(ToString::to_string)
+            .collect()
+    };
+    let boolean = |var: &str| -> Result<Option<bool>, ConfigError> {
+        match env::var(var) {
+            Err(_) => Ok(None),
+            Ok(v) => match v.to_lowercase().as_str() {
+                "1" | "true" | "yes" | "on" => Ok(Some(true)),
+                "0" | "false" | "no" | "off" | "" => Ok(Some(false)),
+                _ => Err(invalid(var, format!("expected a boolean, found \"{v}\""))),
+            },
+        }
+    };
+
+    let mut layer = Layer {
+        auto: boolean("QUD_AUTO")?,
+        noconfirm: boolean("QUD_NOCONFIRM")?,
+        verbose: boolean("QUD_VERBOSE")?,
+        dry_run: boolean("QUD_DRY")?,
+        skip_up_to_date: boolean("QUD_SKIP_UP_TO_DATE")?,
+        history: boolean("QUD_HISTORY")?,
+        capture_stderr: boolean("QUD_CAPTURE_STDERR")?,
+        wait: boolean("QUD_WAIT")?,
+        all_users: boolean("QUD_ALL_USERS")?,
+        ..Layer::default()
+    };
+    for excl in list("QUD_EXCL") {
+        Config::add_exclusion(&mut layer.exclusions, &excl).map_err(|e| invalid("QUD_EXCL", e))?;
+    }
+    let only = list("QUD_ONLY");
+    if !only.is_empty() {
+        layer.only = Some(only);
+    }
+    for spec in list("QUD_SPEC") {
+        let (pm, path) = Config::parse_spec(&spec).map_err(|e| invalid("QUD_SPEC", e))?;
+        layer.specs.insert(pm, path);
+    }
+    for ext in list("QUD_EXT") {
+        let (pm, flags) = Config::parse_ext(&ext).map_err(|e| invalid("QUD_EXT", e))?;
+        layer.exts.entry(pm).or_default().extend(flags);
+    }
+    for on_failure in list("QUD_ON_FAILURE") {
+        Config::add_on_failure(&mut layer, &on_failure)
+            .map_err(|e| invalid("QUD_ON_FAILURE", e))?;
+    }
+    let timeouts: [(_, TimeoutField); 3] = [
+        ("QUD_TIMEOUT", |t| &mut t.step),
+        ("QUD_MANAGER_TIMEOUT", |t| &mut t.manager),
+        ("QUD_STALL_TIMEOUT", |t| &mut t.stall),
+    ];
+    for (var, limit) in timeouts {
+        for value in list(var) {
+            Config::add_timeout(&mut layer, &value, limit).map_err(|e| invalid(var, e))?;
+        }
+    }
+    let hooks: [(_, HookField); 2] = [
+        ("QUD_PRE_HOOK", |h| &mut h.pre),
+        ("QUD_POST_HOOK", |h| &mut h.post),
+    ];
+    for (var, hook) in hooks {
+        for value in lines(var) {
+            Config::add_hook(&mut layer, &value, hook).map_err(|e| invalid(var, e))?;
+        }
+    }
+    for retries in list("QUD_RETRIES") {
+        Config::add_retries(&mut layer, &retries).map_err(|e| invalid("QUD_RETRIES", e))?;
+    }
+    if let Ok(delay) = env::var("QUD_RETRY_DELAY") {
+        let delay = Config::parse_duration(&delay).map_err(|e| invalid("QUD_RETRY_DELAY", e))?;
+        layer.retry_delay = Some(delay);
+    }
+    if let Ok(timeout) = env::var("QUD_LOCK_TIMEOUT") {
+        let timeout =
+            Config::parse_duration(&timeout).map_err(|e| invalid("QUD_LOCK_TIMEOUT", e))?;
+        layer.lock_timeout = Some(timeout);
+    }
+    if let Ok(snapshot) = env::var("QUD_SNAPSHOT") {
+        let snapshot = Config::parse_snapshot(&snapshot).map_err(|e| invalid("QUD_SNAPSHOT", e))?;
+        layer.snapshot = Some(snapshot);
+    }
+    if let Ok(jobs) = env::var("QUD_JOBS") {
+        layer.jobs = Some(Config::parse_jobs(&jobs).map_err(|e| invalid("QUD_JOBS", e))?);
+    }
+    if let Ok(escalation) = env::var("QUD_ESCALATION") {
+        layer.escalation = Some(
+            escalation
+                .parse()
+                .map_err(|e| invalid("QUD_ESCALATION", e))?,
+        );
+    }
+    if let Ok(user) = env::var("QUD_USER") {
+        layer.user = Some(user);
+    }
+    let users = list("QUD_USERS");
+    if !users.is_empty() {
+        layer.users = Some(users);
+    }
+    if let Ok(uids) = env::var("QUD_UID_RANGE") {
+        let uids = Config::parse_uid_range(&uids).map_err(|e| invalid("QUD_UID_RANGE", e))?;
+        layer.uid_range = Some(uids);
+    }
+    if let Ok(format) = env::var("QUD_FORMAT") {
+        layer.format = Some(format.parse().map_err(|e| invalid("QUD_FORMAT", e))?);
+    }
+    if let Ok(ord) = env::var("QUD_ORD") {
+        layer.ord = Some(Config::parse_ord(&ord));
+    }
+    Ok(layer)
+}
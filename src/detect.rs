@@ -0,0 +1,102 @@
+//! Detection of installed package managers.
+
+use crate::account::Account;
+use crate::helpers::{find_in_dirs, find_matching_executables};
+use crate::managers::{PackageManager, Registry, Scope};
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Directories of a home directory that users add to their PATH for user-level package managers.
+const HOME_BIN_DIRS: &[&str] = &[
+    ".local/bin",
+    "bin",
+    ".cargo/bin",
+    ".linuxbrew/bin",
+    ".nix-profile/bin",
+];
+
+/// Shared installations of user-level package managers, owned by the user who installed them.
+const SHARED_BIN_DIRS: &[&str] = &["/home/linuxbrew/.linuxbrew/bin"];
+
+/// The package managers found in PATH.
+pub struct Detection {
+    /// The executable used for each package manager, in PATH order.
+    pub found: Vec<(String, PathBuf)>,
+    /// Other installations of the same package managers, shadowed by those in `found`.
+    pub duplicates: HashMap<String, Vec<PathBuf>>,
+    /// Paths leading to the same file as one in `found` or `duplicates`, e.g. `/bin/pacman` where
+    /// `/bin` links to `/usr/bin`, or `pip` linking to `pip3`.
+    pub aliases: HashMap<String, Vec<PathBuf>>,
+}
+
+/// Searches PATH for the executables of every package manager in `registry`.
+#[must_use]
+pub fn detect(registry: &Registry) -> Detection {
+    let mut found: Vec<(String, PathBuf)> = Vec::new();
+    let mut duplicates: HashMap<String, Vec<PathBuf>> = HashMap::new();
+    let mut aliases: HashMap<String, Vec<PathBuf>> = HashMap::new();
+    // The files behind the executables of each package manager, with symlinks resolved.
+    let mut files: HashMap<String, Vec<PathBuf>> = HashMap::new();
+    for (exe, candidate) in find_matching_executables(&registry.executables()) {
+        let Some(manager) = registry.by_executable(&exe) else {
+            continue;
+        };
+        let pm_name = manager.name().to_string();
+        let file = fs::canonicalize(&candidate).unwrap_or_else(|_| candidate.clone());
+        let seen = files.entry(pm_name.clone()).or_default();
+        if seen.contains(&file) {
+            aliases.entry(pm_name).or_default().push(candidate);
+            continue;
+        }
+        seen.push(file);
+        if found.iter().any(|(name, _)| *name == pm_name) {
+            duplicates.entry(pm_name).or_default().push(candidate);
+        } else {
+            found.push((pm_name, candidate));
+        }
+    }
+    Detection {
+        found,
+        duplicates,
+        aliases,
+    }
+}
+
+/// The user-level package managers of `account`, searched for in the usual directories of its home
+/// and in PATH. One installed elsewhere than the home directory only counts if `account` owns it
+/// (e.g. Homebrew in `/home/linuxbrew`) or has installed something with it.
+#[must_use]
+pub fn user_managers(registry: &Registry, account: &Account) -> Vec<(String, PathBuf)> {
+    let dirs: Vec<PathBuf> = HOME_BIN_DIRS
+        .iter()
+        .map(|dir| account.home.join(dir))
+        .chain(SHARED_BIN_DIRS.iter().map(PathBuf::from))
+        .chain(env::var_os("PATH").iter().flat_map(env::split_paths))
+        .collect();
+    registry
+        .all()
+        .filter(|manager| manager.scope() == Scope::User)
+        .filter_map(|manager| {
+            let exe = find_in_dirs(manager.executable(), dirs.iter().cloned())?;
+            belongs_to(manager, &exe, account).then(|| (manager.name().to_string(), exe))
+        })
+        .collect()
+}
+
+fn belongs_to(manager: &dyn PackageManager, exe: &Path, account: &Account) -> bool {
+    #[cfg(unix)]
+    let owned = {
+        use std::os::unix::fs::MetadataExt;
+        fs::metadata(exe).is_ok_and(|meta| meta.uid() == account.uid)
+    };
+    #[cfg(not(unix))]
+    let owned = false;
+    exe.starts_with(&account.home)
+        || owned
+        || manager
+            .home_dirs()
+            .iter()
+            .any(|dir| account.home.join(dir).exists())
+}
@@ -0,0 +1,258 @@
+//! How privileged steps get their privileges.
+
+use crate::helpers::find_in_path;
+use crate::plan::{PlannedStep, UpdatePlan};
+use crate::self_up::perm::is_elevated;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::process::{Command, Stdio};
+use std::str::FromStr;
+use std::sync::mpsc::{self, RecvTimeoutError, Sender};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+/// How often cached credentials are refreshed during a run. sudo forgets them after 5 minutes by
+/// default.
+const REFRESH_INTERVAL: Duration = Duration::from_secs(60);
+
+/// The program running privileged steps.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Escalation {
+    /// The first of sudo, doas, run0 and pkexec that is installed (`runas` on Windows).
+    #[default]
+    Auto,
+    Sudo,
+    Doas,
+    /// systemd's `run0`.
+    Run0,
+    /// polkit's `pkexec`.
+    Pkexec,
+    /// `runas /user:Administrator`.
+    Runas,
+    /// Run privileged steps as they are, e.g. in containers without sudo.
+    None,
+}
+
+impl FromStr for Escalation {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Escalation, String> {
+        match s {
+            "auto" => Ok(Escalation::Auto),
+            "sudo" => Ok(Escalation::Sudo),
+            "doas" => Ok(Escalation::Doas),
+            "run0" => Ok(Escalation::Run0),
+            "pkexec" => Ok(Escalation::Pkexec),
+            "runas" => Ok(Escalation::Runas),
+            "none" => Ok(Escalation::None),
+            _ => Err(format!(
+                "Invalid escalation: {s} (expected auto, sudo, doas, run0, pkexec, runas or none)"
+            )),
+        }
+    }
+}
+
+impl fmt::Display for Escalation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Escalation::Auto => "auto",
+            Escalation::Sudo => "sudo",
+            Escalation::Doas => "doas",
+            Escalation::Run0 => "run0",
+            Escalation::Pkexec => "pkexec",
+            Escalation::Runas => "runas",
+            Escalation::None => "none",
+        })
+    }
+}
+
+impl Escalation {
+    /// The backend privileged steps run with: `None` if qud already runs elevated or escalation
+    /// is off. [`Escalation::Auto`] picks an installed program, falling back to sudo.
+    #[must_use]
+    pub fn resolve(self) -> Option<Escalation> {
+        if self == Escalation::None || is_elevated() {
+            return None;
+        }
+        if self != Escalation::Auto {
+            return Some(self);
+        }
+        #[cfg(target_os = "windows")]
+        return Some(Escalation::Runas);
+        #[cfg(not(target_os = "windows"))]
+        Some(
+            [
+                Escalation::Sudo,
+                Escalation::Doas,
+                Escalation::Run0,
+                Escalation::Pkexec,
+            ]
+            .into_iter()
+            .find(|backend| backend.program().and_then(find_in_path).is_some())
+            .unwrap_or(Escalation::Sudo),
+        )
+    }
+
+    /// The program to run.
+    #[must_use]
+    pub fn program(self) -> Option<&'static str> {
+        match self {
+            Escalation::Sudo => Some("sudo"),
+            Escalation::Doas => Some("doas"),
+            Escalation::Run0 => Some("run0"),
+            Escalation::Pkexec => Some("pkexec"),
+            Escalation::Runas => Some("runas"),
+            Escalation::Auto | Escalation::None => None,
+        }
+    }
+
+    /// Builds the process running `command` with `args` through this backend.
+    #[must_use]
+    pub fn command(self, command: &str, args: &[String]) -> Command {
+        let Some(program) = self.program() else {
+            let mut cmd = Command::new(command);
+            cmd.args(args);
+            return cmd;
+        };
+        let mut cmd = Command::new(program);
+        if self == Escalation::Runas {
+            // runas takes the whole command line as one argument.
+            cmd.arg("/user:Administrator")
+                .arg(format!("{command} {}", args.join(" ")));
+        } else {
+            cmd.arg(command).args(args);
+        }
+        cmd
+    }
+
+    /// The command checking the user's credentials, for backends that remember them for later
+    /// steps. Unless `prompt` is set, it fails instead of asking for a password. run0 and pkexec
+    /// remember nothing, so they ask for every step.
+    fn validate(self, prompt: bool) -> Option<Command> {
+        let args: &[&str] = match (self, prompt) {
+            (Escalation::Sudo, true) => &["-v"],
+            (Escalation::Sudo, false) => &["-n", "-v"],
+            (Escalation::Doas, true) => &["true"],
+            (Escalation::Doas, false) => &["-n", "true"],
+            _ => return None,
+        };
+        let mut cmd = Command::new(self.program()?);
+        cmd.args(args).stdout(Stdio::null());
+        Some(cmd)
+    }
+
+    /// The command extending how long the backend remembers the credentials, without asking for
+    /// them again.
+    fn refresh(self) -> Option<Command> {
+        if self != Escalation::Sudo {
+            return None;
+        }
+        let mut cmd = Command::new("sudo");
+        cmd.args(["-n", "-v"])
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null());
+        Some(cmd)
+    }
+
+    /// Shows `command` as run through this backend.
+    #[must_use]
+    pub fn display(self, command: &str) -> String {
+        match self.program() {
+            Some("runas") => format!("runas /user:Administrator \"{command}\""),
+            Some(program) => format!("{program} {command}"),
+            None => command.to_string(),
+        }
+    }
+}
+
+/// Checks once, before anything is updated, that the privileged steps of `plan` can get their
+/// privileges, asking for the password unless `auto` is set. The credentials are then kept fresh
+/// until the returned [`Keepalive`] is dropped, so that no step stops to ask again.
+///
+/// With `auto`, a sudo check that fails without a password is followed by `sudo -n -l` for every
+/// privileged step, since sudoers rules may let those commands run without one.
+///
+/// # Errors
+/// Returns a message if the backend is not installed, authentication failed, or with `auto` a
+/// privileged step would need a password.
+pub fn authenticate(plan: &UpdatePlan, auto: bool) -> Result<Option<Keepalive>, String> {
+    let Some((backend, program)) = plan
+        .escalation()
+        .and_then(|backend| Some((backend, backend.program()?)))
+    else {
+        return Ok(None);
+    };
+    if find_in_path(program).is_none() {
+        return Err(format!(
+            "Cannot run privileged steps: {program} is not installed. Pick another program with --escalation, or run qud as root."
+        ));
+    }
+    let Some(mut validate) = backend.validate(!auto) else {
+        return Ok(None);
+    };
+    if validate.status().is_ok_and(|status| status.success()) {
+        return Ok(backend.refresh().map(Keepalive::start));
+    }
+    if !auto {
+        return Err(format!("Authentication with {program} failed."));
+    }
+    if backend != Escalation::Sudo {
+        return Err(format!(
+            "{program} needs a password, which --auto does not ask for. Allow it without one, or run qud as root."
+        ));
+    }
+    match plan.privileged_steps().find(|step| !allowed(step)) {
+        Some(step) => Err(format!(
+            "sudo needs a password to run {} {}, which --auto does not ask for. Allow it with NOPASSWD, or run qud as root.",
+            step.program,
+            step.args.join(" ")
+        )),
+        None => Ok(None),
+    }
+}
+
+/// Whether sudo lets `step` run without asking for a password.
+fn allowed(step: &PlannedStep) -> bool {
+    Command::new("sudo")
+        .args(["-n", "-l", &step.program])
+        .args(&step.args)
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status()
+        .is_ok_and(|status| status.success())
+}
+
+/// Refreshes cached credentials in the background until dropped.
+#[derive(Debug)]
+pub struct Keepalive {
+    stop: Option<Sender<()>>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Keepalive {
+    fn start(mut refresh: Command) -> Keepalive {
+        let (stop, stopped) = mpsc::channel();
+        let thread = thread::spawn(move || {
+            while let Err(RecvTimeoutError::Timeout) = stopped.recv_timeout(REFRESH_INTERVAL) {
+                let _ = refresh.status();
+            }
+        });
+        Keepalive {
+            stop: Some(stop),
+            thread: Some(thread),
+        }
+    }
+}
+
+impl Drop for Keepalive {
+    fn drop(&mut self) {
+        // Disconnecting the channel wakes the thread up.
+        self.stop.take();
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
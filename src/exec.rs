@@ -0,0 +1,1132 @@
+//! Execution of update plans.
+
+use crate::conf::{Config, FailurePolicy, Timeouts};
+use crate::helpers::format_list;
+use crate::lock::{self, NativeState};
+use crate::managers::Ctx;
+use crate::outdated;
+use crate::output::{is_machine, Event, Reporter};
+use crate::plan::{ManagerPlan, PlannedStep, StepKind, UpdatePlan};
+use crate::say;
+use crate::snapshot::{SnapshotPlan, SnapshotRecord};
+use colored::Colorize;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+use std::io::{self, BufRead, BufReader, IsTerminal, Read, Write};
+use std::process::{Child, Command, ExitStatus, Stdio};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Number of trailing stderr lines kept for each step.
+const TAIL_LINES: usize = 20;
+/// How often a running step is checked against its time limits.
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+/// The longest wait between two attempts of a step.
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(300);
+/// How long a step may take to exit after being asked to stop before it is killed.
+const KILL_GRACE: Duration = Duration::from_secs(10);
+/// How often a package manager's lock held by another program is checked again.
+const LOCK_POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Why a step failed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FailureReason {
+    /// It exited with a non-zero status or was killed by a signal.
+    Exit,
+    /// It could not be started.
+    Spawn,
+    /// It ran longer than its time limit.
+    Timeout,
+    /// It printed nothing for longer than its stall limit.
+    Stalled,
+    /// It did not run because another program held the package manager's lock.
+    Locked,
+}
+
+/// The outcome of running one step.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StepResult {
+    /// The [label](ManagerPlan::label) of the package manager, empty for hooks of the whole run.
+    pub manager: String,
+    pub kind: StepKind,
+    pub program: String,
+    pub args: Vec<String>,
+    pub success: bool,
+    /// Why the step failed, if it did.
+    pub reason: Option<FailureReason>,
+    /// How often the step was run. More than one if it was retried.
+    pub attempts: u32,
+    /// The exit code, if the process exited normally.
+    pub exit_code: Option<i32>,
+    /// Why the step could not run at all, e.g. a missing executable.
+    pub error: Option<String>,
+    #[serde(
+        rename = "duration_secs",
+        serialize_with = "as_secs",
+        deserialize_with = "from_secs"
+    )]
+    pub duration: Duration,
+    /// The last lines the step wrote to stderr. Only captured with [`Config::capture_stderr`], in
+    /// the JSON formats and when output is piped anyway (parallel runs, stall timeouts).
+    pub stderr_tail: Vec<String>,
+}
+
+pub(crate) fn as_secs<S: serde::Serializer>(
+    duration: &Duration,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    serializer.serialize_f64(duration.as_secs_f64())
+}
+
+pub(crate) fn from_secs<'de, D: serde::Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Duration, D::Error> {
+    let secs = f64::deserialize(deserializer)?;
+    Duration::try_from_secs_f64(secs).map_err(serde::de::Error::custom)
+}
+
+/// What running a plan did.
+#[derive(Debug, Default)]
+pub struct Outcome {
+    /// The results of the steps that ran.
+    pub results: Vec<StepResult>,
+    /// Package managers, by [label](ManagerPlan::label), whose upgrades were skipped because
+    /// nothing was pending.
+    pub up_to_date: Vec<String>,
+    /// The snapshot taken before updating system packages.
+    pub snapshot: Option<SnapshotRecord>,
+}
+
+/// Runs every step of `plan`, or prints the steps in dry-run mode.
+/// A failed step is handled according to the package manager's [`FailurePolicy`].
+///
+/// With more than one job, interactive package managers run first, one at a time, and the rest run
+/// concurrently with their output prefixed by the package manager's name.
+///
+/// If the plan has a snapshot, it is taken before any package manager runs; if that fails, package
+/// managers updating system packages are skipped.
+///
+/// The hooks of the whole run come first and last. If one of its pre-hooks fails, nothing else
+/// runs; if a pre-hook of a package manager fails, that package manager is skipped. Post-hooks run
+/// even after failures.
+///
+/// With [`Config::skip_up_to_date`], package managers are asked for pending updates after their
+/// refresh steps, and their upgrade steps are skipped if there are none.
+pub fn execute(plan: &UpdatePlan, config: &Config, reporter: &mut Reporter) -> Outcome {
+    let run = Run {
+        config,
+        reporter: Mutex::new(reporter),
+        results: Mutex::new(Vec::new()),
+        up_to_date: Mutex::new(Vec::new()),
+        stop: AtomicBool::new(false),
+    };
+    let hooks = |kind| plan.hooks.iter().filter(move |h| h.kind == kind);
+    for hook in hooks(StepKind::PreHook) {
+        if !run.hook(None, hook, None, BTreeMap::new()) {
+            eprintln!(
+                "{} Not updating anything because a pre-hook failed.",
+                "ERR:".red()
+            );
+            return run.outcome();
+        }
+    }
+    let snapshot = plan
+        .snapshot
+        .as_ref()
+        .filter(|_| plan.managers.iter().any(|m| m.snapshot))
+        .and_then(|snapshot| run.snapshot(snapshot));
+    // System packages are not updated without the snapshot that was asked for.
+    let unprotected = snapshot.as_ref().is_some_and(|s| s.error.is_some());
+    let managers = plan
+        .managers
+        .iter()
+        .filter(|m| !(unprotected && m.snapshot));
+    let (parallel, serial): (Vec<&ManagerPlan>, Vec<&ManagerPlan>) =
+        if config.jobs > 1 && !config.dry_run {
+            managers.partition(|m| !m.interactive)
+        } else {
+            (Vec::new(), managers.collect())
+        };
+    for manager in serial {
+        if run.stopped() {
+            break;
+        }
+        run.manager(manager, false);
+    }
+    if !parallel.is_empty() && !run.stopped() {
+        let workers = config.jobs.min(parallel.len());
+        let queue = Mutex::new(parallel.into_iter());
+        thread::scope(|scope| {
+            for _ in 0..workers {
+                scope.spawn(|| loop {
+                    let next = queue.lock().unwrap().next();
+                    match next {
+                        Some(manager) if !run.stopped() => run.manager(manager, true),
+                        _ => break,
+                    }
+                });
+            }
+        });
+    }
+    let failed: BTreeSet<String> = run
+        .results
+        .lock()
+        .unwrap()
+        .iter()
+        .filter(|r| !r.success)
+        .map(|r| r.manager.clone())
+        .collect();
+    let context = BTreeMap::from([
+        (
+            "QUD_STATUS".to_string(),
+            if failed.is_empty() {
+                "succeeded"
+            } else {
+                "failed"
+            }
+            .to_string(),
+        ),
+        (
+            "QUD_FAILED".to_string(),
+            failed.into_iter().collect::<Vec<_>>().join(" "),
+        ),
+    ]);
+    for hook in hooks(StepKind::PostHook) {
+        run.hook(None, hook, None, context.clone());
+    }
+    Outcome {
+        snapshot,
+        ..run.outcome()
+    }
+}
+
+/// State shared by the package managers of one run.
+struct Run<'a, 'r> {
+    config: &'a Config,
+    reporter: Mutex<&'r mut Reporter>,
+    results: Mutex<Vec<StepResult>>,
+    up_to_date: Mutex<Vec<String>>,
+    /// Set when a fail-fast package manager failed.
+    stop: AtomicBool,
+}
+
+impl Run<'_, '_> {
+    fn outcome(self) -> Outcome {
+        Outcome {
+            results: self.results.into_inner().unwrap(),
+            up_to_date: self.up_to_date.into_inner().unwrap(),
+            snapshot: None,
+        }
+    }
+
+    /// Takes the snapshot of the plan, or prints it in dry-run mode.
+    fn snapshot(&self, snapshot: &SnapshotPlan) -> Option<SnapshotRecord> {
+        if self.config.dry_run {
+            say!("Dry run: snapshot: {}", snapshot.step);
+            return None;
+        }
+        say!(
+            "{} Taking a {} snapshot: {}",
+            "INFO:".blue(),
+            snapshot.tool,
+            snapshot.step
+        );
+        let record = snapshot.take();
+        match (&record.error, &record.id) {
+            (Some(e), _) => eprintln!(
+                "{} Not updating system packages because the snapshot failed: {e}",
+                "ERR:".red()
+            ),
+            (None, Some(id)) => say!("{} Created snapshot {id}.", "INFO:".blue()),
+            (None, None) => say!("{} Created a snapshot.", "INFO:".blue()),
+        }
+        Some(record)
+    }
+
+    fn stopped(&self) -> bool {
+        self.stop.load(Ordering::Relaxed)
+    }
+
+    fn emit(&self, event: &Event) {
+        self.reporter.lock().unwrap().emit(event);
+    }
+
+    /// Runs the steps of one package manager. `parallel` detaches the steps from the terminal
+    /// and prefixes their output.
+    fn manager(&self, manager: &ManagerPlan, parallel: bool) {
+        let label = manager.label();
+        say!(
+            "{} Processing package manager: {} ({})",
+            "INFO:".blue(),
+            label,
+            manager.exe.display()
+        );
+        let prefix = parallel.then(|| format!("[{label}] "));
+        let hooks = |kind| manager.steps.iter().filter(move |s| s.kind == kind);
+        for hook in hooks(StepKind::PreHook) {
+            if !self.hook(Some(manager), hook, prefix.as_deref(), BTreeMap::new()) {
+                eprintln!(
+                    "{} Skipping {} because its pre-hook failed.",
+                    "WARN:".yellow(),
+                    label
+                );
+                return;
+            }
+        }
+        let context = self.steps(manager, prefix.as_deref());
+        for hook in hooks(StepKind::PostHook) {
+            self.hook(Some(manager), hook, prefix.as_deref(), context.clone());
+        }
+    }
+
+    /// Runs the refresh, hold and upgrade steps of one package manager, then releases what was
+    /// held. Returns what its post-hooks are told about them: `QUD_STATUS` and the command and exit
+    /// code of the last step.
+    fn steps(&self, manager: &ManagerPlan, prefix: Option<&str>) -> BTreeMap<String, String> {
+        let policy = self.config.failure_policy(&manager.name);
+        let timeouts = self.config.timeouts(&manager.name);
+        let label = manager.label();
+        let started = Instant::now();
+        let steps: Vec<&PlannedStep> = manager
+            .steps
+            .iter()
+            .filter(|s| !s.kind.is_hook() && s.kind != StepKind::Release)
+            .collect();
+        let mut context = BTreeMap::new();
+        let mut status = "succeeded";
+        if let (false, Some(first)) = (self.config.dry_run, steps.first()) {
+            if let Err(e) = self.wait_for_locks(manager) {
+                eprintln!("{} Not updating {label}: {e}", "ERR:".red());
+                let result = StepResult {
+                    manager: label,
+                    kind: first.kind,
+                    program: first.program.clone(),
+                    args: first.args.clone(),
+                    success: false,
+                    reason: Some(FailureReason::Locked),
+                    attempts: 0,
+                    exit_code: None,
+                    error: Some(e),
+                    duration: started.elapsed(),
+                    stderr_tail: Vec::new(),
+                };
+                self.emit(&Event::StepFinished { result: &result });
+                self.results.lock().unwrap().push(result);
+                if policy == FailurePolicy::FailFast {
+                    self.stop.store(true, Ordering::Relaxed);
+                }
+                context.insert("QUD_STATUS".to_string(), "failed".to_string());
+                return context;
+            }
+        }
+        let mut checked = !self.config.skip_up_to_date;
+        let mut held = false;
+        let mut held_before = Vec::new();
+        for (i, &step) in steps.iter().enumerate() {
+            if !checked && matches!(step.kind, StepKind::Hold | StepKind::Upgrade) {
+                checked = true;
+                if self.up_to_date(manager, step) {
+                    status = "up_to_date";
+                    break;
+                }
+            }
+            let without;
+            let step = if step.kind == StepKind::Hold {
+                if !self.config.dry_run {
+                    held_before = self.held(manager, step);
+                }
+                let Some(step) = without_held(self.config, manager, step, &held_before) else {
+                    continue;
+                };
+                held = true;
+                without = step;
+                &without
+            } else {
+                step
+            };
+            if self.config.dry_run {
+                say!("Dry run: {step}");
+                continue;
+            }
+            let remaining = timeouts
+                .manager
+                .map(|t| t.saturating_sub(started.elapsed()));
+            if remaining == Some(Duration::ZERO) {
+                eprintln!(
+                    "{} Skipping the remaining steps of {} because its time limit was reached.",
+                    "WARN:".yellow(),
+                    label
+                );
+                break;
+            }
+            self.emit(&Event::StepStarted {
+                manager: &label,
+                step,
+            });
+            let result = self.step(Some(manager), step, prefix, timeouts, started);
+            self.emit(&Event::StepFinished { result: &result });
+            let failed = !result.success;
+            context.insert("QUD_STEP".to_string(), step.to_string());
+            context.insert(
+                "QUD_EXIT_CODE".to_string(),
+                result.exit_code.map(|c| c.to_string()).unwrap_or_default(),
+            );
+            self.results.lock().unwrap().push(result);
+            if !failed {
+                continue;
+            }
+            status = "failed";
+            match policy {
+                FailurePolicy::FailFast => {
+                    eprintln!(
+                        "{} Stopping because {} failed (fail-fast).",
+                        "ERR:".red(),
+                        label
+                    );
+                    self.stop.store(true, Ordering::Relaxed);
+                    break;
+                }
+                FailurePolicy::KeepGoing if i + 1 < steps.len() => {
+                    eprintln!(
+                        "{} Skipping the remaining steps of {} because a step failed.",
+                        "WARN:".yellow(),
+                        label
+                    );
+                    break;
+                }
+                FailurePolicy::RunAll if step.kind == StepKind::Hold => {
+                    eprintln!(
+                        "{} Skipping the upgrade of {} because its excluded packages could not be held.",
+                        "WARN:".yellow(),
+                        label
+                    );
+                    break;
+                }
+                FailurePolicy::KeepGoing | FailurePolicy::RunAll => {}
+            }
+        }
+        if held && !self.release(manager, prefix, timeouts, &held_before) {
+            status = "failed";
+        }
+        context.insert("QUD_STATUS".to_string(), status.to_string());
+        context
+    }
+
+    /// The excluded packages of `manager` that were held before the run, listed with
+    /// [`PackageManager::held`](crate::PackageManager::held) as the user `step` runs as. They are
+    /// neither held again nor released afterwards.
+    fn held(&self, manager: &ManagerPlan, step: &PlannedStep) -> Vec<String> {
+        let Some(excluded) = self.config.exclusions.get(&manager.name) else {
+            return Vec::new();
+        };
+        let registry = self.config.registry();
+        let ctx = Ctx {
+            exe: &manager.exe,
+            auto: self.config.auto,
+            dir: &step.dir,
+            sudo: false,
+        };
+        let Some(query) = registry.get(&manager.name).and_then(|pm| pm.held(&ctx)) else {
+            return Vec::new();
+        };
+        match outdated::run(&query, &step.dir, step.user.as_ref()) {
+            Ok(packages) => {
+                let held: Vec<String> = packages
+                    .into_iter()
+                    .map(|p| p.name)
+                    .filter(|name| excluded.contains(name))
+                    .collect();
+                if self.config.verbose && !held.is_empty() {
+                    say!(
+                        "{} {} already holds {}, leaving them held.",
+                        "INFO:".blue(),
+                        manager.label(),
+                        format_list(&held)
+                    );
+                }
+                held
+            }
+            Err(e) => {
+                eprintln!(
+                    "{} Failed to list the packages {} holds, releasing every excluded package after the upgrade: {e}",
+                    "WARN:".yellow(),
+                    manager.label()
+                );
+                Vec::new()
+            }
+        }
+    }
+
+    /// Releases the packages held for the upgrade, except those in `held_before`, even if it
+    /// failed, was stopped or ran out of time. Returns whether that succeeded.
+    fn release(
+        &self,
+        manager: &ManagerPlan,
+        prefix: Option<&str>,
+        timeouts: Timeouts,
+        held_before: &[String],
+    ) -> bool {
+        let timeouts = Timeouts {
+            manager: None,
+            ..timeouts
+        };
+        let mut released = true;
+        let steps: Vec<PlannedStep> = manager
+            .steps
+            .iter()
+            .filter(|s| s.kind == StepKind::Release)
+            .filter_map(|s| without_held(self.config, manager, s, held_before))
+            .collect();
+        for step in &steps {
+            if self.config.dry_run {
+                say!("Dry run: {step}");
+                continue;
+            }
+            self.emit(&Event::StepStarted {
+                manager: &manager.label(),
+                step,
+            });
+            let result = self.step(Some(manager), step, prefix, timeouts, Instant::now());
+            self.emit(&Event::StepFinished { result: &result });
+            if !result.success {
+                eprintln!(
+                    "{} {} could not release the packages it held for the upgrade. Release them with: {step}",
+                    "ERR:".red(),
+                    manager.label()
+                );
+                released = false;
+            }
+            self.results.lock().unwrap().push(result);
+        }
+        released
+    }
+
+    /// Runs a hook with `context` added to its environment. Hooks are never retried, and the
+    /// time limit of a package manager does not apply to them. Returns whether it succeeded.
+    /// `manager` is `None` for hooks of the whole run.
+    fn hook(
+        &self,
+        manager: Option<&ManagerPlan>,
+        hook: &PlannedStep,
+        prefix: Option<&str>,
+        context: BTreeMap<String, String>,
+    ) -> bool {
+        if self.config.dry_run {
+            say!("Dry run: {}: {hook}", hook.kind);
+            return true;
+        }
+        let mut hook = hook.clone();
+        hook.env.extend(context);
+        let timeouts = Timeouts {
+            manager: None,
+            ..self.config.timeouts(manager.map_or("", |m| &m.name))
+        };
+        self.emit(&Event::StepStarted {
+            manager: &manager.map(ManagerPlan::label).unwrap_or_default(),
+            step: &hook,
+        });
+        let result = self.step(manager, &hook, prefix, timeouts, Instant::now());
+        self.emit(&Event::StepFinished { result: &result });
+        let success = result.success;
+        self.results.lock().unwrap().push(result);
+        success
+    }
+}
+
+impl Run<'_, '_> {
+    /// Waits until no other program holds the locks of the package manager, up to
+    /// [`Config::lock_timeout`]. Fails right away if a lock was left behind.
+    fn wait_for_locks(&self, manager: &ManagerPlan) -> Result<(), String> {
+        let registry = self.config.registry();
+        let Some(pm) = registry.get(&manager.name) else {
+            return Ok(());
+        };
+        let started = Instant::now();
+        let mut waiting = false;
+        loop {
+            let mut held = None;
+            for lock in pm.locks() {
+                match lock::inspect(lock) {
+                    NativeState::Free => {}
+                    NativeState::Stale => {
+                        return Err(format!(
+                            "{} exists, but no process holds it. It was probably left behind by an \
+                             interrupted {}; if none is running, remove it (sudo rm {}) and try again.",
+                            lock.path, lock.program, lock.path
+                        ))
+                    }
+                    NativeState::Held(process) => {
+                        held = Some((lock, process));
+                        break;
+                    }
+                }
+            }
+            let Some((lock, process)) = held else {
+                return Ok(());
+            };
+            let process = process.map_or_else(|| "another program".to_string(), |p| p.to_string());
+            if started.elapsed() >= self.config.lock_timeout {
+                return Err(format!(
+                    "{} is held by {process}, gave up after {:.0}s (see --lock-timeout).",
+                    lock.path,
+                    started.elapsed().as_secs_f64()
+                ));
+            }
+            if !waiting {
+                waiting = true;
+                say!(
+                    "{} Waiting up to {}s for {} to release {}...",
+                    "INFO:".blue(),
+                    self.config.lock_timeout.as_secs(),
+                    process,
+                    lock.path
+                );
+            }
+            thread::sleep(LOCK_POLL_INTERVAL);
+        }
+    }
+
+    /// Asks the package manager for pending updates before its first upgrade `step`.
+    /// Returns true if there are none, i.e. the upgrade can be skipped.
+    fn up_to_date(&self, manager: &ManagerPlan, step: &PlannedStep) -> bool {
+        let label = manager.label();
+        let registry = self.config.registry();
+        let query = registry
+            .get(&manager.name)
+            .and_then(|pm| outdated::probe(pm, &manager.exe, self.config.auto, &step.dir));
+        let Some(query) = query else {
+            if self.config.verbose {
+                say!(
+                    "{} {} cannot list pending updates, upgrading anyway.",
+                    "INFO:".blue(),
+                    label
+                );
+            }
+            return false;
+        };
+        if self.config.dry_run {
+            say!(
+                "Dry run: {} {} (the upgrade is skipped if nothing is pending)",
+                query.step.program,
+                query.step.args.join(" ")
+            );
+            return false;
+        }
+        let packages = match outdated::run(&query, &step.dir, step.user.as_ref()) {
+            Ok(packages) => outdated::without_excluded(self.config, &manager.name, packages),
+            Err(e) => {
+                eprintln!(
+                    "{} Failed to list pending updates of {}, upgrading anyway: {e}",
+                    "WARN:".yellow(),
+                    label
+                );
+                return false;
+            }
+        };
+        if !packages.is_empty() {
+            if self.config.verbose {
+                say!(
+                    "{} {} has {} pending update(s).",
+                    "INFO:".blue(),
+                    label,
+                    packages.len()
+                );
+            }
+            return false;
+        }
+        say!(
+            "{} {} is up to date, skipping its upgrade.",
+            "INFO:".blue(),
+            label
+        );
+        self.emit(&Event::UpToDate { manager: &label });
+        self.up_to_date.lock().unwrap().push(label);
+        true
+    }
+
+    /// Runs one step, retrying it with exponential backoff if it is idempotent. `manager` is
+    /// `None` for hooks of the whole run.
+    fn step(
+        &self,
+        manager: Option<&ManagerPlan>,
+        step: &PlannedStep,
+        prefix: Option<&str>,
+        timeouts: Timeouts,
+        started: Instant,
+    ) -> StepResult {
+        let retries = if step.idempotent {
+            self.config.retries(manager.map_or("", |m| &m.name))
+        } else {
+            0
+        };
+        let label = manager.map(ManagerPlan::label).unwrap_or_default();
+        let mut attempt = 1;
+        loop {
+            let remaining = timeouts
+                .manager
+                .map(|t| t.saturating_sub(started.elapsed()));
+            let limits = Limits {
+                time: [timeouts.step, remaining].into_iter().flatten().min(),
+                stall: timeouts.stall,
+            };
+            let mut result = upd(&label, step, prefix, limits, self.config.capture_stderr);
+            result.attempts = attempt;
+            let retryable = result.reason != Some(FailureReason::Spawn);
+            if result.success || !retryable || attempt > retries || self.stopped() {
+                return result;
+            }
+            let delay = backoff(self.config.retry_delay, attempt);
+            if remaining.is_some_and(|r| r <= delay + result.duration) {
+                return result;
+            }
+            eprintln!(
+                "{} {} failed (attempt {} of {}), retrying in {:.0}s.",
+                "WARN:".yellow(),
+                step,
+                attempt,
+                retries + 1,
+                delay.as_secs_f64()
+            );
+            self.emit(&Event::StepRetrying {
+                result: &result,
+                delay: delay.as_secs_f64(),
+            });
+            thread::sleep(delay);
+            attempt += 1;
+        }
+    }
+}
+
+/// The wait before retry number `attempt`: `base`, doubled for every earlier retry.
+fn backoff(base: Duration, attempt: u32) -> Duration {
+    let factor = 2u32.saturating_pow(attempt.saturating_sub(1));
+    base.saturating_mul(factor).min(MAX_RETRY_DELAY)
+}
+
+/// Time limits of a single step.
+#[derive(Clone, Copy)]
+struct Limits {
+    time: Option<Duration>,
+    stall: Option<Duration>,
+}
+
+/// `step`, holding or releasing excluded packages of `manager`, without those in `held`. `None` if
+/// that leaves none of them.
+fn without_held(
+    config: &Config,
+    manager: &ManagerPlan,
+    step: &PlannedStep,
+    held: &[String],
+) -> Option<PlannedStep> {
+    if held.is_empty() {
+        return Some(step.clone());
+    }
+    let excluded = config.exclusions.get(&manager.name)?;
+    let mut step = step.clone();
+    step.args.retain(|arg| !held.contains(arg));
+    step.args
+        .iter()
+        .any(|arg| excluded.contains(arg))
+        .then_some(step)
+}
+
+/// How a step ended.
+struct Finished {
+    status: ExitStatus,
+    stderr_tail: Vec<String>,
+    /// Set if the step was stopped for exceeding a limit.
+    stopped: Option<FailureReason>,
+}
+
+/// Runs a step once. `tail` keeps the tail of its stderr.
+fn upd(
+    manager: &str,
+    step: &PlannedStep,
+    prefix: Option<&str>,
+    limits: Limits,
+    tail: bool,
+) -> StepResult {
+    let (succeeded, failed) = if step.kind.is_hook() {
+        (
+            format!("The {} succeeded", step.kind),
+            format!("The {} failed", step.kind),
+        )
+    } else {
+        (
+            format!("Successfully updated with {}", step.program),
+            format!("Failed to update with {}", step.program),
+        )
+    };
+    say!(
+        "{} Executing command: {step} in directory: {}",
+        "INFO:".blue(),
+        step.dir.display()
+    );
+    let start = Instant::now();
+    let finished = run(step, prefix, limits, tail);
+    let mut result = StepResult {
+        manager: manager.to_string(),
+        kind: step.kind,
+        program: step.program.clone(),
+        args: step.args.clone(),
+        success: false,
+        reason: None,
+        attempts: 1,
+        exit_code: None,
+        error: None,
+        duration: start.elapsed(),
+        stderr_tail: Vec::new(),
+    };
+    match finished {
+        Ok(Finished {
+            status,
+            stderr_tail,
+            stopped,
+        }) => {
+            result.success = status.success() && stopped.is_none();
+            result.exit_code = status.code();
+            result.stderr_tail = stderr_tail;
+            if let Some(reason) = stopped {
+                result.reason = Some(reason);
+                eprintln!(
+                    "{} {failed}, stopped after {:.0}s: {}",
+                    "ERR:".red(),
+                    result.duration.as_secs_f64(),
+                    match reason {
+                        FailureReason::Stalled => "no output",
+                        _ => "timed out",
+                    }
+                );
+            } else if status.success() {
+                say!(
+                    "{} {succeeded}, exited with status {}",
+                    "INFO:".blue(),
+                    status
+                );
+            } else {
+                result.reason = Some(FailureReason::Exit);
+                say!("{} {failed}, exited with status: {}", "ERR:".red(), status);
+            }
+        }
+        Err(e) => {
+            eprintln!("{} {failed}, error: {}", "ERR:".red(), e);
+            result.reason = Some(FailureReason::Spawn);
+            result.error = Some(e.to_string());
+        }
+    }
+    result
+}
+
+/// Runs a step to completion, stopping it if it exceeds `limits`.
+///
+/// In the JSON formats its output is forwarded to stderr, keeping stdout machine-readable. With a
+/// `prefix`, the step runs without a terminal and every line of its output is prefixed. Output is
+/// also piped to detect stalls, and stderr alone with `tail`. Returns the tail of its stderr if it
+/// was captured.
+fn run(
+    step: &PlannedStep,
+    prefix: Option<&str>,
+    limits: Limits,
+    tail: bool,
+) -> io::Result<Finished> {
+    let mut cmd = gen_upd_cmd(step);
+    let capture = is_machine() || prefix.is_some() || limits.stall.is_some();
+    if !capture && !tail && limits.time.is_none() {
+        return Ok(Finished {
+            status: cmd.status()?,
+            stderr_tail: Vec::new(),
+            stopped: None,
+        });
+    }
+    if prefix.is_some() {
+        cmd.stdin(Stdio::null());
+    }
+    // A step in its own process group can be stopped with everything it started, but must not
+    // read from the terminal, which belongs to our group. Otherwise its descendants are looked up
+    // when it is stopped, see `terminate`.
+    let own_group = prefix.is_some() || !io::stdin().is_terminal();
+    #[cfg(not(target_os = "windows"))]
+    if own_group {
+        use std::os::unix::process::CommandExt;
+        cmd.process_group(0);
+    }
+    if capture {
+        cmd.stdout(Stdio::piped()).stderr(Stdio::piped());
+    } else if tail {
+        cmd.stderr(Stdio::piped());
+    }
+    let mut child = cmd.spawn()?;
+    let last_output = Arc::new(Mutex::new(Instant::now()));
+    let prefix = prefix.unwrap_or_default().to_string();
+    let stdout = child.stdout.take().map(|out| {
+        let (prefix, last_output) = (prefix.clone(), Arc::clone(&last_output));
+        thread::spawn(move || forward(out, !is_machine(), &prefix, false, &last_output))
+    });
+    let stderr = child.stderr.take().map(|err| {
+        let last_output = Arc::clone(&last_output);
+        thread::spawn(move || forward(err, false, &prefix, true, &last_output))
+    });
+    let (status, stopped) = supervise(&mut child, own_group, limits, &last_output)?;
+    // Processes left behind by a stopped step may hold its output open.
+    let join = stopped.is_none() || own_group;
+    if let Some(stdout) = stdout.filter(|_| join) {
+        let _ = stdout.join();
+    }
+    let stderr_tail = stderr
+        .filter(|_| join)
+        .and_then(|handle| handle.join().ok())
+        .map(|bytes| tail_lines(&bytes))
+        .unwrap_or_default();
+    Ok(Finished {
+        status,
+        stderr_tail,
+        stopped,
+    })
+}
+
+/// Waits for a step, stopping it once it exceeds a limit.
+fn supervise(
+    child: &mut Child,
+    group: bool,
+    limits: Limits,
+    last_output: &Mutex<Instant>,
+) -> io::Result<(ExitStatus, Option<FailureReason>)> {
+    let start = Instant::now();
+    loop {
+        if let Some(status) = child.try_wait()? {
+            return Ok((status, None));
+        }
+        let stopped = if limits.time.is_some_and(|limit| start.elapsed() >= limit) {
+            Some(FailureReason::Timeout)
+        } else if limits
+            .stall
+            .is_some_and(|limit| last_output.lock().unwrap().elapsed() >= limit)
+        {
+            Some(FailureReason::Stalled)
+        } else {
+            None
+        };
+        if stopped.is_some() {
+            return Ok((terminate(child, group)?, stopped));
+        }
+        thread::sleep(POLL_INTERVAL);
+    }
+}
+
+/// Asks a step to stop and kills it if it is still running after [`KILL_GRACE`].
+/// With `group`, everything in the step's process group is signalled. Otherwise the step's
+/// descendants are signalled one by one, which only Linux can list; elsewhere they keep running.
+fn terminate(child: &mut Child, group: bool) -> io::Result<ExitStatus> {
+    #[cfg(not(target_os = "windows"))]
+    {
+        // Processes whose parent exits are adopted by init, so the tree is remembered.
+        let mut tree = if group {
+            Vec::new()
+        } else {
+            platform::descendants(child.id())
+        };
+        platform::signal(child.id(), group, libc::SIGTERM);
+        platform::signal_all(&tree, libc::SIGTERM);
+        let mut status = None;
+        let start = Instant::now();
+        while start.elapsed() < KILL_GRACE {
+            if status.is_none() {
+                status = child.try_wait()?;
+            }
+            if status.is_some() && !platform::any_alive(&tree) {
+                break;
+            }
+            thread::sleep(POLL_INTERVAL);
+        }
+        if status.is_none() {
+            if !group {
+                tree.extend(platform::descendants(child.id()));
+            }
+            platform::signal(child.id(), group, libc::SIGKILL);
+        }
+        platform::signal_all(&tree, libc::SIGKILL);
+        if let Some(status) = status {
+            return Ok(status);
+        }
+    }
+    #[cfg(target_os = "windows")]
+    {
+        let _ = group;
+        child.kill()?;
+    }
+    child.wait()
+}
+
+/// Copies a stream of the child to our stdout or stderr, prefixing every line unless `prefix`
+/// is empty, and records when it last produced output. Returns the last bytes read if `keep`
+/// is set.
+fn forward(
+    reader: impl Read,
+    to_stdout: bool,
+    prefix: &str,
+    keep: bool,
+    last_output: &Mutex<Instant>,
+) -> Vec<u8> {
+    const KEEP_BYTES: usize = 16 * 1024;
+    let mut reader = BufReader::new(reader);
+    let mut kept = Vec::new();
+    let mut line = Vec::new();
+    loop {
+        line.clear();
+        // Without a prefix, forward whatever is available so progress output stays live.
+        let read = if prefix.is_empty() {
+            reader.fill_buf().map(|buf| {
+                line.extend_from_slice(buf);
+                buf.len()
+            })
+        } else {
+            reader.read_until(b'\n', &mut line)
+        };
+        match read {
+            Ok(0) | Err(_) => break,
+            Ok(n) => {
+                if prefix.is_empty() {
+                    reader.consume(n);
+                }
+            }
+        }
+        *last_output.lock().unwrap() = Instant::now();
+        let mut out = Vec::with_capacity(prefix.len() + line.len());
+        out.extend_from_slice(prefix.as_bytes());
+        out.extend_from_slice(&line);
+        if !prefix.is_empty() && !out.ends_with(b"\n") {
+            out.push(b'\n');
+        }
+        let _ = if to_stdout {
+            io::stdout().write_all(&out)
+        } else {
+            io::stderr().write_all(&out)
+        };
+        if keep {
+            kept.extend_from_slice(&line);
+            if kept.len() > KEEP_BYTES {
+                kept.drain(..kept.len() - KEEP_BYTES);
+            }
+        }
+    }
+    kept
+}
+
+fn tail_lines(bytes: &[u8]) -> Vec<String> {
+    let text = String::from_utf8_lossy(bytes);
+    let lines: Vec<&str> = text.lines().filter(|l| !l.trim().is_empty()).collect();
+    lines[lines.len().saturating_sub(TAIL_LINES)..]
+        .iter()
+        .map(ToString::to_string)
+        .collect()
+}
+
+/// Builds the process for a planned step, with its account, working directory and environment.
+#[must_use]
+pub fn gen_upd_cmd(step: &PlannedStep) -> Command {
+    let mut cmd = match step.escalation {
+        Some(escalation) => escalation.command(&step.program, &step.args),
+        None => {
+            let mut cmd = Command::new(&step.program);
+            cmd.args(&step.args);
+            cmd
+        }
+    };
+    if let Some(user) = &step.user {
+        user.apply(&mut cmd);
+    }
+    cmd.current_dir(&step.dir)
+        .envs(&step.env)
+        .stdout(Stdio::inherit())
+        .stderr(Stdio::inherit())
+        .stdin(Stdio::inherit());
+    cmd
+}
+
+#[cfg(not(target_os = "windows"))]
+mod platform {
+    #[cfg(target_os = "linux")]
+    use std::fs;
+    use std::os::raw::c_int;
+
+    /// Sends `signal` to the process `pid`, or to its whole process group.
+    pub fn signal(pid: u32, group: bool, signal: c_int) {
+        let Ok(pid) = libc::pid_t::try_from(pid) else {
+            return;
+        };
+        unsafe {
+            libc::kill(if group { -pid } else { pid }, signal);
+        }
+    }
+
+    /// A process, identified by its PID and start time so that a reused PID is not signalled.
+    pub type Process = (u32, u64);
+
+    /// The processes `pid` started, directly or not.
+    #[cfg(target_os = "linux")]
+    pub fn descendants(pid: u32) -> Vec<Process> {
+        let processes: Vec<(u32, u32, u64)> = fs::read_dir("/proc")
+            .into_iter()
+            .flatten()
+            .filter_map(|entry| {
+                let pid = entry.ok()?.file_name().to_str()?.parse().ok()?;
+                let (parent, start) = stat(pid)?;
+                Some((pid, parent, start))
+            })
+            .collect();
+        let mut found = Vec::new();
+        let mut parents = vec![pid];
+        while let Some(parent) = parents.pop() {
+            for &(pid, ppid, start) in &processes {
+                if ppid == parent {
+                    found.push((pid, start));
+                    parents.push(pid);
+                }
+            }
+        }
+        found
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    pub fn descendants(_pid: u32) -> Vec<Process> {
+        Vec::new()
+    }
+
+    /// The parent and start time of `pid`, from `/proc/<pid>/stat`. `None` once it has exited.
+    #[cfg(target_os = "linux")]
+    fn stat(pid: u32) -> Option<(u32, u64)> {
+        let stat = fs::read_to_string(format!("/proc/{pid}/stat")).ok()?;
+        // The command name may contain spaces and parentheses, the fields after it do not.
+        let fields: Vec<&str> = stat.rsplit_once(')')?.1.split_whitespace().collect();
+        if fields.first() == Some(&"Z") {
+            return None;
+        }
+        Some((fields.get(1)?.parse().ok()?, fields.get(19)?.parse().ok()?))
+    }
+
+    /// Whether `process` is still running.
+    #[cfg(target_os = "linux")]
+    fn alive(&(pid, start): &Process) -> bool {
+        stat(pid).is_some_and(|(_, started)| started == start)
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    fn alive(_process: &Process) -> bool {
+        true
+    }
+
+    /// Whether any of `processes` is still running.
+    pub fn any_alive(processes: &[Process]) -> bool {
+        processes.iter().any(alive)
+    }
+
+    /// Sends `signal` to each of `processes` that is still running.
+    pub fn signal_all(processes: &[Process], signal: c_int) {
+        for process in processes.iter().filter(|process| alive(process)) {
+            self::signal(process.0, false, signal);
+        }
+    }
+}
@@ -1,8 +1,8 @@
 use std::{env, fs};
 use std::collections::{HashMap, HashSet};
 use std::path::{Path, PathBuf};
-use walkdir::WalkDir;
 use crate::conf::OrdMode;
+use crate::say;
 
 #[cfg(target_family = "windows")]
 fn is_executable(path: &Path) -> bool {
@@ -19,39 +19,78 @@ fn is_executable(path: &Path) -> bool {
         .unwrap_or(false)
 }
 
-/// Recursively searches the directories in PATH for executables matching any name in `target_filenames`.
-#[must_use]
-pub fn find_matching_executables(target_filenames: &[&str]) -> Vec<String> {
-    let mut executables = Vec::new();
+/// The file names an executable called `name` may have.
+#[cfg(target_family = "windows")]
+fn executable_names(name: &str) -> Vec<String> {
+    let mut names: Vec<String> = ["exe", "bat", "cmd", "com"]
+        .iter()
+        .map(|ext| format!("{name}.{ext}"))
+        .collect();
+    names.push(name.to_string());
+    names
+}
 
-    if let Ok(paths) = env::var("PATH") {
-        for path in env::split_paths(&paths) {
-            if path.exists() {
-                for entry in WalkDir::new(path).into_iter().flatten() {
-                    let entry_path = entry.path();
-                    if entry_path.is_file() && is_executable(entry_path) {
-                        if let Some(file_name) = entry_path.file_name().and_then(|s| s.to_str()) {
-                            if target_filenames.contains(&file_name)
-                                && !executables.contains(&entry_path.display().to_string())
-                            {
-                                executables.push(entry_path.display().to_string());
-                            }
-                        }
-                    }
+#[cfg(not(target_family = "windows"))]
+fn executable_names(name: &str) -> Vec<String> {
+    vec![name.to_string()]
+}
+
+/// Searches the directories in PATH, in order, for executables named exactly like one of `names`
+/// (on Windows also with an executable extension). Subdirectories are not searched.
+/// Returns the name each executable was found for, and its path.
+#[must_use]
+pub fn find_matching_executables(names: &[&str]) -> Vec<(String, PathBuf)> {
+    let mut executables: Vec<(String, PathBuf)> = Vec::new();
+    let Some(paths) = env::var_os("PATH") else {
+        return executables;
+    };
+    for dir in env::split_paths(&paths) {
+        for name in names {
+            let found = executable_names(name)
+                .into_iter()
+                .map(|file_name| dir.join(file_name))
+                .find(|candidate| is_executable(candidate));
+            if let Some(path) = found {
+                if !executables.iter().any(|(_, p)| *p == path) {
+                    executables.push((name.to_string(), path));
                 }
             }
         }
     }
-
     executables
 }
 
+/// Returns the first executable named `name` in PATH, or `name` itself if it is a path.
+#[must_use]
+pub fn find_in_path(name: &str) -> Option<PathBuf> {
+    let path = Path::new(name);
+    if path.components().count() > 1 {
+        return is_executable(path).then(|| path.to_path_buf());
+    }
+    find_in_dirs(name, env::split_paths(&env::var_os("PATH")?))
+}
+
+/// Returns the first executable named `name` in `dirs`.
+pub fn find_in_dirs(name: &str, dirs: impl IntoIterator<Item = PathBuf>) -> Option<PathBuf> {
+    dirs.into_iter()
+        .flat_map(|dir| {
+            executable_names(name)
+                .into_iter()
+                .map(move |file_name| dir.join(file_name))
+        })
+        .find(|candidate| is_executable(candidate))
+}
+
 /// Reorders the list of candidate package managers based on the provided ordering mode.
-pub fn reorder_candidates(candidates: Vec<PathBuf>, ord_mode: &OrdMode, verbose: bool) -> Vec<PathBuf> {
+pub fn reorder_candidates(
+    candidates: Vec<(String, PathBuf)>,
+    ord_mode: &OrdMode,
+    verbose: bool,
+) -> Vec<(String, PathBuf)> {
     match ord_mode {
         OrdMode::Specified(order_vec) => {
             if verbose {
-                println!("Reordering package managers using specified order: {order_vec:?}");
+                say!("Reordering package managers using specified order: {order_vec:?}");
             }
             // Map each package manager name to its order index.
             let order_map: HashMap<String, usize> = order_vec
@@ -59,10 +98,9 @@ pub fn reorder_candidates(candidates: Vec<PathBuf>, ord_mode: &OrdMode, verbose:
                 .enumerate()
                 .map(|(i, pm)| (pm.to_string(), i))
                 .collect();
-            let mut enumerated: Vec<(usize, PathBuf)> =
+            let mut enumerated: Vec<(usize, (String, PathBuf))> =
                 candidates.into_iter().enumerate().collect();
-            enumerated.sort_by_key(|(orig_index, candidate)| {
-                let pm_name = candidate.file_name().and_then(|s| s.to_str()).unwrap_or("");
+            enumerated.sort_by_key(|(orig_index, (pm_name, _))| {
                 if let Some(&order_index) = order_map.get(pm_name) {
                     (0, order_index)
                 } else {
@@ -75,14 +113,12 @@ pub fn reorder_candidates(candidates: Vec<PathBuf>, ord_mode: &OrdMode, verbose:
                 .collect()
         }
         OrdMode::Interactive => {
-            println!("Interactive ordering mode enabled.");
-            println!("Detected package managers:");
-            for (i, candidate) in candidates.iter().enumerate() {
-                if let Some(pm_name) = candidate.file_name().and_then(|s| s.to_str()) {
-                    println!("  {i}: {pm_name}");
-                }
+            say!("Interactive ordering mode enabled.");
+            say!("Detected package managers:");
+            for (i, (pm_name, _)) in candidates.iter().enumerate() {
+                say!("  {i}: {pm_name}");
             }
-            println!("Enter the desired update order as comma-separated indices (e.g. 2,0,1) or press Enter to keep the current order:");
+            say!("Enter the desired update order as comma-separated indices (e.g. 2,0,1) or press Enter to keep the current order:");
             let mut input = String::new();
             std::io::stdin()
                 .read_line(&mut input)
@@ -90,7 +126,7 @@ pub fn reorder_candidates(candidates: Vec<PathBuf>, ord_mode: &OrdMode, verbose:
             let input = input.trim();
             if input.is_empty() {
                 if verbose {
-                    println!("No input provided, keeping original order.");
+                    say!("No input provided, keeping original order.");
                 }
                 return candidates;
             }
@@ -99,7 +135,7 @@ pub fn reorder_candidates(candidates: Vec<PathBuf>, ord_mode: &OrdMode, verbose:
                 .filter_map(|s| s.trim().parse::<usize>().ok())
                 .collect();
             if verbose {
-                println!("Specified indices: {indices:?}");
+                say!("Specified indices: {indices:?}");
             }
             let mut ordered = Vec::new();
             let mut selected_indices = HashSet::new();
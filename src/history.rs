@@ -0,0 +1,385 @@
+//! The run history.
+//!
+//! Every update run is recorded as one JSON file under `$XDG_STATE_HOME/qud/history`
+//! (`~/.local/state/qud/history` by default, `%LOCALAPPDATA%\qud\history` on Windows), named after
+//! the time it started.
+
+use crate::conf::Config;
+use crate::exec::StepResult;
+use crate::plan::UpdatePlan;
+use crate::say;
+use crate::summary::{Status, Summary};
+use colored::Colorize;
+use serde::{Deserialize, Serialize};
+use std::env;
+use std::error::Error;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Everything recorded about one run.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RunRecord {
+    /// The name of the record, e.g. `20261016-120000`.
+    pub id: String,
+    pub qud_version: String,
+    /// Seconds since the Unix epoch.
+    pub started: u64,
+    /// Seconds since the Unix epoch.
+    pub finished: u64,
+    /// The command line qud was started with.
+    pub args: Vec<String>,
+    /// The effective configuration.
+    pub config: serde_json::Value,
+    pub plan: UpdatePlan,
+    pub results: Vec<StepResult>,
+    pub summary: Summary,
+    pub exit_code: i32,
+}
+
+/// Which runs to list.
+#[derive(Debug, Clone, Default)]
+pub struct Filter {
+    /// Only runs that updated this package manager.
+    pub manager: Option<String>,
+    /// Only runs where something failed (with `manager`, where that package manager failed).
+    pub failed: bool,
+    /// At most this many of the most recent runs.
+    pub limit: Option<usize>,
+}
+
+impl Filter {
+    fn matches(&self, run: &RunRecord) -> bool {
+        let managers = run
+            .summary
+            .managers
+            .iter()
+            .filter(|m| self.manager.as_ref().is_none_or(|pm| names(&m.name, pm)));
+        let mut ran = managers.filter(|m| m.status != Status::Skipped);
+        match (&self.manager, self.failed) {
+            (None, false) => true,
+            (None, true) => run.exit_code != 0,
+            (Some(_), false) => ran.next().is_some(),
+            (Some(_), true) => ran.any(|m| m.status == Status::Failed),
+        }
+    }
+}
+
+/// Whether the summary `label` is that of `pm`, for any user with `--all-users` unless `pm` names
+/// one (`rustup@alice`).
+fn names(label: &str, pm: &str) -> bool {
+    label == pm
+        || label
+            .strip_prefix(pm)
+            .is_some_and(|user| user.starts_with('@'))
+}
+
+/// Seconds since the Unix epoch.
+#[must_use]
+pub fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs())
+}
+
+/// The directory qud keeps its state in: `$XDG_STATE_HOME/qud`, `~/.local/state/qud` or
+/// `%LOCALAPPDATA%\qud`.
+#[must_use]
+pub fn state_dir() -> Option<PathBuf> {
+    #[cfg(not(target_os = "windows"))]
+    let state = env::var_os("XDG_STATE_HOME")
+        .filter(|v| !v.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| Some(PathBuf::from(env::var_os("HOME")?).join(".local/state")));
+    #[cfg(target_os = "windows")]
+    let state = env::var_os("LOCALAPPDATA").map(PathBuf::from);
+    Some(state?.join("qud"))
+}
+
+/// The directory runs are recorded in.
+#[must_use]
+pub fn dir() -> Option<PathBuf> {
+    Some(state_dir()?.join("history"))
+}
+
+impl RunRecord {
+    /// Records a finished run that started at `started`.
+    #[must_use]
+    pub fn new(
+        started: u64,
+        config: &Config,
+        plan: &UpdatePlan,
+        results: Vec<StepResult>,
+        summary: Summary,
+        exit_code: i32,
+    ) -> RunRecord {
+        RunRecord {
+            id: timestamp(started, "", "-", ""),
+            qud_version: env!("CARGO_PKG_VERSION").to_string(),
+            started,
+            finished: now(),
+            args: env::args().collect(),
+            config: serde_json::to_value(config).unwrap_or_default(),
+            plan: plan.clone(),
+            results,
+            summary,
+            exit_code,
+        }
+    }
+
+    /// Writes the record to the history directory. A suffix keeps runs started in the same
+    /// second apart.
+    ///
+    /// # Errors
+    /// Returns an error if there is no home directory or the file cannot be written.
+    pub fn save(&mut self) -> io::Result<PathBuf> {
+        let dir =
+            dir().ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no home directory"))?;
+        fs::create_dir_all(&dir)?;
+        let base = self.id.clone();
+        for n in 1.. {
+            if n > 1 {
+                self.id = format!("{base}-{n}");
+            }
+            let path = dir.join(format!("{}.json", self.id));
+            let file = fs::OpenOptions::new()
+                .write(true)
+                .create_new(true)
+                .open(&path);
+            match file {
+                Ok(file) => {
+                    serde_json::to_writer_pretty(file, self)?;
+                    return Ok(path);
+                }
+                Err(e) if e.kind() == io::ErrorKind::AlreadyExists => {}
+                Err(e) => return Err(e),
+            }
+        }
+        unreachable!()
+    }
+}
+
+/// Reads the recorded runs matching `filter`, most recent first. Unreadable records are skipped
+/// with a warning.
+///
+/// # Errors
+/// Returns an error if the history directory cannot be read.
+pub fn list(filter: &Filter) -> io::Result<Vec<RunRecord>> {
+    let Some(dir) = dir().filter(|d| d.is_dir()) else {
+        return Ok(Vec::new());
+    };
+    let mut paths: Vec<PathBuf> = fs::read_dir(dir)?
+        .filter_map(|entry| Some(entry.ok()?.path()))
+        .filter(|path| path.extension().is_some_and(|ext| ext == "json"))
+        .collect();
+    // Runs started in the same second are numbered from 2.
+    paths.sort_by_cached_key(|path| {
+        let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+        let (base, n) = stem.split_at(stem.len().min(15));
+        (
+            base.to_string(),
+            n.trim_start_matches('-').parse().unwrap_or(1u32),
+        )
+    });
+    let mut runs = Vec::new();
+    for path in paths.iter().rev() {
+        if filter.limit.is_some_and(|limit| runs.len() >= limit) {
+            break;
+        }
+        match read(path) {
+            Ok(run) if filter.matches(&run) => runs.push(run),
+            Ok(_) => {}
+            Err(e) => eprintln!(
+                "{} Skipping unreadable run record {}: {e}",
+                "WARN:".yellow(),
+                path.display()
+            ),
+        }
+    }
+    Ok(runs)
+}
+
+/// Reads the run named `id`.
+///
+/// # Errors
+/// Returns an error if there is no such run or its record cannot be read.
+pub fn find(id: &str) -> Result<RunRecord, Box<dyn Error>> {
+    let dir = dir().ok_or("no home directory")?;
+    let path = dir.join(format!("{id}.json"));
+    if !path.is_file() {
+        return Err(format!("no run {id} in {}", dir.display()).into());
+    }
+    read(&path)
+}
+
+fn read(path: &std::path::Path) -> Result<RunRecord, Box<dyn Error>> {
+    Ok(serde_json::from_str(&fs::read_to_string(path)?)?)
+}
+
+/// Prints runs as a table.
+pub fn print_list(runs: &[RunRecord]) {
+    if runs.is_empty() {
+        say!("{} No runs recorded.", "INFO:".blue());
+        return;
+    }
+    let width = runs.iter().map(|r| r.id.len()).max().unwrap_or(0);
+    say!(
+        "{}",
+        format!(
+            "  {:width$}  {:19}  {:>8}  {:4}  MANAGERS",
+            "ID", "STARTED (UTC)", "DURATION", "EXIT"
+        )
+        .bold()
+    );
+    for run in runs {
+        let managers: Vec<String> = run
+            .summary
+            .managers
+            .iter()
+            .filter(|m| m.status != Status::Skipped)
+            .map(|m| match m.status {
+                Status::Succeeded => m.name.clone(),
+                Status::Failed => format!("{} ({})", m.name, "failed".red()),
+                _ => format!("{} ({})", m.name, status_text(m.status)),
+            })
+            .collect();
+        say!(
+            "  {:width$}  {:19}  {:>7.0}s  {:4}  {}",
+            run.id,
+            timestamp(run.started, "-", " ", ":"),
+            run.summary.elapsed.as_secs_f64(),
+            run.exit_code,
+            managers.join(", ")
+        );
+    }
+}
+
+/// Prints every detail of one run.
+pub fn print_run(run: &RunRecord) {
+    say!("{} {}", "Run".bold(), run.id);
+    say!("  Started:  {} UTC", timestamp(run.started, "-", " ", ":"));
+    say!("  Finished: {} UTC", timestamp(run.finished, "-", " ", ":"));
+    say!("  qud:      {}", run.qud_version);
+    say!("  Command:  {}", run.args.join(" "));
+    say!("  Exit:     {}", run.exit_code);
+    for manager in &run.summary.managers {
+        if manager.status == Status::Skipped {
+            continue;
+        }
+        say!(
+            "{} {} ({}, {:.1}s)",
+            "Manager".bold(),
+            manager.name,
+            status_text(manager.status),
+            manager.elapsed.as_secs_f64()
+        );
+        for result in run.results.iter().filter(|r| r.manager == manager.name) {
+            print_result(result);
+        }
+    }
+    let hooks: Vec<&StepResult> = run
+        .results
+        .iter()
+        .filter(|r| r.manager.is_empty())
+        .collect();
+    if !hooks.is_empty() {
+        say!("{}", "Hooks".bold());
+        for result in hooks {
+            print_result(result);
+        }
+    }
+    if let Some(snapshot) = &run.summary.snapshot {
+        match &snapshot.error {
+            Some(e) => say!("{} {} failed: {e}", "Snapshot".bold(), snapshot.tool),
+            None => say!(
+                "{} {} {}",
+                "Snapshot".bold(),
+                snapshot.tool,
+                snapshot.id.as_deref().unwrap_or("(id unknown)")
+            ),
+        }
+    }
+}
+
+fn print_result(result: &StepResult) {
+    let outcome = match (result.success, result.exit_code, &result.error) {
+        (true, _, _) => "ok".green(),
+        (false, _, Some(e)) => e.red(),
+        (false, Some(code), None) => format!("exit {code}").red(),
+        (false, None, None) => format!("{:?}", result.reason).to_lowercase().red(),
+    };
+    say!(
+        "  {} {}  [{}, {:.1}s{}]",
+        result.program,
+        result.args.join(" "),
+        outcome,
+        result.duration.as_secs_f64(),
+        if result.attempts > 1 {
+            format!(", {} attempts", result.attempts)
+        } else {
+            String::new()
+        }
+    );
+    for line in &result.stderr_tail {
+        say!("    | {line}");
+    }
+}
+
+fn status_text(status: Status) -> &'static str {
+    match status {
+        Status::Succeeded => "succeeded",
+        Status::Failed => "failed",
+        Status::UpToDate => "up to date",
+        Status::Skipped => "skipped",
+        Status::Vetoed => "vetoed",
+        Status::NotApplicable => "not applicable",
+    }
+}
+
+/// Formats `secs` since the Unix epoch as a UTC date and time, with `date` between the parts of
+/// the date, `sep` before the time and `time` between its parts.
+pub(crate) fn timestamp(secs: u64, date: &str, sep: &str, time: &str) -> String {
+    let days = secs / 86_400;
+    let rem = secs % 86_400;
+    // Howard Hinnant's civil_from_days, for days since 1970-01-01.
+    let z = days + 719_468;
+    let era = z / 146_097;
+    let doe = z - era * 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + u64::from(month <= 2);
+    format!(
+        "{year:04}{date}{month:02}{date}{day:02}{sep}{:02}{time}{:02}{time}{:02}",
+        rem / 3600,
+        rem % 3600 / 60,
+        rem % 60
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn timestamp_formats() {
+        assert_eq!(timestamp(0, "", "-", ""), "19700101-000000");
+        assert_eq!(
+            timestamp(1_735_689_599, "-", " ", ":"),
+            "2024-12-31 23:59:59"
+        );
+    }
+
+    #[test]
+    fn timestamp_leap_days() {
+        let day = |secs| timestamp(secs, "-", " ", ":");
+        assert_eq!(day(951_782_400), "2000-02-29 00:00:00");
+        assert_eq!(day(1_709_164_800), "2024-02-29 00:00:00");
+        // 2100 is not a leap year.
+        assert_eq!(day(4_107_542_399), "2100-02-28 23:59:59");
+        assert_eq!(day(4_107_542_400), "2100-03-01 00:00:00");
+    }
+}
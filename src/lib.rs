@@ -0,0 +1,30 @@
+//! qud detects the package managers installed on a machine and updates them.
+//!
+//! The `qud` binary is a thin command-line front end; everything else is available here for
+//! embedding: build a [`Config`], [`detect`](detect::detect) package managers, select
+//! [`candidates`](plan::candidates), plan an [`UpdatePlan`](plan::UpdatePlan) and run it with
+//! [`exec::execute`].
+
+#![allow(clippy::doc_markdown)]
+
+pub mod account;
+pub mod conf;
+mod conf_file;
+pub mod detect;
+pub mod escalation;
+pub mod exec;
+pub mod helpers;
+pub mod history;
+pub mod lock;
+pub mod managers;
+pub mod outdated;
+pub mod output;
+pub mod plan;
+pub mod plan_file;
+pub mod self_up;
+pub mod snapshot;
+pub mod summary;
+
+pub use conf::{Config, ConfigBuilder, FailurePolicy, Hooks, OrdMode, Timeouts};
+pub use conf_file::ConfigError;
+pub use managers::{PackageManager, Registry};
@@ -0,0 +1,357 @@
+//! Keeps qud runs from updating at the same time.
+//!
+//! A run that updates system packages takes a lock shared by all users of the machine (in
+//! `/run/lock`), one that only updates user-level package managers a lock of its user. The lock is
+//! an advisory file lock released by the operating system when qud exits, so it never goes stale.
+//! Its holder writes its pid, start time and command line into the file for whoever has to wait.
+//!
+//! The locks of the package managers themselves ([`NativeLock`]) are only inspected, so that qud
+//! can wait for another program holding them and name it. That needs `/proc`, so it only works on
+//! Linux.
+
+use crate::history;
+use crate::managers::{LockKind, NativeLock};
+use crate::plan::UpdatePlan;
+use crate::say;
+use colored::Colorize;
+use serde::{Deserialize, Serialize};
+use std::env;
+use std::fmt;
+use std::fs::{self, File, OpenOptions, TryLockError};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+/// Which runs exclude each other.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Scope {
+    /// All runs on the machine that update system packages.
+    System,
+    /// All runs of the current user.
+    User,
+}
+
+impl Scope {
+    /// [`Scope::System`] if `plan` needs privileges, updates system packages or updates for
+    /// other users.
+    #[must_use]
+    pub fn of(plan: &UpdatePlan) -> Scope {
+        let system = plan
+            .managers
+            .iter()
+            .any(|m| m.snapshot || m.user.is_some() || m.steps.iter().any(|s| s.privileged));
+        if system {
+            Scope::System
+        } else {
+            Scope::User
+        }
+    }
+
+    /// The lock file of the scope.
+    #[must_use]
+    pub fn path(self) -> Option<PathBuf> {
+        let dir = match self {
+            #[cfg(not(target_os = "windows"))]
+            Scope::System => ["/run/lock", "/var/lock"]
+                .iter()
+                .map(PathBuf::from)
+                .find(|dir| dir.is_dir())
+                .unwrap_or_else(env::temp_dir),
+            #[cfg(target_os = "windows")]
+            Scope::System => PathBuf::from(env::var_os("ProgramData")?).join("qud"),
+            #[cfg(not(target_os = "windows"))]
+            Scope::User => env::var_os("XDG_RUNTIME_DIR")
+                .filter(|v| !v.is_empty())
+                .map(PathBuf::from)
+                .or_else(history::state_dir)?,
+            #[cfg(target_os = "windows")]
+            Scope::User => history::state_dir()?,
+        };
+        Some(dir.join("qud.lock"))
+    }
+}
+
+/// The run holding a lock.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Holder {
+    pub pid: u32,
+    /// Seconds since the Unix epoch.
+    pub started: u64,
+    pub args: Vec<String>,
+}
+
+impl fmt::Display for Holder {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "pid {}, started {} UTC: {}",
+            self.pid,
+            history::timestamp(self.started, "-", " ", ":"),
+            self.args.join(" ")
+        )
+    }
+}
+
+/// A held lock, released when dropped or when qud exits.
+#[derive(Debug)]
+pub struct Lock {
+    pub path: PathBuf,
+    _file: File,
+}
+
+impl Lock {
+    /// Takes the lock of `scope`. If another run holds it, waits for that run to finish if `wait`
+    /// is set and fails otherwise.
+    ///
+    /// # Errors
+    /// Returns a message naming the holder if the lock is taken and `wait` is not set, or if the
+    /// lock file cannot be opened.
+    pub fn acquire(scope: Scope, wait: bool) -> Result<Lock, String> {
+        let path = scope
+            .path()
+            .ok_or("Cannot take the qud lock: no directory for the lock file")?;
+        let (file, writable) =
+            open(&path).map_err(|e| format!("Cannot take the qud lock {}: {e}", path.display()))?;
+        match file.try_lock() {
+            Ok(()) => {}
+            Err(TryLockError::WouldBlock) => {
+                let holder = match holder(&path) {
+                    Some(holder) => format!(" ({holder})"),
+                    None => String::new(),
+                };
+                if !wait {
+                    return Err(format!(
+                        "Another qud run holds {}{holder}. Use --wait to wait until it finishes.",
+                        path.display()
+                    ));
+                }
+                say!(
+                    "{} Waiting for another qud run to release {}{holder}...",
+                    "INFO:".blue(),
+                    path.display()
+                );
+                file.lock()
+                    .map_err(|e| format!("Cannot take the qud lock {}: {e}", path.display()))?;
+            }
+            Err(TryLockError::Error(e)) => {
+                return Err(format!("Cannot take the qud lock {}: {e}", path.display()))
+            }
+        }
+        if writable {
+            // Only for the next run to show; the lock works without it.
+            let _ = record(&file);
+        }
+        Ok(Lock { path, _file: file })
+    }
+}
+
+/// Opens the lock file, creating it so that every user can lock it. Files created by another user
+/// may only be readable, which is enough to lock them but not to record the holder.
+fn open(path: &Path) -> io::Result<(File, bool)> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    match OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .open(path)
+    {
+        Ok(file) => {
+            #[cfg(unix)]
+            {
+                use std::os::unix::fs::PermissionsExt;
+                // Fails unless this user created the file.
+                let _ = file.set_permissions(fs::Permissions::from_mode(0o666));
+            }
+            Ok((file, true))
+        }
+        Err(e) if e.kind() == io::ErrorKind::PermissionDenied => Ok((File::open(path)?, false)),
+        Err(e) => Err(e),
+    }
+}
+
+fn record(mut file: &File) -> io::Result<()> {
+    let holder = Holder {
+        pid: std::process::id(),
+        started: history::now(),
+        args: env::args().collect(),
+    };
+    file.set_len(0)?;
+    file.write_all(serde_json::to_string(&holder)?.as_bytes())
+}
+
+/// The run holding the lock at `path`, if it recorded itself.
+#[must_use]
+pub fn holder(path: &Path) -> Option<Holder> {
+    serde_json::from_str(&fs::read_to_string(path).ok()?).ok()
+}
+
+/// The state of a package manager's lock.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum NativeState {
+    Free,
+    /// Held by a running process, if it could be identified.
+    Held(Option<Process>),
+    /// The lock file exists, but no process holds it: it was left behind by a package manager that
+    /// crashed or was killed.
+    Stale,
+}
+
+/// A running process.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Process {
+    pub pid: u32,
+    pub command: String,
+}
+
+impl fmt::Display for Process {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.command.is_empty() {
+            write!(f, "pid {}", self.pid)
+        } else {
+            write!(f, "pid {} ({})", self.pid, self.command)
+        }
+    }
+}
+
+/// Finds out whether `lock` is held and by whom.
+#[must_use]
+pub fn inspect(lock: &NativeLock) -> NativeState {
+    let path = Path::new(lock.path);
+    match lock.kind {
+        LockKind::File if !path.exists() => NativeState::Free,
+        LockKind::File if !Path::new("/proc").is_dir() => NativeState::Held(None),
+        // Open files of other users' processes cannot be read without privileges, so fall back to
+        // any running instance of the package manager.
+        LockKind::File => match opened_by(path).or_else(|| running(lock.program)) {
+            Some(pid) => NativeState::Held(Some(process(pid))),
+            None => NativeState::Stale,
+        },
+        LockKind::Advisory => match advisory_holder(path) {
+            Some(pid) => NativeState::Held(pid.map(process)),
+            None => NativeState::Free,
+        },
+        LockKind::PidFile => {
+            let pid = fs::read_to_string(path)
+                .ok()
+                .and_then(|pid| pid.trim().parse::<u32>().ok())
+                .filter(|pid| Path::new(&format!("/proc/{pid}")).exists());
+            match pid {
+                Some(pid) => NativeState::Held(Some(process(pid))),
+                None => NativeState::Free,
+            }
+        }
+    }
+}
+
+fn process(pid: u32) -> Process {
+    let command = fs::read(format!("/proc/{pid}/cmdline"))
+        .map(|cmdline| {
+            String::from_utf8_lossy(&cmdline)
+                .split('\0')
+                .filter(|arg| !arg.is_empty())
+                .collect::<Vec<_>>()
+                .join(" ")
+        })
+        .unwrap_or_default();
+    Process { pid, command }
+}
+
+/// Other processes, by pid.
+fn processes() -> impl Iterator<Item = (u32, PathBuf)> {
+    let own = std::process::id();
+    fs::read_dir("/proc")
+        .into_iter()
+        .flatten()
+        .filter_map(|entry| {
+            let entry = entry.ok()?;
+            let pid = entry.file_name().to_str()?.parse::<u32>().ok()?;
+            Some((pid, entry.path()))
+        })
+        .filter(move |(pid, _)| *pid != own)
+}
+
+/// A process with `path` open.
+fn opened_by(path: &Path) -> Option<u32> {
+    let path = path.canonicalize().ok()?;
+    processes().find_map(|(pid, dir)| {
+        fs::read_dir(dir.join("fd"))
+            .ok()?
+            .filter_map(Result::ok)
+            .any(|fd| fs::read_link(fd.path()).is_ok_and(|target| target == path))
+            .then_some(pid)
+    })
+}
+
+/// A process running `program`.
+fn running(program: &str) -> Option<u32> {
+    // The kernel truncates process names to 15 bytes.
+    let name = &program[..program.len().min(15)];
+    processes().find_map(|(pid, dir)| {
+        let comm = fs::read_to_string(dir.join("comm")).ok()?;
+        (comm.trim_end() == name).then_some(pid)
+    })
+}
+
+/// Whether a process holds an `fcntl` or `flock` lock on `path`, and its pid if the kernel reports
+/// one.
+#[cfg(target_os = "linux")]
+fn advisory_holder(path: &Path) -> Option<Option<u32>> {
+    use std::os::unix::fs::MetadataExt;
+
+    let metadata = fs::metadata(path).ok()?;
+    let dev = metadata.dev();
+    let major = ((dev >> 8) & 0xfff) | ((dev >> 32) & !0xfff);
+    let minor = (dev & 0xff) | ((dev >> 12) & !0xff);
+    let file = format!("{major:02x}:{minor:02x}:{}", metadata.ino());
+    lock_holder(&fs::read_to_string("/proc/locks").ok()?, &file)
+}
+
+/// Finds the lock held on `file`, given as `major:minor:inode` in hex, in the contents of
+/// `/proc/locks`, and its pid if there is one.
+#[cfg(target_os = "linux")]
+fn lock_holder(locks: &str, file: &str) -> Option<Option<u32>> {
+    // "1: POSIX  ADVISORY  WRITE 1234 08:01:131090 0 EOF"; waiting processes are marked "->".
+    locks.lines().find_map(|line| {
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        if fields.get(1) == Some(&"->") || fields.get(5) != Some(&file) {
+            return None;
+        }
+        // Open file description locks have no owning process.
+        Some(fields[4].parse::<u32>().ok())
+    })
+}
+
+#[cfg(not(target_os = "linux"))]
+fn advisory_holder(_path: &Path) -> Option<Option<u32>> {
+    None
+}
+
+#[cfg(all(test, target_os = "linux"))]
+mod tests {
+    use super::*;
+
+    const LOCKS: &str = "1: POSIX  ADVISORY  WRITE 812 00:1a:1234 0 EOF
+1: -> POSIX  ADVISORY  WRITE 990 08:01:131090 0 EOF
+2: FLOCK  ADVISORY  WRITE 1234 08:01:131090 0 EOF
+3: OFDLCK ADVISORY  WRITE -1 08:01:77 0 EOF
+";
+
+    #[test]
+    fn lock_holder_skips_waiters() {
+        assert_eq!(lock_holder(LOCKS, "08:01:131090"), Some(Some(1234)));
+        assert_eq!(lock_holder(LOCKS, "00:1a:1234"), Some(Some(812)));
+    }
+
+    #[test]
+    fn lock_holder_without_pid() {
+        assert_eq!(lock_holder(LOCKS, "08:01:77"), Some(None));
+    }
+
+    #[test]
+    fn lock_holder_unlocked() {
+        assert_eq!(lock_holder(LOCKS, "08:01:13109"), None);
+        assert_eq!(lock_holder("", "08:01:131090"), None);
+    }
+}
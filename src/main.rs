@@ -1,763 +1,405 @@
-#![allow(clippy::doc_markdown)]
+mod cli;
 
-mod conf;
-mod helpers;
-mod self_up;
-
-use conf::Config;
-use helpers::{find_matching_executables, format_list, p_cont, p_cont_ext, reorder_candidates};
+use cli::Action;
+use qud::account::{self, Account};
+use qud::conf::Config;
+use qud::escalation::{self, Keepalive};
+use qud::helpers::format_list;
+use qud::history::{self, Filter, RunRecord};
+use qud::lock::{Lock, Scope};
+use qud::managers::Registry;
+use qud::outdated::{self, ManagerUpdates};
+use qud::output::{self, DetectedManager, Event, Reporter};
+use qud::plan::{StepKind, UpdatePlan};
+use qud::plan_file::SavedPlan;
+use qud::self_up::perm::is_elevated;
+use qud::summary::{ExitCode, Summary};
+use qud::{detect, exec, plan, say};
 
 use colored::Colorize;
-use std::collections::{HashMap, HashSet};
+use std::collections::HashSet;
 use std::env;
+use std::fs;
 use std::io::Write;
-use std::path::{Path, PathBuf};
-use std::process::{Command, Stdio};
-
-// Supported package managers:
-// Linux: pacman, yay, apt, apt-get, dnf, zypper, snap, flatpak, xbps-install, apk, emerge, guix, nix, yum, eopkg, cave, sbopkg, scratch
-// Windows: choco, scoop, winget, Windows itself (via PowerShell)
-// General: rustup, brew, port (MacPorts), pkg (FreeBSD), cargo, npm, pip, composer, gem, conda, poetry, nuget, asdf, vcpkg, conan, stack, opam, mix, sdkman,
-// gvm, pnpm, yarn, maven, and go
-const PM: [&str; 46] = [
-    "pacman",
-    "yay",
-    "apt",
-    "apt-get",
-    "dnf",
-    "zypper",
-    "snap",
-    "flatpak",
-    "xbps-install",
-    "choco",
-    "scoop",
-    "winget",
-    "rustup",
-    "brew",
-    "apk",
-    "nix",
-    "emerge",
-    "guix",
-    "yum",
-    "port",
-    "pkg",
-    "eopkg",
-    "cargo",
-    "npm",
-    "pip",
-    "composer",
-    "gem",
-    "conda",
-    "poetry",
-    "nuget",
-    "asdf",
-    "vcpkg",
-    "conan",
-    "stack",
-    "opam",
-    "mix",
-    "sdkman",
-    "gvm",
-    "pnpm",
-    "yarn",
-    "maven",
-    "go",
-    "cave",
-    "sbopkg",
-    "scratch",
-    "windowsupdate",
-];
+use std::path::Path;
+use std::time::Instant;
 
 #[allow(clippy::too_many_lines)]
 fn main() {
-    let config = Config::parse_args();
-
-    let mut seen = HashMap::new();
-    let mut duplicates: HashMap<String, Vec<PathBuf>> = HashMap::new();
-    let mut candidates = Vec::new();
-    for candidate in find_matching_executables(&PM)
-        .into_iter()
-        .map(PathBuf::from)
-    {
-        if let Some(pm_name) = candidate.file_name().and_then(|s| s.to_str()) {
-            if seen.contains_key(pm_name) {
-                duplicates
-                    .entry(pm_name.to_string())
-                    .or_default()
-                    .push(candidate.clone());
-            } else {
-                seen.insert(pm_name.to_string(), candidate.clone());
-                candidates.push(candidate);
-            }
-        }
+    let (action, config) = cli::parse_args();
+    output::set_format(config.format);
+    let mut reporter = Reporter::new(config.format);
+    if let Action::Apply { plan } = &action {
+        let code = apply(plan, &config, &mut reporter);
+        reporter.finish();
+        code.exit();
     }
+    if let Action::History { filter, show } = &action {
+        let code = show_history(filter, show.as_deref(), &mut reporter);
+        reporter.finish();
+        code.exit();
+    }
+    let registry = config.registry();
+    let detection = detect::detect(&registry);
+    reporter.emit(&Event::Detected {
+        managers: DetectedManager::all(&detection),
+    });
 
     if config.verbose {
-        for candidate in &candidates {
-            if let Some(pm_name) = candidate.file_name().and_then(|s| s.to_str()) {
-                if !config.specs.contains_key(pm_name) {
-                    if let Some(dups) = duplicates.get(pm_name) {
-                        let mut all_paths = vec![candidate.display().to_string()];
-                        all_paths.extend(dups.iter().map(|p| p.display().to_string()));
-                        eprintln!(
-                            "{} Multiple installations of {} found: {}. Using {}. Use --spec {}::/path/to/executable to override this.",
-                            "Warning:".yellow(),
-                            pm_name,
-                            format_list(&all_paths),
-                            candidate.display(),
-                            pm_name
-                        );
-                    }
+        for (pm_name, candidate) in &detection.found {
+            if !config.specs.contains_key(pm_name) {
+                if let Some(aliases) = detection.aliases.get(pm_name) {
+                    let aliases: Vec<String> =
+                        aliases.iter().map(|p| p.display().to_string()).collect();
+                    say!(
+                        "{} Not counting {} as another installation of {}, it links to one already found.",
+                        "INFO:".blue(),
+                        format_list(&aliases),
+                        pm_name
+                    );
+                }
+                if let Some(dups) = detection.duplicates.get(pm_name) {
+                    // Show where links lead, to tell the installations apart.
+                    let all_paths: Vec<String> = std::iter::once(candidate)
+                        .chain(dups)
+                        .map(|path| match fs::canonicalize(path) {
+                            Ok(file) if file != *path => {
+                                format!("{} -> {}", path.display(), file.display())
+                            }
+                            _ => path.display().to_string(),
+                        })
+                        .collect();
+                    eprintln!(
+                        "{} Multiple installations of {} found: {}. Using {}. Use --spec {}::/path/to/executable to override this.",
+                        "Warning:".yellow(),
+                        pm_name,
+                        format_list(&all_paths),
+                        candidate.display(),
+                        pm_name
+                    );
                 }
             }
         }
     }
 
     if config.verbose {
-        println!(
+        say!(
             "{} Found {} candidate package manager executable(s).",
             "INFO:".blue(),
-            candidates.len()
+            detection.found.len()
         );
     }
 
     if config.list {
-        println!("Detected package managers:");
-        for path in &candidates {
-            if let Some(pm_name) = path.file_name().and_then(|s| s.to_str()) {
-                println!("  {} ({})", pm_name, path.display());
-            }
+        say!("Detected package managers:");
+        for (pm_name, path) in &detection.found {
+            say!("  {} ({})", pm_name, path.display());
         }
+        reporter.finish();
         return;
     }
 
-    let mut final_candidates = Vec::new();
-    let mut used_pm_names = HashSet::new();
-    for candidate in candidates {
-        if let Some(pm_name) = candidate.file_name().and_then(|s| s.to_str()) {
-            used_pm_names.insert(pm_name.to_string());
-            if let Some(spec_path) = config.specs.get(pm_name) {
-                if config.verbose {
-                    println!(
-                        "{} Overriding {} with specified executable: {}",
-                        "INFO:".blue(),
-                        pm_name,
-                        spec_path.display()
-                    );
-                }
-                final_candidates.push(spec_path.clone());
-            } else {
-                final_candidates.push(candidate);
-            }
-        }
-    }
-    for (pm, spec_path) in &config.specs {
-        if !used_pm_names.contains(pm) {
-            if config.verbose {
-                println!(
-                    "{} Adding specified executable for {} not found in PATH: {}",
-                    "INFO:".blue(),
-                    pm,
-                    spec_path.display()
-                );
-            }
-            final_candidates.push(spec_path.clone());
+    let candidates = plan::candidates(&config, detection);
+    #[cfg(not(target_os = "windows"))]
+    let current_dir = env::current_dir().unwrap_or_else(|_| "/".into());
+    #[cfg(target_os = "windows")]
+    let current_dir = env::current_dir().unwrap_or_else(|_| "C:\\".into());
+
+    let owner = match account::owner(&config) {
+        Ok(owner) => owner,
+        Err(e) => {
+            eprintln!("{} {e}", "ERR:".red());
+            ExitCode::Failure.exit();
         }
+    };
+    if let (Some(owner), true) = (&owner, config.verbose) {
+        say!(
+            "{} Running user-level package managers as {}.",
+            "INFO:".blue(),
+            owner.name
+        );
     }
 
-    #[allow(unused_mut)]
-    let mut final_candidates = if let Some(ref ord_mode) = config.ord {
-        reorder_candidates(final_candidates, ord_mode, config.verbose)
-    } else {
-        final_candidates
-    };
+    if let Action::Outdated = action {
+        let managers = outdated::query(
+            &config,
+            &registry,
+            &candidates,
+            &current_dir,
+            owner.as_ref(),
+        );
+        outdated::print(&managers);
+        reporter.emit(&Event::Outdated {
+            managers: &managers,
+        });
+        reporter.finish();
+        outdated_code(&managers).exit();
+    }
+    let mut plan = UpdatePlan::new(&config, &registry, candidates, &current_dir, owner.as_ref());
+    if config.all_users {
+        add_users(&mut plan, &config, &registry, owner.as_ref());
+    }
 
-    #[cfg(target_os = "windows")]
-    {
-        let include_windowsupdate = match &config.only {
-            Some(only_list) => only_list.iter().any(|s| s == "windowsupdate"),
-            None => true,
-        };
-        if include_windowsupdate {
-            if !final_candidates.iter().any(|p| {
-                p.file_name()
-                    .and_then(|s| s.to_str())
-                    .map(|s| s == "windowsupdate")
-                    .unwrap_or(false)
-            }) {
-                if config.verbose {
-                    println!("{} Adding Windows update.", "INFO:".blue());
-                }
-                final_candidates.push(PathBuf::from("windowsupdate"));
-            }
+    if let Action::Plan { output } = &action {
+        let result = SavedPlan::new(plan).and_then(|saved| saved.save(output));
+        if let Err(e) = result {
+            eprintln!("{} Failed to save plan: {e}", "ERR:".red());
+            ExitCode::Failure.exit();
         }
+        return;
     }
 
-    let planned_updates: Vec<String> = final_candidates
-        .iter()
-        .filter_map(|candidate| {
-            if let Some(pm_name) = candidate.file_name().and_then(|s| s.to_str()) {
-                if let Some(ref only_list) = config.only {
-                    if !only_list.iter().any(|s| s == pm_name) {
-                        return None;
-                    }
-                }
-                if let Some(exclusions) = config.exclusions.get(pm_name) {
-                    if exclusions.is_empty() {
-                        return None;
-                    }
-                }
-                Some(format!("{} ({})", pm_name, candidate.display()))
-            } else {
-                None
-            }
-        })
-        .collect();
-
-    println!("Updating with:");
-    for item in &planned_updates {
-        println!("  {item}");
+    say!("Updating with:");
+    for manager in &plan.managers {
+        say!("  {} ({})", manager.label(), manager.exe.display());
     }
 
-    if !config.auto && !config.noconfirm {
-        let updatable: Vec<(PathBuf, String)> = final_candidates
+    if !config.auto && !config.noconfirm && !plan.is_empty() {
+        say!("{} Detected package managers to update:", "INFO:".blue());
+        for (i, manager) in plan.managers.iter().enumerate() {
+            say!("  {}. {}", i + 1, manager.label());
+        }
+        say!(
+            "{} Enter numbers of package managers to skip (space separated), or press Enter to proceed:",
+            "INFO:".blue()
+        );
+        flush();
+        let Some(input) = answer() else {
+            say!("Cancelled.");
+            ExitCode::Aborted.exit();
+        };
+        let skip_set: HashSet<usize> = input
+            .split_whitespace()
+            .filter_map(|s| s.parse::<usize>().ok())
+            .filter_map(|n| n.checked_sub(1))
+            .collect();
+        let skip_labels: HashSet<String> = plan
+            .managers
             .iter()
-            .filter_map(|candidate| {
-                if let Some(pm_name) = candidate.file_name().and_then(|s| s.to_str()) {
-                    if let Some(ref only_list) = config.only {
-                        if !only_list.iter().any(|s| s == pm_name) {
-                            return None;
-                        }
-                    }
-                    if let Some(exclusions) = config.exclusions.get(pm_name) {
-                        if exclusions.is_empty() {
-                            return None;
-                        }
-                    }
-                    Some((candidate.clone(), pm_name.to_string()))
-                } else {
-                    None
-                }
-            })
+            .enumerate()
+            .filter(|(i, _)| skip_set.contains(i))
+            .map(|(_, manager)| manager.label())
             .collect();
-
-        if !updatable.is_empty() {
-            println!("{} Detected package managers to update:", "INFO:".blue());
-            for (i, (_candidate, pm_name)) in updatable.iter().enumerate() {
-                println!("  {}. {}", i + 1, pm_name);
-            }
-            println!(
-                "{} Enter numbers of package managers to skip (space separated), or press Enter to proceed:",
-                "INFO:".blue()
-            );
-            std::io::stdout().flush().unwrap();
-            let mut input = String::new();
-            std::io::stdin().read_line(&mut input).unwrap();
-            let skip_numbers: Vec<usize> = input
-                .split_whitespace()
-                .filter_map(|s| s.parse::<usize>().ok())
-                .collect();
-            let skip_set: HashSet<usize> = skip_numbers.into_iter().map(|n| n - 1).collect();
-            let skip_pm_names: HashSet<String> = updatable
-                .iter()
-                .enumerate()
-                .filter(|(i, _)| skip_set.contains(i))
-                .map(|(_, (_, pm_name))| pm_name.clone())
-                .collect();
-            final_candidates.retain(|candidate| {
-                if let Some(pm_name) = candidate.file_name().and_then(|s| s.to_str()) {
-                    !skip_pm_names.contains(pm_name)
-                } else {
-                    true
-                }
-            });
-            println!("{} Proceeding with updates for:", "INFO:".blue());
-            for candidate in &final_candidates {
-                if let Some(pm_name) = candidate.file_name().and_then(|s| s.to_str()) {
-                    println!("  {} ({})", pm_name, candidate.display());
-                }
-            }
+        plan.skip(&skip_labels);
+        say!("{} Proceeding with updates for:", "INFO:".blue());
+        for manager in &plan.managers {
+            say!("  {} ({})", manager.label(), manager.exe.display());
         }
     }
 
-    for package_manager in final_candidates {
-        let Some(pm_name) = package_manager.file_name().and_then(|s| s.to_str()) else {
-            continue;
-        };
+    // Taken only once the prompt is answered, like in `apply`.
+    let _lock = lock(&plan, &config);
+    reporter.emit(&Event::Plan { plan: &plan });
+    let code = run(&plan, &config, &mut reporter);
+    reporter.finish();
+    code.exit();
+}
 
-        if let Some(ref only_list) = config.only {
-            if !only_list.iter().any(|s| s == pm_name) {
-                if config.verbose {
-                    println!(
-                        "{} Skipping {} because it is not in the --only list.",
-                        "INFO:".blue(),
-                        pm_name
-                    );
-                }
-                continue;
-            }
-        }
+/// [`ExitCode::UpdatesAvailable`] if any package manager has pending updates, otherwise whether
+/// the queries worked.
+fn outdated_code(managers: &[ManagerUpdates]) -> ExitCode {
+    let failed = managers.iter().filter(|m| m.error.is_some()).count();
+    if managers.iter().any(|m| !m.packages.is_empty()) {
+        ExitCode::UpdatesAvailable
+    } else if failed == 0 {
+        ExitCode::Success
+    } else if failed == managers.len() {
+        ExitCode::Failure
+    } else {
+        ExitCode::PartialFailure
+    }
+}
 
-        if let Some(exclusions) = config.exclusions.get(pm_name) {
-            if exclusions.is_empty() {
-                if config.verbose {
-                    println!(
-                        "{} Skipping {} because it is fully excluded via --excl",
-                        "INFO:".blue(),
-                        pm_name
-                    );
-                }
-                continue;
+/// Runs `plan`, reports a summary and records the run in the history. Dry runs only print the
+/// steps.
+fn run(plan: &UpdatePlan, config: &Config, reporter: &mut Reporter) -> ExitCode {
+    let started = history::now();
+    let start = Instant::now();
+    let _keepalive = authenticate(plan, config);
+    let outcome = exec::execute(plan, config, reporter);
+    if config.dry_run {
+        return ExitCode::Success;
+    }
+    let summary = Summary::new(plan, &outcome, start.elapsed());
+    summary.print();
+    reporter.emit(&Event::Summary { summary: &summary });
+    let code = summary.exit_code();
+    if config.history {
+        let mut record =
+            RunRecord::new(started, config, plan, outcome.results, summary, code as i32);
+        match record.save() {
+            Ok(path) if config.verbose => {
+                say!("{} Recorded run in {}", "INFO:".blue(), path.display());
             }
+            Ok(_) => {}
+            Err(e) => eprintln!("{} Failed to record run in history: {e}", "WARN:".yellow()),
         }
-
-        let mut extra_args = config.get_exclusion_args(pm_name);
-        extra_args.extend(config.get_ext_args(pm_name));
-
-        process_pm(
-            pm_name,
-            config.auto,
-            #[cfg(not(target_os = "windows"))]
-            &env::current_dir().unwrap_or_else(|_| "/".into()),
-            #[cfg(target_os = "windows")]
-            &env::current_dir().unwrap_or_else(|_| "C:\\".into()),
-            &extra_args,
-            config.dry_run,
-        );
     }
+    code
 }
 
-#[allow(clippy::too_many_lines)]
-fn process_pm(pm_name: &str, auto: bool, current_dir: &Path, extra_args: &[String], dry_run: bool) {
-    println!(
-        "{} Processing package manager: {} in directory: {}",
-        "INFO:".blue(),
-        pm_name,
-        current_dir.display()
-    );
-    match pm_name {
-        "pacman" => {
-            let args: &[&str] = if auto {
-                &["-Syu", "--noconfirm"]
-            } else {
-                &["-Syu"]
-            };
-            upd("pacman", args, true, extra_args, dry_run);
-        }
-        "yay" => {
-            let args: &[&str] = if auto {
-                &[
-                    "-Syu",
-                    "--noconfirm",
-                    "--answerdiff",
-                    "None",
-                    "--answerclean",
-                    "None",
-                ]
-            } else {
-                &["-Syu"]
-            };
-            upd("yay", args, false, extra_args, dry_run);
-        }
-        "apt" | "apt-get" => {
-            upd(pm_name, &["update"], true, extra_args, dry_run);
-            let upgrade_args: &[&str] = if auto {
-                &["upgrade", "-y"]
-            } else {
-                &["upgrade"]
-            };
-            upd(pm_name, upgrade_args, true, extra_args, dry_run);
-        }
-        "dnf" => {
-            let args: &[&str] = if auto {
-                &["upgrade", "--refresh", "-y"]
-            } else {
-                &["upgrade", "--refresh"]
-            };
-            upd("dnf", args, true, extra_args, dry_run);
-        }
-        "zypper" => {
-            let args: &[&str] = if auto {
-                &["--non-interactive", "update"]
-            } else {
-                &["update"]
-            };
-            upd("zypper", args, true, extra_args, dry_run);
-        }
-        "snap" => {
-            upd("snap", &["refresh"], true, extra_args, dry_run);
-        }
-        "flatpak" => {
-            let args: &[&str] = if auto { &["update", "-y"] } else { &["update"] };
-            upd("flatpak", args, false, extra_args, dry_run);
-        }
-        "xbps-install" => {
-            let args: &[&str] = if auto { &["-Syu", "--yes"] } else { &["-Syu"] };
-            upd("xbps-install", args, true, extra_args, dry_run);
-        }
-        "choco" => {
-            let args: &[&str] = if auto {
-                &["upgrade", "all", "-y"]
-            } else {
-                &["upgrade", "all"]
-            };
-            upd("choco", args, false, extra_args, dry_run);
-        }
-        "scoop" => {
-            upd("scoop", &["update", "*"], false, extra_args, dry_run);
-        }
-        "winget" => {
-            let args: &[&str] = if auto {
-                &[
-                    "upgrade",
-                    "--all",
-                    "--accept-source-agreements",
-                    "--accept-package-agreements",
-                ]
-            } else {
-                &["upgrade", "--all"]
-            };
-            upd("winget", args, false, extra_args, dry_run);
-        }
-        #[cfg(target_os = "windows")]
-        "windowsupdate" => {
-            let setup_commands = [
-                "Set-ExecutionPolicy -Scope CurrentUser RemoteSigned -Force",
-                "if (!(Get-Module -ListAvailable -Name PSWindowsUpdate)) { Install-Module -Name PSWindowsUpdate -Force -Scope CurrentUser }",
-                "Import-Module PSWindowsUpdate",
-                if auto {
-                    "Start-Process powershell -Verb RunAs -ArgumentList '-NoProfile -Command &{Install-WindowsUpdate -AcceptAll -AutoReboot}'"
-                } else {
-                    "Start-Process powershell -Verb RunAs -ArgumentList '-NoProfile -Command &{Install-WindowsUpdate}'"
-                }
-            ].join("; ");
-
-            upd(
-                "powershell",
-                &["-Command", &setup_commands],
-                false,
-                extra_args,
-                dry_run,
-            );
-        }
-        "rustup" => {
-            upd("rustup", &["update"], false, extra_args, dry_run);
-        }
-        "brew" => {
-            upd("brew", &["update"], false, extra_args, dry_run);
-            upd("brew", &["upgrade"], false, extra_args, dry_run);
-        }
-        "apk" => {
-            upd("apk", &["update"], true, extra_args, dry_run);
-            upd("apk", &["upgrade"], true, extra_args, dry_run);
-        }
-        "nix" => {
-            upd("nix-channel", &["--update"], false, extra_args, dry_run);
-            upd("nix-env", &["-u", "*"], false, extra_args, dry_run);
-        }
-        "emerge" => {
-            upd("emerge", &["--sync"], true, extra_args, dry_run);
-            let args: &[&str] = if auto {
-                &["-uDN", "@world"]
-            } else {
-                &["-avuDN", "@world"]
-            };
-            upd("emerge", args, true, extra_args, dry_run);
-        }
-        "guix" => {
-            upd("guix", &["pull"], false, extra_args, dry_run);
-            upd(
-                "guix",
-                &["package", "--upgrade"],
-                false,
-                extra_args,
-                dry_run,
-            );
-        }
-        "yum" => {
-            let args: &[&str] = if auto { &["update", "-y"] } else { &["update"] };
-            upd("yum", args, true, extra_args, dry_run);
-        }
-        "port" => {
-            upd("port", &["selfupdate"], true, extra_args, dry_run);
-            upd("port", &["upgrade", "outdated"], true, extra_args, dry_run);
-        }
-        "pkg" => {
-            upd("pkg", &["update"], true, extra_args, dry_run);
-            let args: &[&str] = if auto {
-                &["upgrade", "-y"]
-            } else {
-                &["upgrade"]
-            };
-            upd("pkg", args, true, extra_args, dry_run);
-        }
-        "eopkg" => {
-            upd("eopkg", &["update-repo"], true, extra_args, dry_run);
-            let args: &[&str] = if auto {
-                &["upgrade", "-y"]
-            } else {
-                &["upgrade"]
-            };
-            upd("eopkg", args, true, extra_args, dry_run);
-        }
-        "cargo" => {
-            if p_cont(current_dir, "Cargo.toml").unwrap_or(false) {
-                upd("cargo", &["update"], false, extra_args, dry_run);
-            }
-        }
-        "npm" => {
-            if p_cont(current_dir, "package.json").unwrap_or(false) {
-                upd("npm", &["update"], false, extra_args, dry_run);
-            }
-        }
-        "pip" => {
-            if p_cont(current_dir, "requirements.txt").unwrap_or(false) {
-                upd(
-                    "pip",
-                    &["install", "--upgrade", "-r", "requirements.txt"],
-                    false,
-                    extra_args,
-                    dry_run,
-                );
-            }
-        }
-        "composer" => {
-            if p_cont(current_dir, "composer.json").unwrap_or(false) {
-                upd("composer", &["update"], false, extra_args, dry_run);
+/// Lists recorded runs, or shows the one named `show`.
+fn show_history(filter: &Filter, show: Option<&str>, reporter: &mut Reporter) -> ExitCode {
+    let runs = match show {
+        Some(id) => history::find(id).map(|run| vec![run]),
+        None => history::list(filter).map_err(Into::into),
+    };
+    match runs {
+        Ok(runs) => {
+            match show {
+                Some(_) => runs.iter().for_each(history::print_run),
+                None => history::print_list(&runs),
             }
+            reporter.emit(&Event::History { runs: &runs });
+            ExitCode::Success
         }
-        "gem" => {
-            upd(
-                "gem",
-                &["update", "--no-document"],
-                false,
-                extra_args,
-                dry_run,
-            );
-        }
-        "conda" => {
-            upd(
-                "conda",
-                &["update", "--all", "-y"],
-                true,
-                extra_args,
-                dry_run,
-            );
-        }
-        "poetry" => {
-            upd("poetry", &["update"], false, extra_args, dry_run);
-        }
-        "nuget" => {
-            if p_cont(current_dir, "packages.config").unwrap_or(false) {
-                upd(
-                    "nuget",
-                    &["update", "packages.config"],
-                    false,
-                    extra_args,
-                    dry_run,
-                );
-            } else if let Some(Ok(f)) = p_cont_ext(current_dir, ".sln") {
-                upd("nuget", &["update", &f], false, extra_args, dry_run);
-            }
+        Err(e) => {
+            eprintln!("{} Failed to read history: {e}", "ERR:".red());
+            ExitCode::Failure
         }
-        "asdf" => {
-            upd("asdf", &["update"], false, extra_args, dry_run);
-            upd(
-                "asdf",
-                &["plugin-update", "--all"],
-                false,
-                extra_args,
-                dry_run,
+    }
+}
+
+/// Runs a saved plan after checking that none of its programs changed.
+fn apply(path: &Path, config: &Config, reporter: &mut Reporter) -> ExitCode {
+    let saved = match SavedPlan::load(path) {
+        Ok(saved) => saved,
+        Err(e) => {
+            eprintln!(
+                "{} Failed to read plan {}: {e}",
+                "ERR:".red(),
+                path.display()
             );
+            return ExitCode::Failure;
         }
-        "vcpkg" => {
-            let args: &[&str] = if auto { &["upgrade"] } else { &["update"] };
-            upd("vcpkg", args, false, extra_args, dry_run);
-        }
-        "conan" => {
-            if p_cont(current_dir, "conanfile.txt").unwrap_or(false)
-                || p_cont(current_dir, "conanfile.py").unwrap_or(false)
-            {
-                upd(
-                    "conan",
-                    &["install", ".", "--update"],
-                    false,
-                    extra_args,
-                    dry_run,
-                );
-            }
-        }
-        "stack" => {
-            if p_cont(current_dir, "stack.yaml").unwrap_or(false) {
-                upd("stack", &["update"], false, extra_args, dry_run);
-                upd("stack", &["upgrade"], false, extra_args, dry_run);
-            }
+    };
+    let changes = saved.changes();
+    if !changes.is_empty() {
+        eprintln!(
+            "{} Refusing to apply {}, executables changed since it was made:",
+            "ERR:".red(),
+            path.display()
+        );
+        for change in &changes {
+            eprintln!("  {change}");
         }
-        "opam" => {
-            upd("opam", &["update"], false, extra_args, dry_run);
-            let args: &[&str] = if auto {
-                &["upgrade", "-y"]
+        return ExitCode::Failure;
+    }
+
+    say!("Applying plan {}:", path.display());
+    let hooks = |kind| saved.plan.hooks.iter().filter(move |h| h.kind == kind);
+    for hook in hooks(StepKind::PreHook) {
+        say!("  {}: {hook}", hook.kind);
+    }
+    for manager in &saved.plan.managers {
+        for step in &manager.steps {
+            if step.kind.is_hook() {
+                say!("  {}: {}: {step}", manager.label(), step.kind);
             } else {
-                &["upgrade"]
-            };
-            upd("opam", args, false, extra_args, dry_run);
-        }
-        "mix" => {
-            if p_cont(current_dir, "mix.exs").unwrap_or(false) {
-                upd("mix", &["deps.update", "--all"], false, extra_args, dry_run);
+                say!("  {}: {step}", manager.label());
             }
         }
-        "sdkman" => {
-            upd("sdkman", &["update"], false, extra_args, dry_run);
-        }
-        "gvm" => {
-            upd("gvm", &["update"], false, extra_args, dry_run);
-        }
-        "pnpm" => {
-            if p_cont(current_dir, "package.json").unwrap_or(false) {
-                upd("pnpm", &["update"], false, extra_args, dry_run);
-            }
-        }
-        "yarn" => {
-            if p_cont(current_dir, "yarn.lock").unwrap_or(false) {
-                upd("yarn", &["upgrade"], false, extra_args, dry_run);
-            }
+    }
+    for hook in hooks(StepKind::PostHook) {
+        say!("  {}: {hook}", hook.kind);
+    }
+    if !config.auto && !config.noconfirm && !config.dry_run {
+        say!("{} Proceed? (Y/n)", "INFO:".blue());
+        flush();
+        let input = answer().map(|input| input.trim().to_lowercase());
+        if input.is_none_or(|input| input == "n" || input == "no") {
+            say!("Cancelled.");
+            return ExitCode::Aborted;
         }
-        "maven" => {
-            if p_cont(current_dir, "pom.xml").unwrap_or(false) {
-                let args: &[&str] = if auto {
-                    &["versions:use-latest-releases"]
+    }
+    // Taken only once confirmed, so that a pending prompt does not keep other runs waiting.
+    let _lock = lock(&saved.plan, config);
+    reporter.emit(&Event::Plan { plan: &saved.plan });
+    run(&saved.plan, config, reporter)
+}
+
+/// Adds the user-level package managers of every login user to `plan`, except those of root and
+/// `owner`, which are planned already. Exits unless qud runs as root.
+fn add_users(plan: &mut UpdatePlan, config: &Config, registry: &Registry, owner: Option<&Account>) {
+    if !is_elevated() {
+        eprintln!(
+            "{} --all-users needs root, to run package managers as other users.",
+            "ERR:".red()
+        );
+        ExitCode::Failure.exit();
+    }
+    let uids = config
+        .uid_range
+        .clone()
+        .unwrap_or_else(account::default_uids);
+    let users: Vec<_> = account::login_users(config.users.as_deref(), &uids)
+        .into_iter()
+        .filter(|user| owner.is_none_or(|owner| owner.uid != user.uid))
+        .map(|user| {
+            let managers = detect::user_managers(registry, &user);
+            (user, managers)
+        })
+        .collect();
+    if config.verbose {
+        for (user, managers) in &users {
+            let names: Vec<&str> = managers.iter().map(|(name, _)| name.as_str()).collect();
+            say!(
+                "{} User-level package managers of {}: {}",
+                "INFO:".blue(),
+                user.name,
+                if names.is_empty() {
+                    "none".to_string()
                 } else {
-                    &["versions:display-dependency-updates"]
-                };
-                upd("mvn", args, false, extra_args, dry_run);
-            }
-        }
-        "go" => {
-            if p_cont(current_dir, "go.mod").unwrap_or(false) {
-                upd("go", &["get", "-u", "./..."], false, extra_args, dry_run);
-            }
-        }
-        "cave" => {
-            upd("cave", &["sync"], true, extra_args, dry_run);
-            let args: &[&str] = if auto {
-                &["upgrade", "--non-interactive"]
-            } else {
-                &["upgrade"]
-            };
-            upd("cave", args, true, extra_args, dry_run);
-        }
-        "sbopkg" => {
-            upd("sbopkg", &["-r"], true, extra_args, dry_run);
-            let args: &[&str] = if auto {
-                &["-i", "--non-interactive"]
-            } else {
-                &["-i"]
-            };
-            upd("sbopkg", args, true, extra_args, dry_run);
-        }
-        "scratch" => {
-            let args: &[&str] = if auto {
-                &["update", "--non-interactive"]
-            } else {
-                &["update"]
-            };
-            upd("scratch", args, true, extra_args, dry_run);
+                    names.join(", ")
+                }
+            );
         }
-        _ => eprintln!(
-            "{} Unknown package manager: {}",
-            "Warning:".yellow(),
-            pm_name
-        ),
     }
+    plan.add_users(config, registry, users);
 }
 
-fn upd(command: &str, base_args: &[&str], use_sudo: bool, extra_args: &[String], dry_run: bool) {
-    let mut args: Vec<String> = base_args.iter().map(ToString::to_string).collect();
-    args.extend_from_slice(extra_args);
-
-    #[cfg(not(target_os = "windows"))]
-    let cmd_str = if use_sudo {
-        format!("sudo {} {}", command, args.join(" "))
-    } else {
-        format!("{} {}", command, args.join(" "))
-    };
-
-    #[cfg(target_os = "windows")]
-    let cmd_str = if use_sudo {
-        format!(
-            "runas /user:Administrator \"{} {}\"",
-            command,
-            args.join(" ")
-        )
-    } else {
-        format!("{} {}", command, args.join(" "))
-    };
-
-    if dry_run {
-        println!("Dry run: {cmd_str}");
-        return;
+/// Takes the lock keeping other qud runs from updating at the same time, or exits if another run
+/// holds it. Dry runs take no lock.
+fn lock(plan: &UpdatePlan, config: &Config) -> Option<Lock> {
+    if config.dry_run {
+        return None;
     }
-
-    println!("{} Executing command: {cmd_str}", "INFO:".blue());
-    match gen_upd_cmd(command, &args, use_sudo).status() {
-        Ok(es) => {
-            if es.success() {
-                println!(
-                    "{} Successfully updated with {}, exited with status {}",
-                    "INFO:".blue(),
-                    command,
-                    es
-                );
-            } else {
-                println!(
-                    "{} Failed to update with {}, exited with status: {}",
-                    "ERR:".red(),
-                    command,
-                    es
-                );
-            }
+    match Lock::acquire(Scope::of(plan), config.wait) {
+        Ok(lock) => Some(lock),
+        Err(e) => {
+            eprintln!("{} {e}", "ERR:".red());
+            ExitCode::Failure.exit();
         }
-        Err(e) => eprintln!(
-            "{} Failed to update with {}, error: {}",
-            "ERR:".red(),
-            command,
-            e
-        ),
     }
 }
 
-#[must_use]
-pub fn gen_upd_cmd(command: &str, args: &[String], use_sudo: bool) -> Command {
-    #[cfg(target_family = "windows")]
-    {
-        let mut cmd = if use_sudo {
-            let mut c = Command::new("runas");
-            c.arg("/user:Administrator")
-                .arg(format!("{} {}", command, args.join(" ")));
-            c
-        } else {
-            Command::new(command)
-        };
-        cmd.args(args)
-            .stdout(Stdio::inherit())
-            .stderr(Stdio::inherit())
-            .stdin(Stdio::inherit());
-        cmd
+/// Gets the privileges for the privileged steps of `plan` once up front and keeps them until the
+/// run ends, or exits if that is impossible. Dry runs need no privileges.
+fn authenticate(plan: &UpdatePlan, config: &Config) -> Option<Keepalive> {
+    if config.dry_run {
+        return None;
     }
-    #[cfg(not(target_os = "windows"))]
-    {
-        let mut cmd = if use_sudo {
-            let mut c = Command::new("sudo");
-            c.arg(command);
-            c
-        } else {
-            Command::new(command)
-        };
-        cmd.args(args)
-            .stdout(Stdio::inherit())
-            .stderr(Stdio::inherit())
-            .stdin(Stdio::inherit());
-        cmd
+    match escalation::authenticate(plan, config.auto) {
+        Ok(keepalive) => keepalive,
+        Err(e) => {
+            eprintln!("{} {e}", "ERR:".red());
+            ExitCode::Failure.exit();
+        }
+    }
+}
+
+/// Flushes a prompt written with [`say!`].
+/// Reads the answer to a prompt, or `None` if stdin cannot be read, which counts as "no".
+fn answer() -> Option<String> {
+    let mut input = String::new();
+    std::io::stdin().read_line(&mut input).ok()?;
+    Some(input)
+}
+
+fn flush() {
+    if output::is_machine() {
+        std::io::stderr().flush().unwrap();
+    } else {
+        std::io::stdout().flush().unwrap();
     }
 }
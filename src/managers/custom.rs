@@ -0,0 +1,209 @@
+//! Package managers defined in configuration files.
+//!
+//! ```toml
+//! [managers.mytool]
+//! executable = "mytool"       # searched for in PATH, defaults to the table name
+//! sudo = true                 # default for all steps
+//! # scope = "user"            # updates the user's home directory instead; needs sudo = false
+//! markers = ["mytool.lock"]   # only run in directories containing one of these files
+//! exclude = "--skip {pkg}"    # repeated per package; "{pkgs}" expands to a comma separated list
+//!
+//! [[managers.mytool.steps]]
+//! args = ["sync"]
+//! refresh = true
+//!
+//! [[managers.mytool.steps]]
+//! args = ["upgrade"]
+//! auto_args = ["upgrade", "--yes"]
+//! idempotent = true           # safe to retry (--retries); refresh steps always are
+//! ```
+
+use super::{Ctx, PackageManager, Scope, Step};
+use crate::helpers::p_cont;
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+/// A package manager described in a configuration file.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CustomManager {
+    /// Taken from the table name.
+    #[serde(skip)]
+    pub name: String,
+    /// The executable searched for in PATH. Defaults to the name.
+    pub executable: Option<String>,
+    /// Whether steps run with elevated privileges unless they say otherwise.
+    #[serde(default)]
+    pub sudo: bool,
+    /// What the manager updates when it has no markers.
+    #[serde(default)]
+    pub scope: CustomScope,
+    /// Files marking a project handled by this manager. Makes the manager project-scoped.
+    #[serde(default)]
+    pub markers: Vec<String>,
+    /// Whether the manager updates packages of the operating system, so a configured snapshot is
+    /// taken first.
+    #[serde(default)]
+    pub snapshot: bool,
+    /// Exclusion arguments. `{pkg}` repeats the arguments for every package,
+    /// `{pkgs}` expands to all packages joined with commas.
+    pub exclude: Option<String>,
+    /// The commands to run, in order.
+    pub steps: Vec<CustomStep>,
+}
+
+/// The scope of a [`CustomManager`] without markers.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CustomScope {
+    /// Packages of the operating system, or installed for all users.
+    #[default]
+    System,
+    /// Packages in the user's home directory: run as the invoking user under sudo, and for every
+    /// user with `--all-users`.
+    User,
+}
+
+/// A command run by a [`CustomManager`].
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CustomStep {
+    /// The program to run. Defaults to the detected executable.
+    pub program: Option<String>,
+    #[serde(default)]
+    pub args: Vec<String>,
+    /// Arguments used instead of `args` in auto mode.
+    pub auto_args: Option<Vec<String>>,
+    /// Overrides the manager's `sudo` setting.
+    pub sudo: Option<bool>,
+    /// Whether the step refreshes package metadata instead of upgrading.
+    #[serde(default)]
+    pub refresh: bool,
+    /// Whether the step may be retried after a failure. Refresh steps always may.
+    #[serde(default)]
+    pub idempotent: bool,
+}
+
+impl CustomManager {
+    /// Checks that the definition makes sense as a whole.
+    ///
+    /// # Errors
+    /// Returns a message if a user-level manager has privileged steps, which would run as root
+    /// in the home of the user it updates.
+    pub fn check(&self) -> Result<(), String> {
+        let privileged = self.steps.iter().any(|step| step.sudo.unwrap_or(self.sudo));
+        if self.scope() == Scope::User && privileged {
+            return Err(format!(
+                "managers.{}: a manager with scope = \"user\" cannot have sudo steps, they would run as root in the user's home.",
+                self.name
+            ));
+        }
+        Ok(())
+    }
+
+    fn steps(&self, ctx: &Ctx, refresh: bool) -> Vec<Step> {
+        self.steps
+            .iter()
+            .filter(|step| step.refresh == refresh)
+            .map(|step| {
+                let args = match &step.auto_args {
+                    Some(auto_args) if ctx.auto => auto_args,
+                    _ => &step.args,
+                };
+                let args: Vec<&str> = args.iter().map(String::as_str).collect();
+                let mut built = match &step.program {
+                    Some(program) => ctx.run(program, &args),
+                    None => ctx.step(&args),
+                };
+                built.sudo = step.sudo.unwrap_or(self.sudo);
+                built.idempotent = step.idempotent;
+                built
+            })
+            .collect()
+    }
+}
+
+impl PackageManager for CustomManager {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn executable(&self) -> &str {
+        self.executable.as_deref().unwrap_or(&self.name)
+    }
+
+    fn scope(&self) -> Scope {
+        if !self.markers.is_empty() {
+            Scope::Project
+        } else if self.scope == CustomScope::User {
+            Scope::User
+        } else {
+            Scope::System
+        }
+    }
+
+    fn needs_privilege(&self) -> bool {
+        self.sudo
+    }
+
+    fn snapshot(&self) -> bool {
+        self.snapshot
+    }
+
+    fn applies(&self, dir: &Path) -> bool {
+        self.markers
+            .iter()
+            .any(|marker| p_cont(dir, marker).unwrap_or(false))
+    }
+
+    fn refresh(&self, ctx: &Ctx) -> Vec<Step> {
+        self.steps(ctx, true)
+    }
+
+    fn upgrade(&self, ctx: &Ctx) -> Vec<Step> {
+        self.steps(ctx, false)
+    }
+
+    fn exclusion_args(&self, pkgs: &[String]) -> Option<Vec<String>> {
+        let template = self.exclude.as_ref()?;
+        let words: Vec<&str> = template.split_whitespace().collect();
+        if template.contains("{pkgs}") {
+            let joined = pkgs.join(",");
+            Some(words.iter().map(|w| w.replace("{pkgs}", &joined)).collect())
+        } else {
+            Some(
+                pkgs.iter()
+                    .flat_map(|pkg| words.iter().map(move |w| w.replace("{pkg}", pkg)))
+                    .collect(),
+            )
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn manager(definition: &str) -> CustomManager {
+        let mut manager: CustomManager = toml::from_str(definition).unwrap();
+        manager.name = "mytool".to_string();
+        manager
+    }
+
+    #[test]
+    fn user_scope_without_sudo() {
+        let user = manager("scope = \"user\"\nsteps = [{ args = [\"upgrade\"] }]");
+        assert_eq!(user.scope(), Scope::User);
+        assert!(user.check().is_ok());
+    }
+
+    #[test]
+    fn user_scope_rejects_sudo() {
+        let default = manager("scope = \"user\"\nsudo = true\nsteps = [{ args = [\"upgrade\"] }]");
+        assert!(default.check().is_err());
+        let step = manager("scope = \"user\"\nsteps = [{ args = [\"upgrade\"], sudo = true }]");
+        assert!(step.check().is_err());
+        let system = manager("sudo = true\nsteps = [{ args = [\"upgrade\"] }]");
+        assert!(system.check().is_ok());
+    }
+}
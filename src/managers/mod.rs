@@ -0,0 +1,450 @@
+//! Package manager adapters.
+//!
+//! Every supported package manager is a type implementing [`PackageManager`], registered once in
+//! [`Registry::builtin`]. Detection, command building and exclusion handling all go through the
+//! registry, so adding or fixing an adapter only touches its own implementation.
+
+mod custom;
+mod project;
+mod system;
+mod tools;
+mod windows;
+
+use serde::Serialize;
+use std::path::Path;
+
+pub use custom::{CustomManager, CustomScope};
+
+/// What a package manager updates.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Scope {
+    /// Packages of the operating system, or installed for all users.
+    System,
+    /// Packages and toolchains in the user's home directory, independent of the working directory.
+    User,
+    /// Dependencies of the project in the working directory.
+    Project,
+}
+
+/// A single command to run as part of an update.
+#[derive(Debug, Clone)]
+pub struct Step {
+    pub program: String,
+    pub args: Vec<String>,
+    /// Run the command with elevated privileges.
+    pub sudo: bool,
+    /// Whether running the command again after a failure is safe, so it may be retried.
+    /// Refresh, hold and release steps are always considered idempotent.
+    pub idempotent: bool,
+}
+
+impl Step {
+    /// Marks the step as safe to retry.
+    #[must_use]
+    pub fn idempotent(mut self) -> Step {
+        self.idempotent = true;
+        self
+    }
+
+    /// Runs the step with elevated privileges, whatever the package manager's default.
+    #[must_use]
+    pub fn privileged(mut self) -> Step {
+        self.sudo = true;
+        self
+    }
+
+    /// Appends `args`, e.g. the names of packages.
+    #[must_use]
+    pub fn with_args(mut self, args: &[String]) -> Step {
+        self.args.extend_from_slice(args);
+        self
+    }
+}
+
+/// How a package manager leaves excluded packages out of an upgrade.
+#[derive(Debug, Clone)]
+pub enum Exclusion {
+    /// Arguments added to the upgrade commands, like pacman's `--ignore`.
+    Args(Vec<String>),
+    /// Commands replacing the upgrade commands, upgrading every package but the excluded ones.
+    Upgrade(Vec<Step>),
+    /// Commands holding the excluded packages before the upgrade, like `apt-mark hold`, and
+    /// releasing them afterwards, even if the upgrade failed.
+    Hold { hold: Vec<Step>, release: Vec<Step> },
+}
+
+/// A package with a newer version available.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Pending {
+    pub name: String,
+    /// The installed version, if the package manager reports it.
+    pub current: Option<String>,
+    /// The version an upgrade would install, if the package manager reports it.
+    pub latest: Option<String>,
+}
+
+/// A read-only command listing pending updates, and how to read its output.
+pub struct Query {
+    pub step: Step,
+    /// Exit codes besides 0 meaning the command worked, e.g. 100 for `dnf check-update` when
+    /// updates are available.
+    pub ok_codes: &'static [i32],
+    /// Whether the updates are listed on stderr instead of stdout.
+    pub stderr: bool,
+    /// Extracts the pending updates from the output.
+    pub parse: fn(&str) -> Vec<Pending>,
+}
+
+impl Query {
+    #[must_use]
+    pub fn new(step: Step, parse: fn(&str) -> Vec<Pending>) -> Query {
+        Query {
+            step,
+            ok_codes: &[],
+            stderr: false,
+            parse,
+        }
+    }
+
+    /// Accepts `codes` as successful exit codes.
+    #[must_use]
+    pub fn ok_codes(mut self, codes: &'static [i32]) -> Query {
+        self.ok_codes = codes;
+        self
+    }
+
+    /// Reads the updates from stderr.
+    #[must_use]
+    pub fn stderr(mut self) -> Query {
+        self.stderr = true;
+        self
+    }
+}
+
+/// How a package manager marks its lock as taken.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LockKind {
+    /// The file exists while the lock is held, like pacman's `db.lck`. A file left behind by a
+    /// crashed process keeps the lock taken until it is removed.
+    File,
+    /// An `fcntl` or `flock` lock on a file that always exists, like dpkg's `lock-frontend`.
+    /// Released by the operating system when its holder exits.
+    Advisory,
+    /// A file containing the pid of the holder, like zypper's `zypp.pid`. Package managers ignore
+    /// it once that process is gone.
+    PidFile,
+}
+
+/// A lock a package manager takes while it changes packages.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct NativeLock {
+    pub path: &'static str,
+    pub kind: LockKind,
+    /// The process name of the package manager, for finding the holder of a [`LockKind::File`]
+    /// lock when its open files cannot be inspected.
+    pub program: &'static str,
+}
+
+impl NativeLock {
+    #[must_use]
+    pub const fn new(path: &'static str, kind: LockKind, program: &'static str) -> NativeLock {
+        NativeLock {
+            path,
+            kind,
+            program,
+        }
+    }
+}
+
+/// Information available to a package manager when building its commands.
+pub struct Ctx<'a> {
+    /// The resolved executable of the package manager.
+    pub exe: &'a Path,
+    /// Whether non-interactive flags should be used.
+    pub auto: bool,
+    /// The directory updates are run in.
+    pub dir: &'a Path,
+    /// Whether commands run with elevated privileges by default.
+    pub sudo: bool,
+}
+
+impl Ctx<'_> {
+    /// Builds a step running the package manager's own executable.
+    #[must_use]
+    pub fn step(&self, args: &[&str]) -> Step {
+        self.run(&self.exe.display().to_string(), args)
+    }
+
+    /// Builds a step running `program`, for managers driven through helper executables.
+    #[must_use]
+    pub fn run(&self, program: &str, args: &[&str]) -> Step {
+        Step {
+            program: program.to_string(),
+            args: args.iter().map(ToString::to_string).collect(),
+            sudo: self.sudo,
+            idempotent: false,
+        }
+    }
+
+    /// Picks `auto` or `manual` depending on whether auto mode is enabled.
+    #[must_use]
+    pub fn pick<'s>(&self, auto: &'s [&'s str], manual: &'s [&'s str]) -> &'s [&'s str] {
+        if self.auto {
+            auto
+        } else {
+            manual
+        }
+    }
+}
+
+/// An adapter for a single package manager.
+pub trait PackageManager {
+    /// The name used to refer to the package manager (`--only`, `--excl`, `--spec`, ...).
+    fn name(&self) -> &str;
+
+    /// The executable file name searched for in PATH.
+    fn executable(&self) -> &str {
+        self.name()
+    }
+
+    /// Whether the package manager works on the system, on the user's home directory or on the
+    /// project in the working directory.
+    fn scope(&self) -> Scope {
+        Scope::System
+    }
+
+    /// Whether the update commands need elevated privileges.
+    fn needs_privilege(&self) -> bool {
+        false
+    }
+
+    /// Whether updates change the packages of the operating system, so a configured filesystem
+    /// snapshot is taken first.
+    fn snapshot(&self) -> bool {
+        false
+    }
+
+    /// Whether there is anything to update in `dir`. Only consulted for project-scoped managers.
+    fn applies(&self, _dir: &Path) -> bool {
+        true
+    }
+
+    /// Commands refreshing package metadata before upgrading.
+    fn refresh(&self, _ctx: &Ctx) -> Vec<Step> {
+        Vec::new()
+    }
+
+    /// Commands upgrading installed packages.
+    fn upgrade(&self, ctx: &Ctx) -> Vec<Step>;
+
+    /// A read-only command listing pending updates. Runs without elevated privileges.
+    /// Returns `None` if the package manager cannot list them.
+    fn outdated(&self, _ctx: &Ctx) -> Option<Query> {
+        None
+    }
+
+    /// Where the package manager keeps what a user installed, relative to their home directory.
+    /// With `--all-users`, a user-level package manager installed outside a user's home is only
+    /// updated for the users who have one of these.
+    fn home_dirs(&self) -> &'static [&'static str] {
+        &[]
+    }
+
+    /// The locks the package manager takes, checked before it runs so that qud can wait for
+    /// another program holding them.
+    fn locks(&self) -> &'static [NativeLock] {
+        &[]
+    }
+
+    /// Translates package exclusions into arguments for the upgrade commands.
+    /// Returns `None` if the package manager cannot exclude packages this way.
+    fn exclusion_args(&self, _pkgs: &[String]) -> Option<Vec<String>> {
+        None
+    }
+
+    /// How the upgrade leaves out the packages in `pkgs`, by default with
+    /// [`exclusion_args`](PackageManager::exclusion_args). Returns `None` if the package manager
+    /// cannot exclude packages.
+    fn exclusion(&self, _ctx: &Ctx, pkgs: &[String]) -> Option<Exclusion> {
+        self.exclusion_args(pkgs).map(Exclusion::Args)
+    }
+
+    /// A read-only command listing the packages the user holds, for package managers excluding
+    /// packages with [`Exclusion::Hold`]. Excluded packages that are held already are neither held
+    /// again nor released afterwards. Only the names of the listed packages are read.
+    fn held(&self, _ctx: &Ctx) -> Option<Query> {
+        None
+    }
+}
+
+/// The set of known package managers.
+pub struct Registry {
+    managers: Vec<Box<dyn PackageManager>>,
+}
+
+impl Registry {
+    /// Creates a registry without any package managers.
+    #[must_use]
+    pub fn empty() -> Registry {
+        Registry {
+            managers: Vec::new(),
+        }
+    }
+
+    /// Creates a registry with every built-in package manager.
+    #[must_use]
+    pub fn builtin() -> Registry {
+        let mut registry = Registry::empty();
+        system::register(&mut registry);
+        windows::register(&mut registry);
+        tools::register(&mut registry);
+        project::register(&mut registry);
+        registry
+    }
+
+    /// Adds a package manager, replacing any existing one with the same name.
+    pub fn register(&mut self, manager: Box<dyn PackageManager>) {
+        if let Some(existing) = self
+            .managers
+            .iter_mut()
+            .find(|m| m.name() == manager.name())
+        {
+            *existing = manager;
+        } else {
+            self.managers.push(manager);
+        }
+    }
+
+    /// Looks up a package manager by name.
+    #[must_use]
+    pub fn get(&self, name: &str) -> Option<&dyn PackageManager> {
+        self.managers
+            .iter()
+            .find(|m| m.name() == name)
+            .map(AsRef::as_ref)
+    }
+
+    /// Looks up a package manager by the file name of its executable.
+    /// If several share an executable, the one registered last wins.
+    #[must_use]
+    pub fn by_executable(&self, file_name: &str) -> Option<&dyn PackageManager> {
+        self.managers
+            .iter()
+            .rev()
+            .find(|m| m.executable() == file_name)
+            .map(AsRef::as_ref)
+    }
+
+    /// All registered package managers.
+    pub fn all(&self) -> impl Iterator<Item = &dyn PackageManager> {
+        self.managers.iter().map(AsRef::as_ref)
+    }
+
+    /// The executable names of all registered package managers.
+    #[must_use]
+    pub fn executables(&self) -> Vec<&str> {
+        self.managers.iter().map(|m| m.executable()).collect()
+    }
+}
+
+/// Builds a pending update from optional version strings.
+fn pending(name: &str, current: Option<&str>, latest: Option<&str>) -> Pending {
+    Pending {
+        name: name.to_string(),
+        current: current.map(ToString::to_string),
+        latest: latest.map(ToString::to_string),
+    }
+}
+
+/// Parses lines of the form `name current -> latest`, as printed by `pacman -Qu` and its kin.
+/// Packages marked `[ignored]` are left out, since the upgrade skips them.
+fn arrow_list(output: &str) -> Vec<Pending> {
+    output
+        .lines()
+        .filter_map(
+            |line| match line.split_whitespace().collect::<Vec<_>>()[..] {
+                [.., "[ignored]"] => None,
+                [name, current, "->", latest, ..] => {
+                    Some(pending(name, Some(current), Some(latest)))
+                }
+                _ => None,
+            },
+        )
+        .collect()
+}
+
+/// Splits `name-1.2.3` at the first hyphen followed by a digit, as Portage and Nix name packages.
+fn split_version(package: &str) -> (&str, Option<&str>) {
+    package
+        .match_indices('-')
+        .find(|(i, _)| package[i + 1..].starts_with(|c: char| c.is_ascii_digit()))
+        .map_or((package, None), |(i, _)| {
+            (&package[..i], Some(&package[i + 1..]))
+        })
+}
+
+/// Parses a list with the name of a package at the start of every line.
+fn name_list(output: &str) -> Vec<Pending> {
+    output
+        .lines()
+        .filter_map(|line| line.split_whitespace().next())
+        .map(|name| pending(name, None, None))
+        .collect()
+}
+
+/// Holds `pkgs` with `hold` and releases them with `release`, both followed by the packages.
+fn hold(ctx: &Ctx, hold: &[&str], release: &[&str], pkgs: &[String]) -> Exclusion {
+    Exclusion::Hold {
+        hold: vec![ctx.step(hold).with_args(pkgs)],
+        release: vec![ctx.step(release).with_args(pkgs)],
+    }
+}
+
+/// Joins exclusions into a single comma separated argument after `flag`, without spaces.
+fn joined_exclusions(flag: &str, pkgs: &[String]) -> Vec<String> {
+    vec![flag.to_string(), pkgs.join(",")]
+}
+
+/// Repeats `flag` before every excluded package.
+fn repeated_exclusions(flag: &str, pkgs: &[String]) -> Vec<String> {
+    pkgs.iter()
+        .flat_map(|pkg| [flag.to_string(), pkg.clone()])
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn arrow_list_skips_ignored() {
+        let output = "linux 6.6.1.arch1-1 -> 6.6.2.arch1-1\nvim 9.0.2-1 -> 9.1.0-1 [ignored]\n";
+        assert_eq!(
+            arrow_list(output),
+            vec![pending(
+                "linux",
+                Some("6.6.1.arch1-1"),
+                Some("6.6.2.arch1-1")
+            )]
+        );
+    }
+
+    #[test]
+    fn arrow_list_skips_other_lines() {
+        let output = ":: Synchronizing package databases...\n\nwarning: something\nlinux\n";
+        assert!(arrow_list(output).is_empty());
+    }
+
+    #[test]
+    fn exclusion_flags() {
+        let pkgs = ["vim".to_string(), "linux".to_string()];
+        assert_eq!(
+            joined_exclusions("--ignore", &pkgs),
+            ["--ignore", "vim,linux"]
+        );
+        assert_eq!(
+            repeated_exclusions("--exclude", &pkgs),
+            ["--exclude", "vim", "--exclude", "linux"]
+        );
+    }
+}
@@ -0,0 +1,523 @@
+//! Package managers updating the dependencies of the project in the working directory.
+
+use super::{pending, Ctx, Exclusion, PackageManager, Pending, Query, Registry, Scope, Step};
+use crate::helpers::{p_cont, p_cont_ext};
+use std::fs;
+use std::path::Path;
+
+pub(super) fn register(registry: &mut Registry) {
+    registry.register(Box::new(Cargo));
+    registry.register(Box::new(Npm));
+    registry.register(Box::new(Pip));
+    registry.register(Box::new(Poetry));
+    registry.register(Box::new(Composer));
+    registry.register(Box::new(Nuget));
+    registry.register(Box::new(Conan));
+    registry.register(Box::new(Stack));
+    registry.register(Box::new(Mix));
+    registry.register(Box::new(Pnpm));
+    registry.register(Box::new(Yarn));
+    registry.register(Box::new(Maven));
+    registry.register(Box::new(Go));
+}
+
+fn has(dir: &Path, file_name: &str) -> bool {
+    p_cont(dir, file_name).unwrap_or(false)
+}
+
+struct Cargo;
+
+impl PackageManager for Cargo {
+    fn name(&self) -> &str {
+        "cargo"
+    }
+
+    fn scope(&self) -> Scope {
+        Scope::Project
+    }
+
+    fn applies(&self, dir: &Path) -> bool {
+        has(dir, "Cargo.toml")
+    }
+
+    fn upgrade(&self, ctx: &Ctx) -> Vec<Step> {
+        vec![ctx.step(&["update"])]
+    }
+
+    fn outdated(&self, ctx: &Ctx) -> Option<Query> {
+        Some(Query::new(ctx.step(&["update", "--dry-run"]), cargo_dry_run).stderr())
+    }
+}
+
+struct Npm;
+
+impl PackageManager for Npm {
+    fn name(&self) -> &str {
+        "npm"
+    }
+
+    fn scope(&self) -> Scope {
+        Scope::Project
+    }
+
+    fn applies(&self, dir: &Path) -> bool {
+        has(dir, "package.json")
+    }
+
+    fn upgrade(&self, ctx: &Ctx) -> Vec<Step> {
+        vec![ctx.step(&["update"])]
+    }
+
+    fn outdated(&self, ctx: &Ctx) -> Option<Query> {
+        Some(Query::new(ctx.step(&["outdated", "--parseable"]), npm_outdated).ok_codes(&[1]))
+    }
+
+    /// Updates the dependencies in `package.json` by name, leaving out the excluded ones.
+    fn exclusion(&self, ctx: &Ctx, pkgs: &[String]) -> Option<Exclusion> {
+        let manifest = fs::read_to_string(ctx.dir.join("package.json")).ok()?;
+        let manifest: serde_json::Value = serde_json::from_str(&manifest).ok()?;
+        let names: Vec<String> = ["dependencies", "devDependencies", "optionalDependencies"]
+            .iter()
+            .filter_map(|key| manifest.get(key)?.as_object())
+            .flat_map(|deps| deps.keys())
+            .filter(|name| !pkgs.contains(name))
+            .cloned()
+            .collect();
+        if names.is_empty() {
+            return Some(Exclusion::Upgrade(Vec::new()));
+        }
+        Some(Exclusion::Upgrade(vec![ctx
+            .step(&["update"])
+            .with_args(&names)]))
+    }
+}
+
+struct Pip;
+
+impl PackageManager for Pip {
+    fn name(&self) -> &str {
+        "pip"
+    }
+
+    fn scope(&self) -> Scope {
+        Scope::Project
+    }
+
+    fn applies(&self, dir: &Path) -> bool {
+        has(dir, "requirements.txt")
+    }
+
+    fn upgrade(&self, ctx: &Ctx) -> Vec<Step> {
+        vec![ctx.step(&["install", "--upgrade", "-r", "requirements.txt"])]
+    }
+
+    /// Upgrades the requirements of `requirements.txt` one by one, leaving out the excluded ones.
+    /// Options are passed on, so requirements of files included with `-r` are all upgraded.
+    fn exclusion(&self, ctx: &Ctx, pkgs: &[String]) -> Option<Exclusion> {
+        let requirements = fs::read_to_string(ctx.dir.join("requirements.txt")).ok()?;
+        let steps = match requirement_args(&requirements, pkgs) {
+            Some(args) => vec![ctx.step(&["install", "--upgrade"]).with_args(&args)],
+            None => Vec::new(),
+        };
+        Some(Exclusion::Upgrade(steps))
+    }
+}
+
+/// The arguments of `pip install --upgrade` for the lines of a requirements file, without the
+/// requirements of `excluded` packages. `None` if nothing would be upgraded.
+fn requirement_args(requirements: &str, excluded: &[String]) -> Option<Vec<String>> {
+    let excluded: Vec<String> = excluded.iter().map(|pkg| normalize(pkg)).collect();
+    let mut args = Vec::new();
+    let mut upgraded = false;
+    for line in requirements.lines() {
+        let line = line.split(" #").next().unwrap_or_default().trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        if line.starts_with('-') {
+            // Included requirement files and editable installs are upgraded as a whole.
+            upgraded |= ["-r", "--requirement", "-e", "--editable"]
+                .iter()
+                .any(|option| line.starts_with(option));
+            args.extend(line.split_whitespace().map(ToString::to_string));
+            continue;
+        }
+        let name = line
+            .split(|c: char| !(c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.')))
+            .next()
+            .unwrap_or_default();
+        if !excluded.contains(&normalize(name)) {
+            args.push(line.to_string());
+            upgraded = true;
+        }
+    }
+    upgraded.then_some(args)
+}
+
+/// Normalizes the name of a Python package, which ignores case and treats runs of `-`, `_` and `.`
+/// alike.
+fn normalize(name: &str) -> String {
+    name.to_lowercase()
+        .split(['-', '_', '.'])
+        .filter(|part| !part.is_empty())
+        .collect::<Vec<_>>()
+        .join("-")
+}
+
+struct Poetry;
+
+impl PackageManager for Poetry {
+    fn name(&self) -> &str {
+        "poetry"
+    }
+
+    fn scope(&self) -> Scope {
+        Scope::Project
+    }
+
+    fn applies(&self, dir: &Path) -> bool {
+        has(dir, "pyproject.toml")
+    }
+
+    fn upgrade(&self, ctx: &Ctx) -> Vec<Step> {
+        vec![ctx.step(&["update"])]
+    }
+}
+
+struct Composer;
+
+impl PackageManager for Composer {
+    fn name(&self) -> &str {
+        "composer"
+    }
+
+    fn scope(&self) -> Scope {
+        Scope::Project
+    }
+
+    fn applies(&self, dir: &Path) -> bool {
+        has(dir, "composer.json")
+    }
+
+    fn upgrade(&self, ctx: &Ctx) -> Vec<Step> {
+        vec![ctx.step(&["update"])]
+    }
+}
+
+/// NuGet, updating either `packages.config` or the first solution file found.
+struct Nuget;
+
+impl PackageManager for Nuget {
+    fn name(&self) -> &str {
+        "nuget"
+    }
+
+    fn scope(&self) -> Scope {
+        Scope::Project
+    }
+
+    fn applies(&self, dir: &Path) -> bool {
+        has(dir, "packages.config") || matches!(p_cont_ext(dir, ".sln"), Some(Ok(_)))
+    }
+
+    fn upgrade(&self, ctx: &Ctx) -> Vec<Step> {
+        if has(ctx.dir, "packages.config") {
+            vec![ctx.step(&["update", "packages.config"])]
+        } else if let Some(Ok(f)) = p_cont_ext(ctx.dir, ".sln") {
+            vec![ctx.step(&["update", &f])]
+        } else {
+            Vec::new()
+        }
+    }
+}
+
+struct Conan;
+
+impl PackageManager for Conan {
+    fn name(&self) -> &str {
+        "conan"
+    }
+
+    fn scope(&self) -> Scope {
+        Scope::Project
+    }
+
+    fn applies(&self, dir: &Path) -> bool {
+        has(dir, "conanfile.txt") || has(dir, "conanfile.py")
+    }
+
+    fn upgrade(&self, ctx: &Ctx) -> Vec<Step> {
+        vec![ctx.step(&["install", ".", "--update"])]
+    }
+}
+
+/// Haskell Stack.
+struct Stack;
+
+impl PackageManager for Stack {
+    fn name(&self) -> &str {
+        "stack"
+    }
+
+    fn scope(&self) -> Scope {
+        Scope::Project
+    }
+
+    fn applies(&self, dir: &Path) -> bool {
+        has(dir, "stack.yaml")
+    }
+
+    fn refresh(&self, ctx: &Ctx) -> Vec<Step> {
+        vec![ctx.step(&["update"])]
+    }
+
+    fn upgrade(&self, ctx: &Ctx) -> Vec<Step> {
+        vec![ctx.step(&["upgrade"])]
+    }
+}
+
+/// Elixir's Mix.
+struct Mix;
+
+impl PackageManager for Mix {
+    fn name(&self) -> &str {
+        "mix"
+    }
+
+    fn scope(&self) -> Scope {
+        Scope::Project
+    }
+
+    fn applies(&self, dir: &Path) -> bool {
+        has(dir, "mix.exs")
+    }
+
+    fn upgrade(&self, ctx: &Ctx) -> Vec<Step> {
+        vec![ctx.step(&["deps.update", "--all"])]
+    }
+}
+
+struct Pnpm;
+
+impl PackageManager for Pnpm {
+    fn name(&self) -> &str {
+        "pnpm"
+    }
+
+    fn scope(&self) -> Scope {
+        Scope::Project
+    }
+
+    fn applies(&self, dir: &Path) -> bool {
+        has(dir, "package.json")
+    }
+
+    fn upgrade(&self, ctx: &Ctx) -> Vec<Step> {
+        vec![ctx.step(&["update"])]
+    }
+}
+
+struct Yarn;
+
+impl PackageManager for Yarn {
+    fn name(&self) -> &str {
+        "yarn"
+    }
+
+    fn scope(&self) -> Scope {
+        Scope::Project
+    }
+
+    fn applies(&self, dir: &Path) -> bool {
+        has(dir, "yarn.lock")
+    }
+
+    fn upgrade(&self, ctx: &Ctx) -> Vec<Step> {
+        vec![ctx.step(&["upgrade"])]
+    }
+}
+
+/// Maven, driven through `mvn` and the versions plugin.
+struct Maven;
+
+impl PackageManager for Maven {
+    fn name(&self) -> &str {
+        "maven"
+    }
+
+    fn scope(&self) -> Scope {
+        Scope::Project
+    }
+
+    fn applies(&self, dir: &Path) -> bool {
+        has(dir, "pom.xml")
+    }
+
+    fn upgrade(&self, ctx: &Ctx) -> Vec<Step> {
+        vec![ctx.run(
+            "mvn",
+            ctx.pick(
+                &["versions:use-latest-releases"],
+                &["versions:display-dependency-updates"],
+            ),
+        )]
+    }
+}
+
+struct Go;
+
+impl PackageManager for Go {
+    fn name(&self) -> &str {
+        "go"
+    }
+
+    fn scope(&self) -> Scope {
+        Scope::Project
+    }
+
+    fn applies(&self, dir: &Path) -> bool {
+        has(dir, "go.mod")
+    }
+
+    fn upgrade(&self, ctx: &Ctx) -> Vec<Step> {
+        vec![ctx.step(&["get", "-u", "./..."])]
+    }
+}
+
+/// Parses `cargo update --dry-run`: `Updating name vcurrent -> vlatest`, which newer versions of
+/// Cargo follow with a note such as `(available: v2.0.0)`.
+fn cargo_dry_run(output: &str) -> Vec<Pending> {
+    output
+        .lines()
+        .filter_map(
+            |line| match line.split_whitespace().collect::<Vec<_>>()[..] {
+                ["Updating", name, current, "->", latest, ..] => Some(pending(
+                    name,
+                    Some(current.trim_start_matches('v')),
+                    Some(latest.trim_start_matches('v')),
+                )),
+                _ => None,
+            },
+        )
+        .collect()
+}
+
+/// Parses `npm outdated --parseable`: `path:name@wanted:name@current:name@latest:dependent`.
+/// Reports the wanted version, which is what `npm update` installs.
+fn npm_outdated(output: &str) -> Vec<Pending> {
+    let version = |field: &str| field.rsplit_once('@').map(|(_, v)| v.to_string());
+    output
+        .lines()
+        .filter_map(|line| {
+            let fields: Vec<&str> = line.split(':').collect();
+            let wanted = fields.get(1)?;
+            let (name, _) = wanted.rsplit_once('@')?;
+            Some(Pending {
+                name: name.to_string(),
+                current: fields.get(2).and_then(|f| version(f)),
+                latest: version(wanted),
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cargo_dry_run_reads_updates() {
+        let output = "    Updating crates.io index
+     Locking 2 packages to latest compatible versions
+    Updating serde v1.0.190 -> v1.0.219
+    Updating toml v0.8.2 -> v0.8.23 (available: v0.9.5)
+      Adding windows-sys v0.59.0
+warning: not updating lockfile due to dry run
+";
+        assert_eq!(
+            cargo_dry_run(output),
+            vec![
+                pending("serde", Some("1.0.190"), Some("1.0.219")),
+                pending("toml", Some("0.8.2"), Some("0.8.23")),
+            ]
+        );
+    }
+
+    #[test]
+    fn npm_outdated_reports_wanted() {
+        let output = "/p/node_modules/lodash:lodash@4.17.21:lodash@4.17.20:lodash@4.17.21:p
+/p/node_modules/@types/node:@types/node@20.19.9:@types/node@20.1.0:@types/node@24.1.0:p
+";
+        assert_eq!(
+            npm_outdated(output),
+            vec![
+                pending("lodash", Some("4.17.20"), Some("4.17.21")),
+                pending("@types/node", Some("20.1.0"), Some("20.19.9")),
+            ]
+        );
+    }
+
+    #[test]
+    fn normalize_python_names() {
+        assert_eq!(normalize("Django"), "django");
+        assert_eq!(normalize("typing_extensions"), "typing-extensions");
+        assert_eq!(normalize("zope.interface"), "zope-interface");
+        assert_eq!(normalize("Foo__-.Bar"), "foo-bar");
+    }
+
+    #[test]
+    fn requirement_args_leave_out_excluded() {
+        let requirements = "# pinned
+Django>=4.2  # web
+requests[socks]==2.32.3
+typing_extensions
+numpy; python_version >= \"3.9\"
+";
+        let excluded = ["django".to_string(), "Typing-Extensions".to_string()];
+        assert_eq!(
+            requirement_args(requirements, &excluded),
+            Some(vec![
+                "requests[socks]==2.32.3".to_string(),
+                "numpy; python_version >= \"3.9\"".to_string(),
+            ])
+        );
+    }
+
+    #[test]
+    fn requirement_args_pass_options_on() {
+        let requirements = "--index-url https://example.org/simple\n-r base.txt\nrequests\n";
+        let args = requirement_args(requirements, &["requests".to_string()]);
+        assert_eq!(
+            args,
+            Some(
+                [
+                    "--index-url",
+                    "https://example.org/simple",
+                    "-r",
+                    "base.txt"
+                ]
+                .map(String::from)
+                .to_vec()
+            )
+        );
+    }
+
+    #[test]
+    fn requirement_args_nothing_left() {
+        let requirements = "--index-url https://example.org/simple\nrequests\n";
+        assert_eq!(
+            requirement_args(requirements, &["requests".to_string()]),
+            None
+        );
+    }
+
+    #[test]
+    fn npm_outdated_without_current() {
+        // Packages missing from node_modules have no current version.
+        let output = "/p/node_modules/left-pad:left-pad@1.3.0:MISSING:left-pad@1.3.0:p\n";
+        assert_eq!(
+            npm_outdated(output),
+            vec![pending("left-pad", None, Some("1.3.0"))]
+        );
+    }
+}
@@ -0,0 +1,888 @@
+//! System package managers of Linux distributions, the BSDs and macOS.
+
+use super::{
+    arrow_list, hold, joined_exclusions, name_list, pending, repeated_exclusions, split_version,
+    Ctx, Exclusion, LockKind, NativeLock, PackageManager, Pending, Query, Registry, Scope, Step,
+};
+use crate::helpers::find_in_path;
+
+pub(super) fn register(registry: &mut Registry) {
+    registry.register(Box::new(Pacman));
+    registry.register(Box::new(Yay));
+    registry.register(Box::new(Apt("apt")));
+    registry.register(Box::new(Apt("apt-get")));
+    registry.register(Box::new(Dnf));
+    registry.register(Box::new(Zypper));
+    registry.register(Box::new(Snap));
+    // Before flatpak, which detection picks for the shared executable.
+    registry.register(Box::new(FlatpakUser));
+    registry.register(Box::new(Flatpak));
+    registry.register(Box::new(Xbps));
+    registry.register(Box::new(Apk));
+    registry.register(Box::new(Emerge));
+    registry.register(Box::new(Yum));
+    registry.register(Box::new(Port));
+    registry.register(Box::new(Pkg));
+    registry.register(Box::new(Eopkg));
+    registry.register(Box::new(Cave));
+    registry.register(Box::new(Sbopkg));
+    registry.register(Box::new(Scratch));
+}
+
+/// pacman (and yay, which runs it) keep `db.lck` open while it exists.
+const PACMAN_LOCKS: &[NativeLock] = &[NativeLock::new(
+    "/var/lib/pacman/db.lck",
+    LockKind::File,
+    "pacman",
+)];
+
+/// apt takes dpkg's frontend lock for the whole operation, and its own locks while downloading.
+const DPKG_LOCKS: &[NativeLock] = &[
+    NativeLock::new("/var/lib/dpkg/lock-frontend", LockKind::Advisory, "apt"),
+    NativeLock::new("/var/lib/dpkg/lock", LockKind::Advisory, "dpkg"),
+    NativeLock::new("/var/lib/apt/lists/lock", LockKind::Advisory, "apt"),
+    NativeLock::new("/var/cache/apt/archives/lock", LockKind::Advisory, "apt"),
+];
+
+/// The rpm database lock, taken by every rpm-based package manager while it installs.
+const RPM_LOCK: NativeLock = NativeLock::new("/var/lib/rpm/.rpm.lock", LockKind::Advisory, "rpm");
+
+const DNF_LOCKS: &[NativeLock] = &[
+    NativeLock::new("/var/lib/dnf/rpmdb_lock.pid", LockKind::PidFile, "dnf"),
+    RPM_LOCK,
+];
+
+const ZYPPER_LOCKS: &[NativeLock] = &[
+    NativeLock::new("/run/zypp.pid", LockKind::PidFile, "zypper"),
+    RPM_LOCK,
+];
+
+const XBPS_LOCKS: &[NativeLock] = &[NativeLock::new(
+    "/var/db/xbps/lock",
+    LockKind::Advisory,
+    "xbps-install",
+)];
+
+const APK_LOCKS: &[NativeLock] = &[NativeLock::new(
+    "/lib/apk/db/lock",
+    LockKind::Advisory,
+    "apk",
+)];
+
+const YUM_LOCKS: &[NativeLock] = &[
+    NativeLock::new("/var/run/yum.pid", LockKind::PidFile, "yum"),
+    RPM_LOCK,
+];
+
+struct Pacman;
+
+impl PackageManager for Pacman {
+    fn name(&self) -> &str {
+        "pacman"
+    }
+
+    fn snapshot(&self) -> bool {
+        true
+    }
+
+    fn needs_privilege(&self) -> bool {
+        true
+    }
+
+    fn locks(&self) -> &'static [NativeLock] {
+        PACMAN_LOCKS
+    }
+
+    fn upgrade(&self, ctx: &Ctx) -> Vec<Step> {
+        vec![ctx
+            .step(ctx.pick(&["-Syu", "--noconfirm"], &["-Syu"]))
+            .idempotent()]
+    }
+
+    fn outdated(&self, ctx: &Ctx) -> Option<Query> {
+        // checkupdates syncs a temporary copy of the database and needs no privileges; it exits with
+        // 2 if nothing is pending. `pacman -Qu` would answer from the last sync and report stale
+        // results as up to date, so without checkupdates the pending updates are unknown.
+        find_in_path("checkupdates")?;
+        Some(Query::new(ctx.run("checkupdates", &[]), arrow_list).ok_codes(&[2]))
+    }
+
+    fn exclusion_args(&self, pkgs: &[String]) -> Option<Vec<String>> {
+        Some(joined_exclusions("--ignore", pkgs))
+    }
+}
+
+struct Yay;
+
+impl PackageManager for Yay {
+    fn name(&self) -> &str {
+        "yay"
+    }
+
+    fn snapshot(&self) -> bool {
+        true
+    }
+
+    fn locks(&self) -> &'static [NativeLock] {
+        PACMAN_LOCKS
+    }
+
+    fn upgrade(&self, ctx: &Ctx) -> Vec<Step> {
+        vec![ctx.step(ctx.pick(
+            &[
+                "-Syu",
+                "--noconfirm",
+                "--answerdiff",
+                "None",
+                "--answerclean",
+                "None",
+            ],
+            &["-Syu"],
+        ))]
+    }
+
+    fn outdated(&self, ctx: &Ctx) -> Option<Query> {
+        Some(Query::new(ctx.step(&["-Qu"]), arrow_list).ok_codes(&[1]))
+    }
+
+    fn exclusion_args(&self, pkgs: &[String]) -> Option<Vec<String>> {
+        Some(joined_exclusions("--ignore", pkgs))
+    }
+}
+
+/// `apt` and `apt-get` share their command line.
+struct Apt(&'static str);
+
+impl PackageManager for Apt {
+    fn name(&self) -> &str {
+        self.0
+    }
+
+    fn snapshot(&self) -> bool {
+        true
+    }
+
+    fn needs_privilege(&self) -> bool {
+        true
+    }
+
+    fn locks(&self) -> &'static [NativeLock] {
+        DPKG_LOCKS
+    }
+
+    fn refresh(&self, ctx: &Ctx) -> Vec<Step> {
+        vec![ctx.step(&["update"])]
+    }
+
+    fn upgrade(&self, ctx: &Ctx) -> Vec<Step> {
+        vec![ctx.step(ctx.pick(&["upgrade", "-y"], &["upgrade"]))]
+    }
+
+    fn outdated(&self, ctx: &Ctx) -> Option<Query> {
+        Some(if self.0 == "apt" {
+            Query::new(ctx.step(&["list", "--upgradable"]), apt_list)
+        } else {
+            Query::new(ctx.step(&["-s", "upgrade"]), apt_get_simulation)
+        })
+    }
+
+    fn exclusion(&self, ctx: &Ctx, pkgs: &[String]) -> Option<Exclusion> {
+        Some(Exclusion::Hold {
+            hold: vec![ctx.run("apt-mark", &["hold"]).with_args(pkgs)],
+            release: vec![ctx.run("apt-mark", &["unhold"]).with_args(pkgs)],
+        })
+    }
+
+    fn held(&self, ctx: &Ctx) -> Option<Query> {
+        Some(Query::new(ctx.run("apt-mark", &["showhold"]), name_list))
+    }
+}
+
+struct Dnf;
+
+impl PackageManager for Dnf {
+    fn name(&self) -> &str {
+        "dnf"
+    }
+
+    fn snapshot(&self) -> bool {
+        true
+    }
+
+    fn needs_privilege(&self) -> bool {
+        true
+    }
+
+    fn locks(&self) -> &'static [NativeLock] {
+        DNF_LOCKS
+    }
+
+    fn upgrade(&self, ctx: &Ctx) -> Vec<Step> {
+        vec![ctx
+            .step(ctx.pick(&["upgrade", "--refresh", "-y"], &["upgrade", "--refresh"]))
+            .idempotent()]
+    }
+
+    fn outdated(&self, ctx: &Ctx) -> Option<Query> {
+        Some(Query::new(ctx.step(&["check-update", "-q"]), check_update).ok_codes(&[100]))
+    }
+
+    fn exclusion_args(&self, pkgs: &[String]) -> Option<Vec<String>> {
+        Some(repeated_exclusions("--exclude", pkgs))
+    }
+}
+
+struct Zypper;
+
+impl PackageManager for Zypper {
+    fn name(&self) -> &str {
+        "zypper"
+    }
+
+    fn snapshot(&self) -> bool {
+        true
+    }
+
+    fn needs_privilege(&self) -> bool {
+        true
+    }
+
+    fn locks(&self) -> &'static [NativeLock] {
+        ZYPPER_LOCKS
+    }
+
+    fn upgrade(&self, ctx: &Ctx) -> Vec<Step> {
+        vec![ctx.step(ctx.pick(&["--non-interactive", "update"], &["update"]))]
+    }
+
+    fn outdated(&self, ctx: &Ctx) -> Option<Query> {
+        let step = ctx.step(&["--quiet", "--non-interactive", "list-updates"]);
+        Some(Query::new(step, zypper_list))
+    }
+
+    fn exclusion(&self, ctx: &Ctx, pkgs: &[String]) -> Option<Exclusion> {
+        Some(hold(ctx, &["addlock"], &["removelock"], pkgs))
+    }
+
+    fn held(&self, ctx: &Ctx) -> Option<Query> {
+        Some(Query::new(ctx.step(&["--quiet", "locks"]), zypper_locks))
+    }
+}
+
+struct Snap;
+
+impl PackageManager for Snap {
+    fn name(&self) -> &str {
+        "snap"
+    }
+
+    fn needs_privilege(&self) -> bool {
+        true
+    }
+
+    fn upgrade(&self, ctx: &Ctx) -> Vec<Step> {
+        vec![ctx.step(&["refresh"]).idempotent()]
+    }
+
+    fn outdated(&self, ctx: &Ctx) -> Option<Query> {
+        Some(Query::new(ctx.step(&["refresh", "--list"]), snap_list))
+    }
+
+    fn exclusion(&self, ctx: &Ctx, pkgs: &[String]) -> Option<Exclusion> {
+        Some(hold(
+            ctx,
+            &["refresh", "--hold"],
+            &["refresh", "--unhold"],
+            pkgs,
+        ))
+    }
+
+    fn held(&self, ctx: &Ctx) -> Option<Query> {
+        Some(Query::new(ctx.step(&["list"]), snap_held))
+    }
+}
+
+struct Flatpak;
+
+impl PackageManager for Flatpak {
+    fn name(&self) -> &str {
+        "flatpak"
+    }
+
+    fn upgrade(&self, ctx: &Ctx) -> Vec<Step> {
+        vec![ctx
+            .step(ctx.pick(&["update", "-y"], &["update"]))
+            .idempotent()]
+    }
+
+    fn outdated(&self, ctx: &Ctx) -> Option<Query> {
+        let step = ctx.step(&["remote-ls", "--updates", "--columns=application,version"]);
+        Some(Query::new(step, flatpak_list))
+    }
+
+    /// Masks the excluded applications in the system installation, which takes privileges.
+    fn exclusion(&self, ctx: &Ctx, pkgs: &[String]) -> Option<Exclusion> {
+        let mask = |args: &[&str]| ctx.step(args).with_args(pkgs).privileged();
+        Some(Exclusion::Hold {
+            hold: vec![mask(&["mask", "--system"])],
+            release: vec![mask(&["mask", "--system", "--remove"])],
+        })
+    }
+
+    fn held(&self, ctx: &Ctx) -> Option<Query> {
+        Some(Query::new(ctx.step(&["mask", "--system"]), flatpak_masks))
+    }
+}
+
+/// The per-user installation of flatpak, only updated for other users with `--all-users`. The
+/// system installation's `flatpak update` also updates that of the user running it.
+struct FlatpakUser;
+
+impl PackageManager for FlatpakUser {
+    fn name(&self) -> &str {
+        "flatpak-user"
+    }
+
+    fn executable(&self) -> &str {
+        "flatpak"
+    }
+
+    fn scope(&self) -> Scope {
+        Scope::User
+    }
+
+    fn home_dirs(&self) -> &'static [&'static str] {
+        &[".local/share/flatpak"]
+    }
+
+    fn upgrade(&self, ctx: &Ctx) -> Vec<Step> {
+        vec![ctx
+            .step(ctx.pick(&["update", "--user", "-y"], &["update", "--user"]))
+            .idempotent()]
+    }
+
+    fn outdated(&self, ctx: &Ctx) -> Option<Query> {
+        let step = ctx.step(&[
+            "remote-ls",
+            "--user",
+            "--updates",
+            "--columns=application,version",
+        ]);
+        Some(Query::new(step, flatpak_list))
+    }
+
+    fn exclusion(&self, ctx: &Ctx, pkgs: &[String]) -> Option<Exclusion> {
+        Some(hold(
+            ctx,
+            &["mask", "--user"],
+            &["mask", "--user", "--remove"],
+            pkgs,
+        ))
+    }
+
+    fn held(&self, ctx: &Ctx) -> Option<Query> {
+        Some(Query::new(ctx.step(&["mask", "--user"]), flatpak_masks))
+    }
+}
+
+struct Xbps;
+
+impl PackageManager for Xbps {
+    fn name(&self) -> &str {
+        "xbps-install"
+    }
+
+    fn snapshot(&self) -> bool {
+        true
+    }
+
+    fn needs_privilege(&self) -> bool {
+        true
+    }
+
+    fn locks(&self) -> &'static [NativeLock] {
+        XBPS_LOCKS
+    }
+
+    fn upgrade(&self, ctx: &Ctx) -> Vec<Step> {
+        vec![ctx.step(ctx.pick(&["-Syu", "--yes"], &["-Syu"]))]
+    }
+
+    /// xbps-install cannot skip packages, but leaves those held with `xbps-pkgdb` alone.
+    fn exclusion(&self, ctx: &Ctx, pkgs: &[String]) -> Option<Exclusion> {
+        Some(Exclusion::Hold {
+            hold: vec![ctx.run("xbps-pkgdb", &["-m", "hold"]).with_args(pkgs)],
+            release: vec![ctx.run("xbps-pkgdb", &["-m", "unhold"]).with_args(pkgs)],
+        })
+    }
+
+    fn held(&self, ctx: &Ctx) -> Option<Query> {
+        Some(Query::new(ctx.run("xbps-query", &["-H"]), xbps_held))
+    }
+}
+
+struct Apk;
+
+impl PackageManager for Apk {
+    fn name(&self) -> &str {
+        "apk"
+    }
+
+    fn snapshot(&self) -> bool {
+        true
+    }
+
+    fn needs_privilege(&self) -> bool {
+        true
+    }
+
+    fn locks(&self) -> &'static [NativeLock] {
+        APK_LOCKS
+    }
+
+    fn refresh(&self, ctx: &Ctx) -> Vec<Step> {
+        vec![ctx.step(&["update"])]
+    }
+
+    fn upgrade(&self, ctx: &Ctx) -> Vec<Step> {
+        vec![ctx.step(&["upgrade"])]
+    }
+
+    /// `apk upgrade --ignore` upgrades every package but the ones listed, without touching the
+    /// version constraints in `/etc/apk/world`.
+    fn exclusion_args(&self, pkgs: &[String]) -> Option<Vec<String>> {
+        let mut args = vec!["--ignore".to_string()];
+        args.extend_from_slice(pkgs);
+        Some(args)
+    }
+}
+
+struct Emerge;
+
+impl PackageManager for Emerge {
+    fn name(&self) -> &str {
+        "emerge"
+    }
+
+    fn snapshot(&self) -> bool {
+        true
+    }
+
+    fn needs_privilege(&self) -> bool {
+        true
+    }
+
+    fn refresh(&self, ctx: &Ctx) -> Vec<Step> {
+        vec![ctx.step(&["--sync"])]
+    }
+
+    fn upgrade(&self, ctx: &Ctx) -> Vec<Step> {
+        vec![ctx.step(ctx.pick(&["-uDN", "@world"], &["-avuDN", "@world"]))]
+    }
+
+    fn outdated(&self, ctx: &Ctx) -> Option<Query> {
+        Some(Query::new(ctx.step(&["-puDN", "@world"]), emerge_pretend))
+    }
+
+    fn exclusion_args(&self, pkgs: &[String]) -> Option<Vec<String>> {
+        Some(repeated_exclusions("--exclude", pkgs))
+    }
+}
+
+struct Yum;
+
+impl PackageManager for Yum {
+    fn name(&self) -> &str {
+        "yum"
+    }
+
+    fn snapshot(&self) -> bool {
+        true
+    }
+
+    fn needs_privilege(&self) -> bool {
+        true
+    }
+
+    fn locks(&self) -> &'static [NativeLock] {
+        YUM_LOCKS
+    }
+
+    fn upgrade(&self, ctx: &Ctx) -> Vec<Step> {
+        vec![ctx
+            .step(ctx.pick(&["update", "-y"], &["update"]))
+            .idempotent()]
+    }
+
+    fn outdated(&self, ctx: &Ctx) -> Option<Query> {
+        Some(Query::new(ctx.step(&["check-update", "-q"]), check_update).ok_codes(&[100]))
+    }
+
+    fn exclusion_args(&self, pkgs: &[String]) -> Option<Vec<String>> {
+        Some(repeated_exclusions("--exclude", pkgs))
+    }
+}
+
+/// MacPorts.
+struct Port;
+
+impl PackageManager for Port {
+    fn name(&self) -> &str {
+        "port"
+    }
+
+    fn needs_privilege(&self) -> bool {
+        true
+    }
+
+    fn refresh(&self, ctx: &Ctx) -> Vec<Step> {
+        vec![ctx.step(&["selfupdate"])]
+    }
+
+    fn upgrade(&self, ctx: &Ctx) -> Vec<Step> {
+        vec![ctx.step(&["upgrade", "outdated"])]
+    }
+}
+
+/// FreeBSD's pkg.
+struct Pkg;
+
+impl PackageManager for Pkg {
+    fn name(&self) -> &str {
+        "pkg"
+    }
+
+    fn needs_privilege(&self) -> bool {
+        true
+    }
+
+    fn refresh(&self, ctx: &Ctx) -> Vec<Step> {
+        vec![ctx.step(&["update"])]
+    }
+
+    fn upgrade(&self, ctx: &Ctx) -> Vec<Step> {
+        vec![ctx.step(ctx.pick(&["upgrade", "-y"], &["upgrade"]))]
+    }
+}
+
+struct Eopkg;
+
+impl PackageManager for Eopkg {
+    fn name(&self) -> &str {
+        "eopkg"
+    }
+
+    fn needs_privilege(&self) -> bool {
+        true
+    }
+
+    fn refresh(&self, ctx: &Ctx) -> Vec<Step> {
+        vec![ctx.step(&["update-repo"])]
+    }
+
+    fn upgrade(&self, ctx: &Ctx) -> Vec<Step> {
+        vec![ctx.step(ctx.pick(&["upgrade", "-y"], &["upgrade"]))]
+    }
+}
+
+struct Cave;
+
+impl PackageManager for Cave {
+    fn name(&self) -> &str {
+        "cave"
+    }
+
+    fn needs_privilege(&self) -> bool {
+        true
+    }
+
+    fn refresh(&self, ctx: &Ctx) -> Vec<Step> {
+        vec![ctx.step(&["sync"])]
+    }
+
+    fn upgrade(&self, ctx: &Ctx) -> Vec<Step> {
+        vec![ctx.step(ctx.pick(&["upgrade", "--non-interactive"], &["upgrade"]))]
+    }
+}
+
+struct Sbopkg;
+
+impl PackageManager for Sbopkg {
+    fn name(&self) -> &str {
+        "sbopkg"
+    }
+
+    fn needs_privilege(&self) -> bool {
+        true
+    }
+
+    fn refresh(&self, ctx: &Ctx) -> Vec<Step> {
+        vec![ctx.step(&["-r"])]
+    }
+
+    fn upgrade(&self, ctx: &Ctx) -> Vec<Step> {
+        vec![ctx.step(ctx.pick(&["-i", "--non-interactive"], &["-i"]))]
+    }
+}
+
+struct Scratch;
+
+impl PackageManager for Scratch {
+    fn name(&self) -> &str {
+        "scratch"
+    }
+
+    fn needs_privilege(&self) -> bool {
+        true
+    }
+
+    fn upgrade(&self, ctx: &Ctx) -> Vec<Step> {
+        vec![ctx.step(ctx.pick(&["update", "--non-interactive"], &["update"]))]
+    }
+}
+
+/// Parses `apt list --upgradable`: `name/suite latest arch [upgradable from: current]`.
+fn apt_list(output: &str) -> Vec<Pending> {
+    output
+        .lines()
+        .filter(|line| line.contains("[upgradable from: "))
+        .filter_map(|line| {
+            let (name, rest) = line.split_once('/')?;
+            let latest = rest.split_whitespace().nth(1);
+            let current = line.rsplit_once("from: ")?.1.trim_end_matches(']');
+            Some(pending(name, Some(current), latest))
+        })
+        .collect()
+}
+
+/// Parses `apt-get -s upgrade`: `Inst name [current] (latest suite [arch])`.
+fn apt_get_simulation(output: &str) -> Vec<Pending> {
+    output
+        .lines()
+        .filter_map(|line| {
+            let mut words = line.strip_prefix("Inst ")?.split_whitespace();
+            let name = words.next()?;
+            let mut next = words.next()?;
+            let current = if next.starts_with('[') {
+                let current = next.trim_matches(|c| c == '[' || c == ']');
+                next = words.next()?;
+                Some(current)
+            } else {
+                None
+            };
+            Some(pending(name, current, Some(next.trim_start_matches('('))))
+        })
+        .collect()
+}
+
+/// Parses `dnf check-update` and `yum check-update`: `name.arch latest repository`.
+fn check_update(output: &str) -> Vec<Pending> {
+    output
+        .lines()
+        .take_while(|line| !line.starts_with("Obsoleting"))
+        .filter_map(
+            |line| match line.split_whitespace().collect::<Vec<_>>()[..] {
+                [package, latest, _repository] => {
+                    let name = package
+                        .rsplit_once('.')
+                        .map_or(package, |(name, _arch)| name);
+                    Some(pending(name, None, Some(latest)))
+                }
+                _ => None,
+            },
+        )
+        .collect()
+}
+
+/// Parses `zypper list-updates`: `v | repository | name | current | latest | arch`.
+fn zypper_list(output: &str) -> Vec<Pending> {
+    output
+        .lines()
+        .filter_map(
+            |line| match line.split('|').map(str::trim).collect::<Vec<_>>()[..] {
+                ["v", _repository, name, current, latest, ..] => {
+                    Some(pending(name, Some(current), Some(latest)))
+                }
+                _ => None,
+            },
+        )
+        .collect()
+}
+
+/// Parses `zypper locks`: a table of `# | name | type | repository` under a header.
+fn zypper_locks(output: &str) -> Vec<Pending> {
+    output
+        .lines()
+        .filter_map(|line| {
+            let mut columns = line.split('|').map(str::trim);
+            columns.next()?.parse::<u32>().ok()?;
+            columns.next().filter(|name| !name.is_empty())
+        })
+        .map(|name| pending(name, None, None))
+        .collect()
+}
+
+/// Parses `snap refresh --list`: a table of `name latest revision ...` under a header.
+fn snap_list(output: &str) -> Vec<Pending> {
+    output
+        .lines()
+        .skip_while(|line| !line.starts_with("Name"))
+        .skip(1)
+        .filter_map(
+            |line| match line.split_whitespace().collect::<Vec<_>>()[..] {
+                [name, latest, ..] => Some(pending(name, None, Some(latest))),
+                _ => None,
+            },
+        )
+        .collect()
+}
+
+/// Parses `snap list` for snaps with `held` among their notes, the last column.
+fn snap_held(output: &str) -> Vec<Pending> {
+    output
+        .lines()
+        .skip(1)
+        .filter_map(|line| {
+            let mut columns = line.split_whitespace();
+            let name = columns.next()?;
+            let notes = columns.last()?;
+            notes
+                .split(',')
+                .any(|note| note == "held")
+                .then(|| pending(name, None, None))
+        })
+        .collect()
+}
+
+/// Parses `xbps-query -H`: the `name-version_revision` of every held package.
+fn xbps_held(output: &str) -> Vec<Pending> {
+    output
+        .lines()
+        .filter_map(|line| line.trim().rsplit_once('-'))
+        .map(|(name, _version)| pending(name, None, None))
+        .collect()
+}
+
+/// Parses `flatpak mask`: one masked pattern per line, below a heading on terminals.
+fn flatpak_masks(output: &str) -> Vec<Pending> {
+    output
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.ends_with(':') && !line.starts_with("No "))
+        .map(|pattern| pending(pattern, None, None))
+        .collect()
+}
+
+/// Parses `flatpak remote-ls --updates --columns=application,version`.
+fn flatpak_list(output: &str) -> Vec<Pending> {
+    output
+        .lines()
+        .filter_map(|line| {
+            let mut columns = line.split('\t');
+            let name = columns.next().filter(|name| !name.is_empty())?;
+            let latest = columns.next().filter(|version| !version.is_empty());
+            Some(pending(name, None, latest))
+        })
+        .collect()
+}
+
+/// Parses `emerge --pretend`: `[ebuild     U  ] category/name-latest[::repository] [current[::repository]]`.
+/// Every merge is listed, including new dependencies and rebuilds.
+fn emerge_pretend(output: &str) -> Vec<Pending> {
+    output
+        .lines()
+        .filter(|line| line.starts_with("[ebuild") || line.starts_with("[binary"))
+        .filter_map(|line| {
+            let (_, rest) = line.split_once(']')?;
+            let mut fields = rest.split_whitespace();
+            let package = fields.next()?;
+            let (name, latest) = split_version(package.split("::").next()?);
+            let current = fields
+                .next()
+                .and_then(|f| f.strip_prefix('['))
+                .map(|f| f.trim_end_matches(']').split("::").next().unwrap_or(f));
+            Some(pending(name, current, latest))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::Path;
+
+    #[test]
+    fn flatpak_masks_with_heading() {
+        let output = "Masked patterns:\n  org.gnome.Boxes\n  org.mozilla.firefox//stable\n";
+        assert_eq!(
+            flatpak_masks(output),
+            vec![
+                pending("org.gnome.Boxes", None, None),
+                pending("org.mozilla.firefox//stable", None, None),
+            ]
+        );
+    }
+
+    #[test]
+    fn flatpak_masks_without_heading() {
+        assert_eq!(
+            flatpak_masks("org.gnome.Boxes\n"),
+            vec![pending("org.gnome.Boxes", None, None)]
+        );
+        assert!(flatpak_masks("No masked patterns\n").is_empty());
+        assert!(flatpak_masks("").is_empty());
+    }
+
+    #[test]
+    fn xbps_held_strips_versions() {
+        let output = "firefox-128.0_1\nlibreoffice-fresh-24.8.1.2_1\n";
+        assert_eq!(
+            xbps_held(output),
+            vec![
+                pending("firefox", None, None),
+                pending("libreoffice-fresh", None, None),
+            ]
+        );
+    }
+
+    #[test]
+    fn zypper_locks_reads_names() {
+        let output = "
+# | Name    | Type    | Repository | Comment
+--+---------+---------+------------+--------
+1 | vim     | package | (any)      |
+2 | kernel* | package | (any)      | keep
+";
+        assert_eq!(
+            zypper_locks(output),
+            vec![pending("vim", None, None), pending("kernel*", None, None)]
+        );
+        assert!(zypper_locks("There are no package locks defined.\n").is_empty());
+    }
+
+    #[test]
+    fn zypper_excludes_with_locks() {
+        let ctx = Ctx {
+            exe: Path::new("/usr/bin/zypper"),
+            auto: true,
+            dir: Path::new("/"),
+            sudo: true,
+        };
+        let pkgs = ["vim".to_string()];
+        let Some(Exclusion::Hold { hold, release }) = Zypper.exclusion(&ctx, &pkgs) else {
+            panic!("zypper should hold excluded packages");
+        };
+        assert_eq!(hold[0].args, ["addlock", "vim"]);
+        assert_eq!(release[0].args, ["removelock", "vim"]);
+        assert!(hold[0].sudo && release[0].sudo);
+    }
+
+    #[test]
+    fn yay_ignores_excluded() {
+        let pkgs = ["vim".to_string(), "linux".to_string()];
+        assert_eq!(
+            Yay.exclusion_args(&pkgs),
+            Some(vec!["--ignore".to_string(), "vim,linux".to_string()])
+        );
+    }
+}
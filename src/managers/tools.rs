@@ -0,0 +1,419 @@
+//! Cross-platform package managers and toolchain managers that are not tied to a project.
+
+use super::{
+    hold, name_list, pending, split_version, Ctx, Exclusion, PackageManager, Pending, Query,
+    Registry, Scope, Step,
+};
+
+pub(super) fn register(registry: &mut Registry) {
+    registry.register(Box::new(Rustup));
+    registry.register(Box::new(CargoUpdate));
+    registry.register(Box::new(Pipx));
+    registry.register(Box::new(Brew));
+    registry.register(Box::new(Nix));
+    registry.register(Box::new(Guix));
+    registry.register(Box::new(Gem));
+    registry.register(Box::new(Conda));
+    registry.register(Box::new(Asdf));
+    registry.register(Box::new(Vcpkg));
+    registry.register(Box::new(Opam));
+    registry.register(Box::new(Sdkman));
+    registry.register(Box::new(Gvm));
+}
+
+struct Rustup;
+
+impl PackageManager for Rustup {
+    fn name(&self) -> &str {
+        "rustup"
+    }
+
+    fn scope(&self) -> Scope {
+        Scope::User
+    }
+
+    fn home_dirs(&self) -> &'static [&'static str] {
+        &[".rustup"]
+    }
+
+    fn upgrade(&self, ctx: &Ctx) -> Vec<Step> {
+        vec![ctx.step(&["update"]).idempotent()]
+    }
+
+    fn outdated(&self, ctx: &Ctx) -> Option<Query> {
+        Some(Query::new(ctx.step(&["check"]), rustup_check))
+    }
+}
+
+/// Binaries installed with `cargo install`, updated through the `cargo install-update` subcommand
+/// of cargo-update.
+struct CargoUpdate;
+
+impl PackageManager for CargoUpdate {
+    fn name(&self) -> &str {
+        "cargo-update"
+    }
+
+    fn executable(&self) -> &str {
+        "cargo-install-update"
+    }
+
+    fn scope(&self) -> Scope {
+        Scope::User
+    }
+
+    fn upgrade(&self, ctx: &Ctx) -> Vec<Step> {
+        // Cargo passes the subcommand's name first.
+        vec![ctx.step(&["install-update", "--all"])]
+    }
+
+    fn outdated(&self, ctx: &Ctx) -> Option<Query> {
+        Some(Query::new(
+            ctx.step(&["install-update", "--list"]),
+            install_update_list,
+        ))
+    }
+}
+
+/// Python applications installed with pipx.
+struct Pipx;
+
+impl PackageManager for Pipx {
+    fn name(&self) -> &str {
+        "pipx"
+    }
+
+    fn scope(&self) -> Scope {
+        Scope::User
+    }
+
+    fn home_dirs(&self) -> &'static [&'static str] {
+        &[".local/share/pipx", ".local/pipx"]
+    }
+
+    fn upgrade(&self, ctx: &Ctx) -> Vec<Step> {
+        vec![ctx.step(&["upgrade-all"])]
+    }
+
+    fn exclusion_args(&self, pkgs: &[String]) -> Option<Vec<String>> {
+        let mut args = vec!["--skip".to_string()];
+        args.extend_from_slice(pkgs);
+        Some(args)
+    }
+}
+
+/// Homebrew.
+struct Brew;
+
+impl PackageManager for Brew {
+    fn name(&self) -> &str {
+        "brew"
+    }
+
+    fn scope(&self) -> Scope {
+        Scope::User
+    }
+
+    fn refresh(&self, ctx: &Ctx) -> Vec<Step> {
+        vec![ctx.step(&["update"])]
+    }
+
+    fn upgrade(&self, ctx: &Ctx) -> Vec<Step> {
+        vec![ctx.step(&["upgrade"])]
+    }
+
+    fn outdated(&self, ctx: &Ctx) -> Option<Query> {
+        Some(Query::new(
+            ctx.step(&["outdated", "--verbose"]),
+            brew_outdated,
+        ))
+    }
+
+    fn exclusion(&self, ctx: &Ctx, pkgs: &[String]) -> Option<Exclusion> {
+        Some(hold(ctx, &["pin"], &["unpin"], pkgs))
+    }
+
+    fn held(&self, ctx: &Ctx) -> Option<Query> {
+        Some(Query::new(ctx.step(&["list", "--pinned"]), name_list))
+    }
+}
+
+/// Nix channels, updated through `nix-channel` and `nix-env`.
+struct Nix;
+
+impl PackageManager for Nix {
+    fn name(&self) -> &str {
+        "nix"
+    }
+
+    fn scope(&self) -> Scope {
+        Scope::User
+    }
+
+    fn refresh(&self, ctx: &Ctx) -> Vec<Step> {
+        vec![ctx.run("nix-channel", &["--update"])]
+    }
+
+    fn upgrade(&self, ctx: &Ctx) -> Vec<Step> {
+        vec![ctx.run("nix-env", &["-u", "*"])]
+    }
+
+    fn outdated(&self, ctx: &Ctx) -> Option<Query> {
+        Some(Query::new(ctx.run("nix-env", &["-u", "--dry-run", "*"]), nix_dry_run).stderr())
+    }
+}
+
+struct Guix;
+
+impl PackageManager for Guix {
+    fn name(&self) -> &str {
+        "guix"
+    }
+
+    fn scope(&self) -> Scope {
+        Scope::User
+    }
+
+    fn refresh(&self, ctx: &Ctx) -> Vec<Step> {
+        vec![ctx.step(&["pull"])]
+    }
+
+    fn upgrade(&self, ctx: &Ctx) -> Vec<Step> {
+        vec![ctx.step(&["package", "--upgrade"])]
+    }
+}
+
+/// RubyGems.
+struct Gem;
+
+impl PackageManager for Gem {
+    fn name(&self) -> &str {
+        "gem"
+    }
+
+    fn scope(&self) -> Scope {
+        Scope::User
+    }
+
+    fn upgrade(&self, ctx: &Ctx) -> Vec<Step> {
+        vec![ctx.step(&["update", "--no-document"])]
+    }
+
+    fn outdated(&self, ctx: &Ctx) -> Option<Query> {
+        Some(Query::new(ctx.step(&["outdated"]), gem_outdated))
+    }
+}
+
+struct Conda;
+
+impl PackageManager for Conda {
+    fn name(&self) -> &str {
+        "conda"
+    }
+
+    fn needs_privilege(&self) -> bool {
+        true
+    }
+
+    fn upgrade(&self, ctx: &Ctx) -> Vec<Step> {
+        vec![ctx.step(&["update", "--all", "-y"])]
+    }
+}
+
+struct Asdf;
+
+impl PackageManager for Asdf {
+    fn name(&self) -> &str {
+        "asdf"
+    }
+
+    fn scope(&self) -> Scope {
+        Scope::User
+    }
+
+    fn refresh(&self, ctx: &Ctx) -> Vec<Step> {
+        vec![ctx.step(&["update"])]
+    }
+
+    fn upgrade(&self, ctx: &Ctx) -> Vec<Step> {
+        vec![ctx.step(&["plugin-update", "--all"])]
+    }
+}
+
+struct Vcpkg;
+
+impl PackageManager for Vcpkg {
+    fn name(&self) -> &str {
+        "vcpkg"
+    }
+
+    fn scope(&self) -> Scope {
+        Scope::User
+    }
+
+    fn upgrade(&self, ctx: &Ctx) -> Vec<Step> {
+        vec![ctx.step(ctx.pick(&["upgrade"], &["update"]))]
+    }
+}
+
+struct Opam;
+
+impl PackageManager for Opam {
+    fn name(&self) -> &str {
+        "opam"
+    }
+
+    fn scope(&self) -> Scope {
+        Scope::User
+    }
+
+    fn refresh(&self, ctx: &Ctx) -> Vec<Step> {
+        vec![ctx.step(&["update"])]
+    }
+
+    fn upgrade(&self, ctx: &Ctx) -> Vec<Step> {
+        vec![ctx.step(ctx.pick(&["upgrade", "-y"], &["upgrade"]))]
+    }
+}
+
+struct Sdkman;
+
+impl PackageManager for Sdkman {
+    fn name(&self) -> &str {
+        "sdkman"
+    }
+
+    fn scope(&self) -> Scope {
+        Scope::User
+    }
+
+    fn upgrade(&self, ctx: &Ctx) -> Vec<Step> {
+        vec![ctx.step(&["update"])]
+    }
+}
+
+struct Gvm;
+
+impl PackageManager for Gvm {
+    fn name(&self) -> &str {
+        "gvm"
+    }
+
+    fn scope(&self) -> Scope {
+        Scope::User
+    }
+
+    fn upgrade(&self, ctx: &Ctx) -> Vec<Step> {
+        vec![ctx.step(&["update"])]
+    }
+}
+
+/// Parses `rustup check`: `toolchain - Update available : current (hash date) -> latest (hash date)`.
+fn rustup_check(output: &str) -> Vec<Pending> {
+    output
+        .lines()
+        .filter_map(|line| {
+            let (name, status) = line.split_once(" - ")?;
+            let versions = status.strip_prefix("Update available : ")?;
+            let (current, latest) = versions.split_once(" -> ")?;
+            let version = |s: &'_ str| s.split_whitespace().next().map(ToString::to_string);
+            Some(Pending {
+                name: name.to_string(),
+                current: version(current),
+                latest: version(latest),
+            })
+        })
+        .collect()
+}
+
+/// Parses the table of `cargo install-update --list`: `name vcurrent vlatest Yes`, where the last
+/// column says whether an update is needed.
+fn install_update_list(output: &str) -> Vec<Pending> {
+    output
+        .lines()
+        .filter_map(
+            |line| match line.split_whitespace().collect::<Vec<_>>()[..] {
+                [name, current, latest, "Yes"] => Some(pending(
+                    name,
+                    Some(current.trim_start_matches('v')),
+                    Some(latest.trim_start_matches('v')),
+                )),
+                _ => None,
+            },
+        )
+        .collect()
+}
+
+/// Parses `brew outdated --verbose`: `name (current, ...) < latest`.
+fn brew_outdated(output: &str) -> Vec<Pending> {
+    output
+        .lines()
+        .filter_map(|line| {
+            let (name, rest) = line.split_once(" (")?;
+            let (installed, rest) = rest.split_once(')')?;
+            let current = installed.rsplit(", ").next();
+            let latest = rest.split_whitespace().last();
+            Some(pending(name, current, latest))
+        })
+        .collect()
+}
+
+/// Parses `gem outdated`: `name (current < latest)`.
+fn gem_outdated(output: &str) -> Vec<Pending> {
+    output
+        .lines()
+        .filter_map(|line| {
+            let (name, versions) = line.split_once(" (")?;
+            let (current, latest) = versions.trim_end_matches(')').split_once(" < ")?;
+            Some(pending(name, Some(current), Some(latest)))
+        })
+        .collect()
+}
+
+/// Parses `nix-env -u --dry-run`: `upgrading 'name-current' to 'name-latest'`.
+fn nix_dry_run(output: &str) -> Vec<Pending> {
+    output
+        .lines()
+        .filter_map(|line| {
+            let rest = line.trim().strip_prefix("upgrading '")?;
+            let (from, to) = rest.trim_end_matches('\'').split_once("' to '")?;
+            let (name, current) = split_version(from);
+            Some(pending(name, current, split_version(to).1))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn install_update_list_reads_needed_updates() {
+        let output = "    Polling registry 'https://index.crates.io/'.......
+
+Package       Installed  Latest   Needs update
+cargo-update  v13.0.0    v13.1.0  Yes
+ripgrep       v14.1.0    v14.1.0  No
+";
+        assert_eq!(
+            install_update_list(output),
+            vec![pending("cargo-update", Some("13.0.0"), Some("13.1.0"))]
+        );
+    }
+
+    #[test]
+    fn rustup_check_reads_toolchains() {
+        let output = "stable-x86_64-unknown-linux-gnu - Update available : 1.88.0 (6b00bc388 2025-06-23) -> 1.89.0 (29483883e 2025-08-04)
+nightly-x86_64-unknown-linux-gnu - Up to date : 1.91.0-nightly (1c9837df1 2025-08-14)
+rustup - Up to date : 1.28.2
+";
+        assert_eq!(
+            rustup_check(output),
+            vec![pending(
+                "stable-x86_64-unknown-linux-gnu",
+                Some("1.88.0"),
+                Some("1.89.0")
+            )]
+        );
+    }
+}
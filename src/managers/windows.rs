@@ -0,0 +1,123 @@
+//! Windows package managers and Windows Update itself.
+
+use super::{hold, pending, Ctx, Exclusion, PackageManager, Pending, Query, Registry, Scope, Step};
+
+pub(super) fn register(registry: &mut Registry) {
+    registry.register(Box::new(Choco));
+    registry.register(Box::new(Scoop));
+    registry.register(Box::new(Winget));
+    #[cfg(target_os = "windows")]
+    registry.register(Box::new(WindowsUpdate));
+}
+
+struct Choco;
+
+impl PackageManager for Choco {
+    fn name(&self) -> &str {
+        "choco"
+    }
+
+    fn upgrade(&self, ctx: &Ctx) -> Vec<Step> {
+        vec![ctx.step(ctx.pick(&["upgrade", "all", "-y"], &["upgrade", "all"]))]
+    }
+
+    fn outdated(&self, ctx: &Ctx) -> Option<Query> {
+        Some(Query::new(ctx.step(&["outdated", "-r"]), choco_outdated))
+    }
+
+    fn exclusion_args(&self, pkgs: &[String]) -> Option<Vec<String>> {
+        Some(vec![format!("--except={}", pkgs.join(","))])
+    }
+}
+
+struct Scoop;
+
+impl PackageManager for Scoop {
+    fn name(&self) -> &str {
+        "scoop"
+    }
+
+    fn scope(&self) -> Scope {
+        Scope::User
+    }
+
+    fn upgrade(&self, ctx: &Ctx) -> Vec<Step> {
+        vec![ctx.step(&["update", "*"])]
+    }
+
+    fn exclusion(&self, ctx: &Ctx, pkgs: &[String]) -> Option<Exclusion> {
+        Some(hold(ctx, &["hold"], &["unhold"], pkgs))
+    }
+
+    fn held(&self, ctx: &Ctx) -> Option<Query> {
+        Some(Query::new(ctx.step(&["list"]), scoop_held))
+    }
+}
+
+struct Winget;
+
+impl PackageManager for Winget {
+    fn name(&self) -> &str {
+        "winget"
+    }
+
+    fn upgrade(&self, ctx: &Ctx) -> Vec<Step> {
+        vec![ctx.step(ctx.pick(
+            &[
+                "upgrade",
+                "--all",
+                "--accept-source-agreements",
+                "--accept-package-agreements",
+            ],
+            &["upgrade", "--all"],
+        ))]
+    }
+}
+
+/// Windows Update through the PSWindowsUpdate PowerShell module.
+#[cfg(target_os = "windows")]
+struct WindowsUpdate;
+
+#[cfg(target_os = "windows")]
+impl PackageManager for WindowsUpdate {
+    fn name(&self) -> &str {
+        "windowsupdate"
+    }
+
+    fn upgrade(&self, ctx: &Ctx) -> Vec<Step> {
+        let setup_commands = [
+            "Set-ExecutionPolicy -Scope CurrentUser RemoteSigned -Force",
+            "if (!(Get-Module -ListAvailable -Name PSWindowsUpdate)) { Install-Module -Name PSWindowsUpdate -Force -Scope CurrentUser }",
+            "Import-Module PSWindowsUpdate",
+            if ctx.auto {
+                "Start-Process powershell -Verb RunAs -ArgumentList '-NoProfile -Command &{Install-WindowsUpdate -AcceptAll -AutoReboot}'"
+            } else {
+                "Start-Process powershell -Verb RunAs -ArgumentList '-NoProfile -Command &{Install-WindowsUpdate}'"
+            },
+        ]
+        .join("; ");
+
+        vec![ctx.run("powershell", &["-Command", &setup_commands])]
+    }
+}
+
+/// Parses `scoop list` for apps marked `Held package` in the info column.
+fn scoop_held(output: &str) -> Vec<Pending> {
+    output
+        .lines()
+        .filter(|line| line.contains("Held package"))
+        .filter_map(|line| line.split_whitespace().next())
+        .map(|name| pending(name, None, None))
+        .collect()
+}
+
+/// Parses `choco outdated -r`: `name|current|latest|pinned`.
+fn choco_outdated(output: &str) -> Vec<Pending> {
+    output
+        .lines()
+        .filter_map(|line| match line.split('|').collect::<Vec<_>>()[..] {
+            [name, current, latest, _pinned] => Some(pending(name, Some(current), Some(latest))),
+            _ => None,
+        })
+        .collect()
+}
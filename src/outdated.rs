@@ -0,0 +1,197 @@
+//! Listing pending updates without applying them.
+
+use crate::account::{self, Account};
+use crate::conf::Config;
+use crate::managers::{Ctx, PackageManager, Pending, Query, Registry, Scope};
+use crate::say;
+use colored::Colorize;
+use serde::Serialize;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+/// The pending updates of one package manager.
+#[derive(Debug, Clone, Serialize)]
+pub struct ManagerUpdates {
+    pub name: String,
+    /// Pending updates, without excluded packages.
+    pub packages: Vec<Pending>,
+    /// Why the package manager could not be queried.
+    pub error: Option<String>,
+}
+
+/// Asks every selected package manager among `candidates` for its pending updates.
+/// Package managers that cannot list updates, or have nothing to update in `dir`, are left out.
+/// Queries of user-level and project package managers run as `owner`, see [`account::owner`].
+#[must_use]
+pub fn query(
+    config: &Config,
+    registry: &Registry,
+    candidates: &[(String, PathBuf)],
+    dir: &Path,
+    owner: Option<&Account>,
+) -> Vec<ManagerUpdates> {
+    let mut managers = Vec::new();
+    for (pm_name, exe) in candidates {
+        if !config.selects(pm_name) {
+            continue;
+        }
+        let Some(manager) = registry.get(pm_name) else {
+            continue;
+        };
+        let Some(result) = pending(manager, exe, config.auto, dir, owner) else {
+            if config.verbose {
+                say!(
+                    "{} {} cannot list pending updates.",
+                    "INFO:".blue(),
+                    pm_name
+                );
+            }
+            continue;
+        };
+        let (packages, error) = match result {
+            Ok(packages) => (packages, None),
+            Err(e) => {
+                eprintln!(
+                    "{} Failed to list pending updates of {}: {e}",
+                    "WARN:".yellow(),
+                    pm_name
+                );
+                (Vec::new(), Some(e))
+            }
+        };
+        managers.push(ManagerUpdates {
+            name: pm_name.clone(),
+            packages: without_excluded(config, pm_name, packages),
+            error,
+        });
+    }
+    managers
+}
+
+/// Leaves out the packages of `pm` excluded by the configuration.
+#[must_use]
+pub fn without_excluded(config: &Config, pm: &str, packages: Vec<Pending>) -> Vec<Pending> {
+    let excluded = config.exclusions.get(pm);
+    packages
+        .into_iter()
+        .filter(|p| excluded.is_none_or(|pkgs| !pkgs.contains(&p.name)))
+        .collect()
+}
+
+/// Runs the pending-updates query of `manager`. Returns `None` if it has none, or if it is a
+/// project manager with nothing to update in `dir`, and an error if the query failed.
+#[must_use]
+pub fn pending(
+    manager: &dyn PackageManager,
+    exe: &Path,
+    auto: bool,
+    dir: &Path,
+    owner: Option<&Account>,
+) -> Option<Result<Vec<Pending>, String>> {
+    if manager.scope() == Scope::Project && !manager.applies(dir) {
+        return None;
+    }
+    let (user, dir) = account::placement(manager.scope(), owner, dir);
+    let query = probe(manager, exe, auto, dir)?;
+    Some(run(&query, dir, user))
+}
+
+/// The pending-updates query of `manager`, if it has one.
+#[must_use]
+pub fn probe(manager: &dyn PackageManager, exe: &Path, auto: bool, dir: &Path) -> Option<Query> {
+    let ctx = Ctx {
+        exe,
+        auto,
+        dir,
+        sudo: false,
+    };
+    manager.outdated(&ctx)
+}
+
+/// Runs `query` in `dir`, as `user` if set, and parses its output.
+///
+/// # Errors
+/// Returns an error if the command cannot be started or fails.
+pub fn run(query: &Query, dir: &Path, user: Option<&Account>) -> Result<Vec<Pending>, String> {
+    let mut cmd = Command::new(&query.step.program);
+    if let Some(user) = user {
+        user.apply(&mut cmd);
+    }
+    let output = cmd
+        .args(&query.step.args)
+        .current_dir(dir)
+        // Parsers expect untranslated output.
+        .env("LC_ALL", "C")
+        .stdin(Stdio::null())
+        .output()
+        .map_err(|e| format!("{}: {e}", query.step.program))?;
+    let code = output.status.code();
+    if !output.status.success() && !code.is_some_and(|c| query.ok_codes.contains(&c)) {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        let reason = stderr.lines().rfind(|l| !l.trim().is_empty()).unwrap_or("");
+        return Err(format!(
+            "{} {} exited with {}{}{reason}",
+            query.step.program,
+            query.step.args.join(" "),
+            output.status,
+            if reason.is_empty() { "" } else { ": " }
+        ));
+    }
+    let text = if query.stderr {
+        output.stderr
+    } else {
+        output.stdout
+    };
+    Ok((query.parse)(&String::from_utf8_lossy(&text)))
+}
+
+/// Prints pending updates as a table.
+pub fn print(managers: &[ManagerUpdates]) {
+    let rows: Vec<[&str; 4]> = managers
+        .iter()
+        .flat_map(|m| {
+            m.packages.iter().map(|p| {
+                [
+                    m.name.as_str(),
+                    p.name.as_str(),
+                    p.current.as_deref().unwrap_or("?"),
+                    p.latest.as_deref().unwrap_or("?"),
+                ]
+            })
+        })
+        .collect();
+    if rows.is_empty() {
+        say!("{} Everything is up to date.", "INFO:".blue());
+        return;
+    }
+    let header = ["MANAGER", "PACKAGE", "CURRENT", "LATEST"];
+    let mut widths = header.map(str::len);
+    for row in &rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.len());
+        }
+    }
+    let line = |row: &[&str; 4]| {
+        format!(
+            "  {:w0$}  {:w1$}  {:w2$}  {}",
+            row[0],
+            row[1],
+            row[2],
+            row[3],
+            w0 = widths[0],
+            w1 = widths[1],
+            w2 = widths[2]
+        )
+    };
+    say!("{}", line(&header).bold());
+    for row in &rows {
+        say!("{}", line(row));
+    }
+    let with_updates = managers.iter().filter(|m| !m.packages.is_empty()).count();
+    say!(
+        "{} {} pending update(s) from {} package manager(s).",
+        "INFO:".blue(),
+        rows.len(),
+        with_updates
+    );
+}
@@ -0,0 +1,204 @@
+//! Output formats.
+//!
+//! In the human format qud prints colored text to stdout. In the JSON formats stdout is reserved
+//! for machine-readable output: human text (see [`say!`](crate::say)) and the output of package
+//! managers go to stderr instead, and a [`Reporter`] prints either one JSON document at the end
+//! of the run or one JSON event per line as the run progresses.
+
+use crate::detect::Detection;
+use crate::exec::StepResult;
+use crate::history::RunRecord;
+use crate::outdated::ManagerUpdates;
+use crate::plan::{PlannedStep, UpdatePlan};
+use crate::summary::Summary;
+use serde::Serialize;
+use serde_json::{json, Map, Value};
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU8, Ordering};
+
+/// How qud reports what it does.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Format {
+    /// Colored text.
+    #[default]
+    Human,
+    /// A single JSON document printed when the run ends.
+    Json,
+    /// One JSON event per line, printed as the run progresses.
+    Ndjson,
+}
+
+impl FromStr for Format {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Format, String> {
+        match s {
+            "human" => Ok(Format::Human),
+            "json" => Ok(Format::Json),
+            "ndjson" => Ok(Format::Ndjson),
+            _ => Err(format!(
+                "Invalid format: {s} (expected human, json or ndjson)"
+            )),
+        }
+    }
+}
+
+static FORMAT: AtomicU8 = AtomicU8::new(0);
+
+/// Sets the process-wide output format.
+pub fn set_format(format: Format) {
+    FORMAT.store(format as u8, Ordering::Relaxed);
+}
+
+/// Whether stdout is reserved for JSON output.
+#[must_use]
+pub fn is_machine() -> bool {
+    FORMAT.load(Ordering::Relaxed) != Format::Human as u8
+}
+
+/// Prints human-readable text: to stdout in the human format, to stderr otherwise.
+#[macro_export]
+macro_rules! say {
+    ($($arg:tt)*) => {
+        if $crate::output::is_machine() {
+            eprintln!($($arg)*);
+        } else {
+            println!($($arg)*);
+        }
+    };
+}
+
+/// A detected package manager.
+#[derive(Debug, Clone, Serialize)]
+pub struct DetectedManager {
+    pub name: String,
+    pub path: PathBuf,
+    /// Other installations of the same package manager further down PATH.
+    pub duplicates: Vec<PathBuf>,
+    /// Paths in PATH linking to the executable or one of the duplicates.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub aliases: Vec<PathBuf>,
+}
+
+impl DetectedManager {
+    /// Lists the package managers of a detection.
+    #[must_use]
+    pub fn all(detection: &Detection) -> Vec<DetectedManager> {
+        detection
+            .found
+            .iter()
+            .map(|(name, path)| DetectedManager {
+                name: name.clone(),
+                path: path.clone(),
+                duplicates: detection.duplicates.get(name).cloned().unwrap_or_default(),
+                aliases: detection.aliases.get(name).cloned().unwrap_or_default(),
+            })
+            .collect()
+    }
+}
+
+/// Something that happened during a run.
+#[derive(Debug, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum Event<'a> {
+    Detected {
+        managers: Vec<DetectedManager>,
+    },
+    Plan {
+        plan: &'a UpdatePlan,
+    },
+    StepStarted {
+        manager: &'a str,
+        step: &'a PlannedStep,
+    },
+    /// A failed attempt of a step that will be retried after `delay` seconds.
+    StepRetrying {
+        result: &'a StepResult,
+        delay: f64,
+    },
+    StepFinished {
+        result: &'a StepResult,
+    },
+    /// The upgrade steps of a package manager were skipped because nothing was pending.
+    UpToDate {
+        manager: &'a str,
+    },
+    Summary {
+        summary: &'a Summary,
+    },
+    Outdated {
+        managers: &'a [ManagerUpdates],
+    },
+    /// Recorded runs, most recent first.
+    History {
+        runs: &'a [RunRecord],
+    },
+}
+
+/// Reports events in the configured format.
+pub struct Reporter {
+    format: Format,
+    document: Map<String, Value>,
+}
+
+impl Reporter {
+    #[must_use]
+    pub fn new(format: Format) -> Reporter {
+        Reporter {
+            format,
+            document: Map::new(),
+        }
+    }
+
+    /// Prints the event (NDJSON) or records it for the final document (JSON).
+    /// Does nothing in the human format.
+    pub fn emit(&mut self, event: &Event) {
+        match self.format {
+            Format::Human => {}
+            Format::Ndjson => println!("{}", json!(event)),
+            Format::Json => match event {
+                Event::Detected { managers } => {
+                    self.document.insert("detected".into(), json!(managers));
+                }
+                Event::Plan { plan } => {
+                    self.document.insert("plan".into(), json!(plan));
+                }
+                // Results record how many attempts a step took, and the summary which package
+                // managers were up to date.
+                Event::StepStarted { .. } | Event::StepRetrying { .. } | Event::UpToDate { .. } => {
+                }
+                Event::StepFinished { result } => {
+                    if let Value::Array(results) = self
+                        .document
+                        .entry("results")
+                        .or_insert_with(|| Value::Array(Vec::new()))
+                    {
+                        results.push(json!(result));
+                    }
+                }
+                Event::Summary { summary } => {
+                    self.document.insert("summary".into(), json!(summary));
+                }
+                Event::Outdated { managers } => {
+                    self.document.insert("outdated".into(), json!(managers));
+                }
+                Event::History { runs } => {
+                    self.document.insert("history".into(), json!(runs));
+                }
+            },
+        }
+    }
+
+    /// Prints the collected document in the JSON format.
+    pub fn finish(self) {
+        if self.format == Format::Json {
+            let document = Value::Object(self.document);
+            println!(
+                "{}",
+                serde_json::to_string_pretty(&document).unwrap_or_default()
+            );
+        }
+    }
+}
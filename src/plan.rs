@@ -0,0 +1,475 @@
+//! Planning of updates.
+//!
+//! An [`UpdatePlan`] is computed once from the configuration and the detected package managers.
+//! Dry runs print it, confirmation edits it and the executor runs exactly its steps.
+
+use crate::account::{self, Account};
+use crate::conf::{Config, Hooks};
+use crate::detect::Detection;
+use crate::escalation::Escalation;
+use crate::helpers::reorder_candidates;
+use crate::managers::{Ctx, Exclusion, PackageManager, Registry, Scope, Step};
+use crate::say;
+use crate::snapshot::SnapshotPlan;
+use colored::Colorize;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashSet};
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+/// Whether a step refreshes package metadata, upgrades packages, is a user-defined hook or takes
+/// a snapshot.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum StepKind {
+    Refresh,
+    /// Holds excluded packages before the upgrade.
+    Hold,
+    Upgrade,
+    /// Releases the packages held before the upgrade.
+    Release,
+    PreHook,
+    PostHook,
+    /// Takes a filesystem snapshot before system packages are updated.
+    Snapshot,
+}
+
+impl StepKind {
+    #[must_use]
+    pub fn is_hook(self) -> bool {
+        matches!(self, StepKind::PreHook | StepKind::PostHook)
+    }
+}
+
+impl fmt::Display for StepKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            StepKind::Refresh => "refresh",
+            StepKind::Hold => "hold",
+            StepKind::Upgrade => "upgrade",
+            StepKind::Release => "release",
+            StepKind::PreHook => "pre-hook",
+            StepKind::PostHook => "post-hook",
+            StepKind::Snapshot => "snapshot",
+        })
+    }
+}
+
+/// A fully resolved command.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PlannedStep {
+    pub kind: StepKind,
+    pub program: String,
+    /// Arguments, including exclusions and extra flags.
+    pub args: Vec<String>,
+    /// Run with elevated privileges.
+    pub privileged: bool,
+    /// The program granting the privileges, resolved when the plan is made. `None` if qud already
+    /// runs elevated or escalation is off.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub escalation: Option<Escalation>,
+    /// The account the step runs as instead of qud's own, see [`account::owner`].
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub user: Option<Account>,
+    /// The working directory.
+    pub dir: PathBuf,
+    /// Environment variables set in addition to the inherited environment.
+    pub env: BTreeMap<String, String>,
+    /// Whether the step may be retried after a failure.
+    #[serde(default)]
+    pub idempotent: bool,
+}
+
+impl PlannedStep {
+    /// A hook running `command` in the shell. `manager` is `None` for hooks of the whole run.
+    #[must_use]
+    pub fn hook(kind: StepKind, command: &str, manager: Option<&str>, dir: &Path) -> PlannedStep {
+        #[cfg(not(target_os = "windows"))]
+        let (program, flag) = ("sh", "-c");
+        #[cfg(target_os = "windows")]
+        let (program, flag) = ("cmd", "/C");
+        let mut env = BTreeMap::from([(
+            "QUD_HOOK".to_string(),
+            if kind == StepKind::PreHook {
+                "pre"
+            } else {
+                "post"
+            }
+            .to_string(),
+        )]);
+        if let Some(manager) = manager {
+            env.insert("QUD_MANAGER".to_string(), manager.to_string());
+        }
+        PlannedStep {
+            kind,
+            program: program.to_string(),
+            args: vec![flag.to_string(), command.to_string()],
+            privileged: false,
+            escalation: None,
+            user: None,
+            dir: dir.to_path_buf(),
+            env,
+            idempotent: false,
+        }
+    }
+}
+
+impl fmt::Display for PlannedStep {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // Hooks are shown as they were configured.
+        if self.kind.is_hook() {
+            return write!(f, "{}", self.args.last().map_or("", String::as_str));
+        }
+        let command = format!("{} {}", self.program, self.args.join(" "));
+        match (self.escalation, &self.user) {
+            (Some(escalation), _) => write!(f, "{}", escalation.display(&command)),
+            (None, Some(user)) => write!(f, "{command} (as {})", user.name),
+            (None, None) => write!(f, "{command}"),
+        }
+    }
+}
+
+/// The steps planned for one package manager.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ManagerPlan {
+    pub name: String,
+    /// The executable the package manager was resolved to.
+    pub exe: PathBuf,
+    /// Empty if there is nothing to update, e.g. a project manager outside a project.
+    /// Pre-hooks come first and post-hooks last.
+    pub steps: Vec<PlannedStep>,
+    /// Whether the steps may prompt for a password or confirmation. Interactive package managers
+    /// never run alongside others.
+    #[serde(default)]
+    pub interactive: bool,
+    /// Whether the package manager updates system packages, so the plan's snapshot is taken
+    /// before it runs.
+    #[serde(default)]
+    pub snapshot: bool,
+    /// The user whose package manager this is, with `--all-users`. Its steps run as that user.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub user: Option<String>,
+}
+
+impl ManagerPlan {
+    /// How results and summaries refer to the package manager: its name, followed by its user
+    /// with `--all-users` (`rustup@alice`).
+    #[must_use]
+    pub fn label(&self) -> String {
+        match &self.user {
+            Some(user) => format!("{}@{user}", self.name),
+            None => self.name.clone(),
+        }
+    }
+}
+
+/// The ordered list of package managers and commands an update will run.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct UpdatePlan {
+    pub managers: Vec<ManagerPlan>,
+    /// Detected package managers that will not be updated: filtered out, excluded or skipped
+    /// at confirmation.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub skipped: Vec<String>,
+    /// Hooks run before and after all package managers.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub hooks: Vec<PlannedStep>,
+    /// The snapshot taken before updating system packages, if configured.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub snapshot: Option<SnapshotPlan>,
+}
+
+impl UpdatePlan {
+    /// Plans updates for `candidates`, skipping package managers filtered out by `--only`
+    /// or fully excluded, and those unknown to `registry`. The unprivileged steps of user-level and
+    /// project package managers run as `owner`, see [`account::owner`].
+    #[must_use]
+    pub fn new(
+        config: &Config,
+        registry: &Registry,
+        candidates: Vec<(String, PathBuf)>,
+        dir: &Path,
+        owner: Option<&Account>,
+    ) -> UpdatePlan {
+        let mut managers = Vec::new();
+        let mut skipped = Vec::new();
+        let escalation = config.escalation.resolve();
+        for (pm_name, exe) in candidates {
+            if !config.selects(&pm_name) {
+                skipped.push(pm_name);
+                continue;
+            }
+            let Some(manager) = registry.get(&pm_name) else {
+                eprintln!(
+                    "{} Unknown package manager: {}",
+                    "Warning:".yellow(),
+                    pm_name
+                );
+                continue;
+            };
+            managers.push(manager_plan(manager, exe, config, dir, escalation, owner));
+        }
+        let snapshot = config
+            .snapshot
+            .as_ref()
+            .filter(|_| managers.iter().any(|m| m.snapshot))
+            .map(|tool| SnapshotPlan::new(tool, dir, escalation));
+        UpdatePlan {
+            managers,
+            skipped,
+            hooks: hook_steps(&config.hooks, None, dir),
+            snapshot,
+        }
+    }
+
+    /// The backend privileged steps run with, if any step needs privileges.
+    #[must_use]
+    pub fn escalation(&self) -> Option<Escalation> {
+        self.privileged_steps().find_map(|step| step.escalation)
+    }
+
+    /// The steps run through an escalation program, including the snapshot.
+    pub fn privileged_steps(&self) -> impl Iterator<Item = &PlannedStep> {
+        self.managers
+            .iter()
+            .flat_map(|m| &m.steps)
+            .chain(self.snapshot.as_ref().map(|s| &s.step))
+            .filter(|step| step.escalation.is_some())
+    }
+
+    /// Adds the user-level package managers of other users, found by
+    /// [`detect::user_managers`](crate::detect::user_managers), to be updated as those users.
+    pub fn add_users(
+        &mut self,
+        config: &Config,
+        registry: &Registry,
+        users: Vec<(Account, Vec<(String, PathBuf)>)>,
+    ) {
+        for (account, candidates) in users {
+            for (pm_name, exe) in candidates {
+                let label = format!("{pm_name}@{}", account.name);
+                // "pm@user" in --excl skips one user's package manager.
+                let excluded = config.exclusions.get(&label).is_some_and(Vec::is_empty);
+                if excluded || !config.selects(&pm_name) {
+                    self.skipped.push(label);
+                    continue;
+                }
+                let Some(manager) = registry.get(&pm_name) else {
+                    continue;
+                };
+                let mut plan =
+                    manager_plan(manager, exe, config, &account.home, None, Some(&account));
+                plan.user = Some(account.name.clone());
+                self.managers.push(plan);
+            }
+        }
+    }
+
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.managers.is_empty()
+    }
+
+    /// Removes the package managers with the given [labels](ManagerPlan::label) from the plan and
+    /// records them as skipped.
+    pub fn skip(&mut self, labels: &HashSet<String>) {
+        let (skipped, kept) = std::mem::take(&mut self.managers)
+            .into_iter()
+            .partition(|m| labels.contains(&m.label()));
+        self.managers = kept;
+        self.skipped.extend(skipped.iter().map(ManagerPlan::label));
+    }
+}
+
+/// Plans the steps of one package manager, surrounded by its hooks.
+fn manager_plan(
+    manager: &dyn PackageManager,
+    exe: PathBuf,
+    config: &Config,
+    dir: &Path,
+    escalation: Option<Escalation>,
+    owner: Option<&Account>,
+) -> ManagerPlan {
+    let name = manager.name();
+    let mut steps = plan_steps(manager, &exe, config, dir, escalation, owner);
+    if !steps.is_empty() {
+        let mut hooks = hook_steps(&config.manager_hooks(name), Some(name), dir);
+        let post = hooks.split_off(hooks.partition_point(|h| h.kind == StepKind::PreHook));
+        steps.splice(0..0, hooks);
+        steps.extend(post);
+    }
+    // Without --auto any package manager may ask for confirmation, and escalation for a
+    // password.
+    let interactive = !config.auto || steps.iter().any(|s| s.escalation.is_some());
+    let snapshot = manager.snapshot() && !steps.is_empty();
+    ManagerPlan {
+        name: name.to_string(),
+        exe,
+        steps,
+        interactive,
+        snapshot,
+        user: None,
+    }
+}
+
+/// Pre-hooks followed by post-hooks.
+fn hook_steps(hooks: &Hooks, manager: Option<&str>, dir: &Path) -> Vec<PlannedStep> {
+    let pre = hooks
+        .pre
+        .iter()
+        .map(|command| PlannedStep::hook(StepKind::PreHook, command, manager, dir));
+    let post = hooks
+        .post
+        .iter()
+        .map(|command| PlannedStep::hook(StepKind::PostHook, command, manager, dir));
+    pre.chain(post).collect()
+}
+
+fn plan_steps(
+    manager: &dyn PackageManager,
+    exe: &Path,
+    config: &Config,
+    dir: &Path,
+    escalation: Option<Escalation>,
+    owner: Option<&Account>,
+) -> Vec<PlannedStep> {
+    if manager.scope() == Scope::Project && !manager.applies(dir) {
+        if config.verbose {
+            say!(
+                "{} Nothing to update for {} in {}.",
+                "INFO:".blue(),
+                manager.name(),
+                dir.display()
+            );
+        }
+        return Vec::new();
+    }
+
+    let (user, dir) = account::placement(manager.scope(), owner, dir);
+    let ctx = Ctx {
+        exe,
+        auto: config.auto,
+        dir,
+        sudo: manager.needs_privilege(),
+    };
+    let ext_args = config.get_ext_args(manager.name());
+    let mut upgrade = manager.upgrade(&ctx);
+    let mut upgrade_args = Vec::new();
+    let (mut hold, mut release) = (Vec::new(), Vec::new());
+    match config.get_exclusion(manager, &ctx) {
+        Some(Exclusion::Args(args)) => upgrade_args = args,
+        Some(Exclusion::Upgrade(steps)) => upgrade = steps,
+        Some(Exclusion::Hold {
+            hold: hold_steps,
+            release: release_steps,
+        }) if !upgrade.is_empty() => {
+            hold = hold_steps;
+            release = release_steps;
+        }
+        Some(Exclusion::Hold { .. }) | None => {}
+    }
+    upgrade_args.extend(ext_args.iter().cloned());
+
+    let resolve = |step: Step, kind: StepKind, extra_args: &[String]| {
+        let mut args = step.args;
+        args.extend_from_slice(extra_args);
+        PlannedStep {
+            kind,
+            program: step.program,
+            args,
+            privileged: step.sudo,
+            escalation: escalation.filter(|_| step.sudo),
+            user: user.filter(|_| !step.sudo).cloned(),
+            dir: dir.to_path_buf(),
+            env: BTreeMap::new(),
+            idempotent: step.idempotent
+                || matches!(kind, StepKind::Refresh | StepKind::Hold | StepKind::Release),
+        }
+    };
+    let mut steps: Vec<PlannedStep> = manager
+        .refresh(&ctx)
+        .into_iter()
+        .map(|step| resolve(step, StepKind::Refresh, &ext_args))
+        .collect();
+    steps.extend(
+        hold.into_iter()
+            .map(|step| resolve(step, StepKind::Hold, &[]))
+            .chain(
+                upgrade
+                    .into_iter()
+                    .map(|step| resolve(step, StepKind::Upgrade, &upgrade_args)),
+            )
+            .chain(
+                release
+                    .into_iter()
+                    .map(|step| resolve(step, StepKind::Release, &[])),
+            ),
+    );
+    steps
+}
+
+/// Applies executable overrides and the configured order to the detected package managers.
+/// Returns the name and executable of every package manager that may be updated.
+#[must_use]
+pub fn candidates(config: &Config, detection: Detection) -> Vec<(String, PathBuf)> {
+    let mut final_candidates = Vec::new();
+    let mut used_pm_names = HashSet::new();
+    for (pm_name, candidate) in detection.found {
+        used_pm_names.insert(pm_name.clone());
+        if let Some(spec_path) = config.specs.get(&pm_name) {
+            if config.verbose {
+                say!(
+                    "{} Overriding {} with specified executable: {}",
+                    "INFO:".blue(),
+                    pm_name,
+                    spec_path.display()
+                );
+            }
+            final_candidates.push((pm_name, spec_path.clone()));
+        } else {
+            final_candidates.push((pm_name, candidate));
+        }
+    }
+    for (pm, spec_path) in &config.specs {
+        if !used_pm_names.contains(pm) {
+            if config.verbose {
+                say!(
+                    "{} Adding specified executable for {} not found in PATH: {}",
+                    "INFO:".blue(),
+                    pm,
+                    spec_path.display()
+                );
+            }
+            final_candidates.push((pm.clone(), spec_path.clone()));
+        }
+    }
+
+    #[allow(unused_mut)]
+    let mut final_candidates = if let Some(ref ord_mode) = config.ord {
+        reorder_candidates(final_candidates, ord_mode, config.verbose)
+    } else {
+        final_candidates
+    };
+
+    #[cfg(target_os = "windows")]
+    {
+        let include_windowsupdate = match &config.only {
+            Some(only_list) => only_list.iter().any(|s| s == "windowsupdate"),
+            None => true,
+        };
+        if include_windowsupdate {
+            if !final_candidates
+                .iter()
+                .any(|(pm_name, _)| pm_name == "windowsupdate")
+            {
+                if config.verbose {
+                    say!("{} Adding Windows update.", "INFO:".blue());
+                }
+                final_candidates
+                    .push(("windowsupdate".to_string(), PathBuf::from("windowsupdate")));
+            }
+        }
+    }
+
+    final_candidates
+}
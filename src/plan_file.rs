@@ -0,0 +1,148 @@
+//! Saved update plans.
+//!
+//! `qud plan` writes an [`UpdatePlan`] together with a fingerprint of every program it runs;
+//! `qud apply` refuses to run the plan if any of those programs now resolves to a different
+//! file or its contents changed. That includes the program granting privileges, like sudo.
+
+use crate::escalation::Escalation;
+use crate::helpers::find_in_path;
+use crate::plan::UpdatePlan;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::BTreeSet;
+use std::error::Error;
+use std::fmt::Write as _;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Version of the plan file format.
+const FORMAT: u32 = 2;
+
+/// The program a step runs, as resolved when the plan was made.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Fingerprint {
+    /// The program as written in the plan's steps.
+    pub program: String,
+    /// The file it resolved to.
+    pub path: PathBuf,
+    /// SHA-256 of the file's contents, hex encoded.
+    pub sha256: String,
+}
+
+/// An update plan as stored on disk.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SavedPlan {
+    pub format: u32,
+    pub qud_version: String,
+    /// Seconds since the Unix epoch.
+    pub created: u64,
+    pub plan: UpdatePlan,
+    pub executables: Vec<Fingerprint>,
+}
+
+impl SavedPlan {
+    /// Fingerprints every program in `plan`.
+    ///
+    /// # Errors
+    /// Returns an error if a program cannot be found or read.
+    pub fn new(plan: UpdatePlan) -> io::Result<SavedPlan> {
+        let steps = plan
+            .managers
+            .iter()
+            .flat_map(|m| &m.steps)
+            .chain(&plan.hooks)
+            .chain(plan.snapshot.as_ref().map(|s| &s.step));
+        let programs: BTreeSet<&str> = steps
+            .flat_map(|s| {
+                let escalation = s.escalation.and_then(Escalation::program);
+                std::iter::once(s.program.as_str()).chain(escalation)
+            })
+            .collect();
+        let executables = programs
+            .into_iter()
+            .map(fingerprint)
+            .collect::<io::Result<Vec<_>>>()?;
+        let created = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_secs());
+        Ok(SavedPlan {
+            format: FORMAT,
+            qud_version: env!("CARGO_PKG_VERSION").to_string(),
+            created,
+            plan,
+            executables,
+        })
+    }
+
+    /// Writes the plan as JSON to `path`, or to stdout if `path` is `-`.
+    ///
+    /// # Errors
+    /// Returns an error if the file cannot be written.
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        let json = serde_json::to_string_pretty(self)?;
+        if path == Path::new("-") {
+            println!("{json}");
+            Ok(())
+        } else {
+            fs::write(path, json + "\n")
+        }
+    }
+
+    /// Reads a plan written by [`SavedPlan::save`].
+    ///
+    /// # Errors
+    /// Returns an error if the file cannot be read, is not a plan or uses an unknown format.
+    pub fn load(path: &Path) -> Result<SavedPlan, Box<dyn Error>> {
+        let saved: SavedPlan = serde_json::from_str(&fs::read_to_string(path)?)?;
+        if saved.format != FORMAT {
+            return Err(format!(
+                "unsupported plan format {} (expected {FORMAT})",
+                saved.format
+            )
+            .into());
+        }
+        Ok(saved)
+    }
+
+    /// Checks that every program still resolves to the same, unchanged file.
+    /// Returns a description of each difference.
+    #[must_use]
+    pub fn changes(&self) -> Vec<String> {
+        let mut changes = Vec::new();
+        for recorded in &self.executables {
+            match fingerprint(&recorded.program) {
+                Err(e) => changes.push(format!("{}: {e}", recorded.program)),
+                Ok(current) if current.path != recorded.path => changes.push(format!(
+                    "{} now resolves to {} instead of {}",
+                    recorded.program,
+                    current.path.display(),
+                    recorded.path.display()
+                )),
+                Ok(current) if current.sha256 != recorded.sha256 => changes.push(format!(
+                    "{} ({}) has changed since the plan was made",
+                    recorded.program,
+                    recorded.path.display()
+                )),
+                Ok(_) => {}
+            }
+        }
+        changes
+    }
+}
+
+fn fingerprint(program: &str) -> io::Result<Fingerprint> {
+    let path = find_in_path(program)
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, format!("{program} not found")))?;
+    let digest = Sha256::digest(fs::read(&path)?);
+    let mut sha256 = String::with_capacity(64);
+    for byte in digest {
+        write!(sha256, "{byte:02x}").unwrap();
+    }
+    Ok(Fingerprint {
+        program: program.to_string(),
+        path,
+        sha256,
+    })
+}
@@ -1,7 +1,12 @@
 use std::io::Write;
 use std::{error::Error, fs, path::PathBuf, process::Command};
 
-pub fn self_update(noconfirm: bool) -> Result<(), Box<dyn Error>> {
+/// Rebuilds qud from the latest sources and replaces the installed binary.
+/// Returns `Ok(false)` if the user cancelled the update.
+///
+/// # Errors
+/// Returns an error if cloning, building or installing fails.
+pub fn self_update(noconfirm: bool) -> Result<bool, Box<dyn Error>> {
     let repo_url = "https://github.com/barely-a-dev/qud.git";
 
     if !noconfirm {
@@ -14,7 +19,7 @@ pub fn self_update(noconfirm: bool) -> Result<(), Box<dyn Error>> {
         let confirm = confirm.trim().to_lowercase();
         if confirm == "n" || confirm == "no" {
             println!("Update cancelled by user.");
-            std::process::exit(0);
+            return Ok(false);
         }
     }
 
@@ -103,7 +108,7 @@ pub fn self_update(noconfirm: bool) -> Result<(), Box<dyn Error>> {
     // Clean up temporary directory.
     fs::remove_dir_all(&temp_dir)?;
 
-    Ok(())
+    Ok(true)
 }
 
 pub mod perm {
@@ -134,6 +139,7 @@ pub mod perm {
         }
     }
 
+    #[must_use]
     pub fn is_elevated() -> bool {
         platform::is_elevated()
     }
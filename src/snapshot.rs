@@ -0,0 +1,327 @@
+//! Filesystem snapshots taken before system packages are updated.
+//!
+//! Only package managers that change the packages of the operating system (see
+//! [`PackageManager::snapshot`](crate::managers::PackageManager::snapshot)) trigger a snapshot,
+//! and a run takes at most one, before the first package manager runs.
+
+use crate::escalation::Escalation;
+use crate::exec::gen_upd_cmd;
+use crate::plan::{PlannedStep, StepKind};
+use crate::say;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use std::str::FromStr;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// How snapshots are taken.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(into = "String", try_from = "String")]
+pub enum Snapshot {
+    /// `snapper create`, with the named snapper configuration or the default one.
+    Snapper { config: Option<String> },
+    /// `timeshift --create`.
+    Timeshift,
+    /// A read-only `btrfs subvolume snapshot` of a subvolume, `/` by default.
+    Btrfs { subvolume: PathBuf },
+    /// An `lvcreate --snapshot` of a logical volume, given as `vg/lv`.
+    Lvm { volume: String },
+}
+
+impl FromStr for Snapshot {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Snapshot, String> {
+        let (tool, target) = match s.split_once(':') {
+            Some((tool, target)) => (tool, Some(target).filter(|t| !t.is_empty())),
+            None => (s, None),
+        };
+        match (tool, target) {
+            ("snapper", config) => Ok(Snapshot::Snapper {
+                config: config.map(ToString::to_string),
+            }),
+            ("timeshift", None) => Ok(Snapshot::Timeshift),
+            ("btrfs", subvolume) => Ok(Snapshot::Btrfs {
+                subvolume: PathBuf::from(subvolume.unwrap_or("/")),
+            }),
+            ("lvm", Some(volume)) if volume.contains('/') => Ok(Snapshot::Lvm {
+                volume: volume.to_string(),
+            }),
+            ("lvm", _) => Err(format!(
+                "Invalid snapshot: {s} (expected lvm:volume-group/logical-volume)"
+            )),
+            _ => Err(format!(
+                "Invalid snapshot: {s} (expected snapper[:config], timeshift, btrfs[:subvolume] or lvm:vg/lv)"
+            )),
+        }
+    }
+}
+
+impl fmt::Display for Snapshot {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Snapshot::Snapper { config: None } => write!(f, "snapper"),
+            Snapshot::Snapper {
+                config: Some(config),
+            } => write!(f, "snapper:{config}"),
+            Snapshot::Timeshift => write!(f, "timeshift"),
+            Snapshot::Btrfs { subvolume } => write!(f, "btrfs:{}", subvolume.display()),
+            Snapshot::Lvm { volume } => write!(f, "lvm:{volume}"),
+        }
+    }
+}
+
+impl From<Snapshot> for String {
+    fn from(snapshot: Snapshot) -> String {
+        snapshot.to_string()
+    }
+}
+
+impl TryFrom<String> for Snapshot {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Snapshot, String> {
+        s.parse()
+    }
+}
+
+/// A snapshot a plan will take.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SnapshotPlan {
+    pub tool: Snapshot,
+    pub step: PlannedStep,
+    /// The id of the snapshot if it is chosen by qud (btrfs and LVM), otherwise it is read from
+    /// the output of the step.
+    pub id: Option<String>,
+}
+
+impl SnapshotPlan {
+    /// Plans a snapshot with `tool`, named after the current time, gaining privileges through
+    /// `escalation`.
+    #[must_use]
+    pub fn new(tool: &Snapshot, dir: &Path, escalation: Option<Escalation>) -> SnapshotPlan {
+        const DESCRIPTION: &str = "qud pre-update";
+        let name = format!(
+            "qud-{}",
+            SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |d| d.as_secs())
+        );
+        let (program, args, id) = match tool {
+            Snapshot::Snapper { config } => {
+                let mut args = Vec::new();
+                if let Some(config) = config {
+                    args.extend(["-c".to_string(), config.clone()]);
+                }
+                args.extend(
+                    [
+                        "create",
+                        "--type",
+                        "single",
+                        "--cleanup-algorithm",
+                        "number",
+                        "--print-number",
+                        "--description",
+                        DESCRIPTION,
+                    ]
+                    .map(ToString::to_string),
+                );
+                ("snapper", args, None)
+            }
+            Snapshot::Timeshift => (
+                "timeshift",
+                [
+                    "--create",
+                    "--scripted",
+                    "--tags",
+                    "O",
+                    "--comments",
+                    DESCRIPTION,
+                ]
+                .map(ToString::to_string)
+                .to_vec(),
+                None,
+            ),
+            Snapshot::Btrfs { subvolume } => {
+                let target = subvolume.join(format!(".{name}"));
+                (
+                    "btrfs",
+                    vec![
+                        "subvolume".to_string(),
+                        "snapshot".to_string(),
+                        "-r".to_string(),
+                        subvolume.display().to_string(),
+                        target.display().to_string(),
+                    ],
+                    Some(target.display().to_string()),
+                )
+            }
+            Snapshot::Lvm { volume } => {
+                let group = volume.split('/').next().unwrap_or_default();
+                (
+                    "lvcreate",
+                    [
+                        "--snapshot",
+                        "--extents",
+                        "10%ORIGIN",
+                        "--name",
+                        &name,
+                        volume,
+                    ]
+                    .map(ToString::to_string)
+                    .to_vec(),
+                    Some(format!("{group}/{name}")),
+                )
+            }
+        };
+        SnapshotPlan {
+            tool: tool.clone(),
+            step: PlannedStep {
+                kind: StepKind::Snapshot,
+                program: program.to_string(),
+                args,
+                privileged: true,
+                escalation,
+                user: None,
+                dir: dir.to_path_buf(),
+                env: BTreeMap::new(),
+                idempotent: false,
+            },
+            id,
+        }
+    }
+
+    /// Takes the snapshot. Its output is shown, except for what is needed to find its id.
+    #[must_use]
+    pub fn take(&self) -> SnapshotRecord {
+        let mut record = SnapshotRecord {
+            tool: self.tool.clone(),
+            id: self.id.clone(),
+            error: None,
+        };
+        let output = gen_upd_cmd(&self.step)
+            .stdout(Stdio::piped())
+            .stderr(Stdio::inherit())
+            .output();
+        match output {
+            Ok(output) if output.status.success() => {
+                let stdout = String::from_utf8_lossy(&output.stdout);
+                if let Snapshot::Snapper { .. } = self.tool {
+                    // --print-number prints nothing else.
+                    record.id = stdout.split_whitespace().next().map(ToString::to_string);
+                    return record;
+                }
+                if !stdout.trim().is_empty() {
+                    say!("{}", stdout.trim_end());
+                }
+                if self.tool == Snapshot::Timeshift {
+                    // "Tagged snapshot '2024-01-01_12-00-00': ondemand"
+                    record.id = stdout.lines().find_map(|line| {
+                        let (_, rest) = line.split_once("snapshot '")?;
+                        rest.split_once('\'').map(|(id, _)| id.to_string())
+                    });
+                }
+            }
+            Ok(output) => {
+                record.error = Some(format!("{} exited with {}", self.step, output.status))
+            }
+            Err(e) => record.error = Some(format!("{}: {e}", self.step.program)),
+        }
+        record
+    }
+}
+
+/// A snapshot taken (or attempted) before updating.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SnapshotRecord {
+    pub tool: Snapshot,
+    /// The snapper number, timeshift name, btrfs path or LVM volume of the snapshot.
+    pub id: Option<String>,
+    /// Why the snapshot could not be taken.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl SnapshotRecord {
+    /// How to return to the snapshot.
+    #[must_use]
+    pub fn rollback_hint(&self) -> Option<String> {
+        let id = self.id.as_deref()?;
+        Some(match &self.tool {
+            Snapshot::Snapper { config: None } => format!("sudo snapper rollback {id}"),
+            Snapshot::Snapper {
+                config: Some(config),
+            } => format!("sudo snapper -c {config} undochange {id}..0"),
+            Snapshot::Timeshift => format!("sudo timeshift --restore --snapshot '{id}'"),
+            Snapshot::Btrfs { .. } => {
+                format!("restore files from the read-only snapshot {id}")
+            }
+            Snapshot::Lvm { .. } => format!("sudo lvconvert --merge {id} and reboot"),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_snapshot_tools() {
+        assert_eq!("snapper".parse(), Ok(Snapshot::Snapper { config: None }));
+        assert_eq!(
+            "snapper:home".parse(),
+            Ok(Snapshot::Snapper {
+                config: Some("home".to_string())
+            })
+        );
+        assert_eq!("timeshift".parse(), Ok(Snapshot::Timeshift));
+        assert_eq!(
+            "btrfs".parse(),
+            Ok(Snapshot::Btrfs {
+                subvolume: PathBuf::from("/")
+            })
+        );
+        assert_eq!(
+            "btrfs:/home".parse(),
+            Ok(Snapshot::Btrfs {
+                subvolume: PathBuf::from("/home")
+            })
+        );
+        assert_eq!(
+            "lvm:vg0/root".parse(),
+            Ok(Snapshot::Lvm {
+                volume: "vg0/root".to_string()
+            })
+        );
+    }
+
+    #[test]
+    fn parse_snapshot_empty_target() {
+        assert_eq!("snapper:".parse(), Ok(Snapshot::Snapper { config: None }));
+    }
+
+    #[test]
+    fn parse_snapshot_rejects_invalid() {
+        for val in ["", "zfs", "timeshift:x", "lvm", "lvm:root", "Snapper"] {
+            assert!(val.parse::<Snapshot>().is_err(), "{val:?} was accepted");
+        }
+    }
+
+    #[test]
+    fn snapshot_round_trips() {
+        for val in [
+            "snapper",
+            "snapper:home",
+            "timeshift",
+            "btrfs:/",
+            "lvm:vg0/root",
+        ] {
+            assert_eq!(
+                val.parse::<Snapshot>().map(|s| s.to_string()),
+                Ok(val.to_string())
+            );
+        }
+    }
+}
@@ -0,0 +1,262 @@
+//! End-of-run summaries and process exit codes.
+
+use crate::exec::{as_secs, from_secs, FailureReason, Outcome, StepResult};
+use crate::plan::{StepKind, UpdatePlan};
+use crate::say;
+use crate::snapshot::SnapshotRecord;
+use colored::Colorize;
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+/// The exit codes of qud.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExitCode {
+    /// Every update succeeded, or there was nothing to do.
+    Success = 0,
+    /// Invalid options, configuration files or environment variables.
+    ConfigError = 1,
+    /// Some package managers failed to update, others succeeded.
+    PartialFailure = 2,
+    /// Every attempted update failed, or qud could not do what it was asked.
+    Failure = 3,
+    /// The user cancelled.
+    Aborted = 4,
+    /// `qud outdated` found pending updates. Chosen to match `dnf check-update`.
+    UpdatesAvailable = 100,
+}
+
+impl ExitCode {
+    /// Exits the process with this code.
+    pub fn exit(self) -> ! {
+        std::process::exit(self as i32)
+    }
+}
+
+/// What happened to a package manager during a run.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Status {
+    Succeeded,
+    Failed,
+    /// Refreshed, but nothing was pending, so nothing was upgraded.
+    UpToDate,
+    /// Filtered out, excluded, skipped at confirmation or never reached.
+    Skipped,
+    /// A pre-hook failed, so nothing was updated.
+    Vetoed,
+    /// Nothing to update, e.g. a project manager outside a project.
+    NotApplicable,
+}
+
+impl Status {
+    fn label(self) -> colored::ColoredString {
+        match self {
+            Status::Succeeded => "succeeded".green(),
+            Status::Failed => "failed".red(),
+            Status::UpToDate => "up to date".green(),
+            Status::Skipped => "skipped".yellow(),
+            Status::Vetoed => "vetoed".yellow(),
+            Status::NotApplicable => "not applicable".normal(),
+        }
+    }
+}
+
+/// The outcome of one package manager.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ManagerSummary {
+    /// The package manager's [label](crate::plan::ManagerPlan::label).
+    pub name: String,
+    pub status: Status,
+    /// Why the first failed step failed.
+    pub reason: Option<FailureReason>,
+    /// How often steps were retried.
+    pub retries: u32,
+    /// Whether a post-hook failed.
+    pub hook_failed: bool,
+    /// Time spent running the package manager's steps.
+    #[serde(
+        rename = "elapsed_secs",
+        serialize_with = "as_secs",
+        deserialize_with = "from_secs"
+    )]
+    pub elapsed: Duration,
+}
+
+/// The outcome of a run.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Summary {
+    pub managers: Vec<ManagerSummary>,
+    /// Failed hooks of the whole run.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub failed_hooks: Vec<String>,
+    /// The snapshot taken before updating system packages.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub snapshot: Option<SnapshotRecord>,
+    /// Whether a package manager covered by the snapshot failed.
+    #[serde(skip)]
+    rollback: bool,
+    /// Wall-clock time of the whole run.
+    #[serde(
+        rename = "elapsed_secs",
+        serialize_with = "as_secs",
+        deserialize_with = "from_secs"
+    )]
+    pub elapsed: Duration,
+}
+
+impl Summary {
+    /// Summarizes the outcome of running `plan`.
+    #[must_use]
+    pub fn new(plan: &UpdatePlan, outcome: &Outcome, elapsed: Duration) -> Summary {
+        let mut managers: Vec<ManagerSummary> = plan
+            .managers
+            .iter()
+            .map(|manager| {
+                let label = manager.label();
+                let ran: Vec<&StepResult> = outcome
+                    .results
+                    .iter()
+                    .filter(|r| r.manager == label)
+                    .collect();
+                let failed = |kind| ran.iter().any(|r| r.kind == kind && !r.success);
+                let status = if manager.steps.is_empty() {
+                    Status::NotApplicable
+                } else if failed(StepKind::PreHook) {
+                    Status::Vetoed
+                } else if ran.iter().any(|r| !r.success) {
+                    Status::Failed
+                } else if outcome.up_to_date.contains(&label) {
+                    Status::UpToDate
+                } else if ran.is_empty() {
+                    Status::Skipped
+                } else {
+                    Status::Succeeded
+                };
+                ManagerSummary {
+                    name: label,
+                    status,
+                    reason: ran.iter().find_map(|r| r.reason),
+                    retries: ran.iter().map(|r| r.attempts.saturating_sub(1)).sum(),
+                    hook_failed: failed(StepKind::PostHook),
+                    elapsed: ran.iter().map(|r| r.duration).sum(),
+                }
+            })
+            .collect();
+        managers.extend(plan.skipped.iter().map(|name| ManagerSummary {
+            name: name.clone(),
+            status: Status::Skipped,
+            reason: None,
+            retries: 0,
+            hook_failed: false,
+            elapsed: Duration::ZERO,
+        }));
+        let failed_hooks = outcome
+            .results
+            .iter()
+            .filter(|r| r.manager.is_empty() && !r.success)
+            .filter_map(|r| r.args.last().cloned())
+            .collect();
+        let rollback = plan.managers.iter().any(|planned| {
+            planned.snapshot
+                && managers
+                    .iter()
+                    .any(|m| m.name == planned.name && m.status == Status::Failed)
+        });
+        Summary {
+            managers,
+            failed_hooks,
+            snapshot: outcome.snapshot.clone(),
+            rollback,
+            elapsed,
+        }
+    }
+
+    /// [`ExitCode::Success`] unless a package manager, hook or snapshot failed. If no package manager
+    /// succeeded or was up to date, [`ExitCode::Failure`], otherwise [`ExitCode::PartialFailure`].
+    #[must_use]
+    pub fn exit_code(&self) -> ExitCode {
+        let count = |status| self.managers.iter().filter(|m| m.status == status).count();
+        let snapshot_failed = self.snapshot.as_ref().is_some_and(|s| s.error.is_some());
+        let failed = count(Status::Failed) + self.failed_hooks.len() + usize::from(snapshot_failed);
+        let succeeded = count(Status::Succeeded) + count(Status::UpToDate);
+        match (failed, succeeded) {
+            (0, _) => ExitCode::Success,
+            (_, 0) => ExitCode::Failure,
+            _ => ExitCode::PartialFailure,
+        }
+    }
+
+    /// Prints the summary as a table.
+    pub fn print(&self) {
+        let width = self
+            .managers
+            .iter()
+            .map(|m| m.name.len())
+            .max()
+            .unwrap_or(0);
+        say!("{} Summary:", "INFO:".blue());
+        for manager in &self.managers {
+            let label = manager.status.label();
+            match manager.status {
+                Status::Succeeded | Status::Failed | Status::UpToDate => {
+                    let mut notes = Vec::new();
+                    match manager.reason {
+                        Some(FailureReason::Timeout) => notes.push("timed out".to_string()),
+                        Some(FailureReason::Stalled) => notes.push("stalled".to_string()),
+                        Some(FailureReason::Spawn) => notes.push("could not start".to_string()),
+                        Some(FailureReason::Locked) => notes.push("locked".to_string()),
+                        Some(FailureReason::Exit) | None => {}
+                    }
+                    if manager.hook_failed {
+                        notes.push("post-hook failed".to_string());
+                    }
+                    match manager.retries {
+                        0 => {}
+                        1 => notes.push("1 retry".to_string()),
+                        n => notes.push(format!("{n} retries")),
+                    }
+                    let notes = if notes.is_empty() {
+                        String::new()
+                    } else {
+                        format!("  ({})", notes.join(", "))
+                    };
+                    say!(
+                        "  {:width$}  {:14}  {:>7.1}s{notes}",
+                        manager.name,
+                        label,
+                        manager.elapsed.as_secs_f64()
+                    );
+                }
+                Status::Skipped | Status::Vetoed | Status::NotApplicable => {
+                    say!("  {:width$}  {}", manager.name, label);
+                }
+            }
+        }
+        for hook in &self.failed_hooks {
+            say!("  {} {hook}", "hook failed:".red());
+        }
+        match &self.snapshot {
+            Some(SnapshotRecord { error: Some(e), .. }) => {
+                say!("  {} {e}", "snapshot failed:".red());
+            }
+            Some(snapshot) => say!(
+                "  snapshot: {} {}",
+                snapshot.tool,
+                snapshot.id.as_deref().unwrap_or("(id unknown)")
+            ),
+            None => {}
+        }
+        if let Some(hint) = self
+            .snapshot
+            .as_ref()
+            .filter(|_| self.rollback)
+            .and_then(SnapshotRecord::rollback_hint)
+        {
+            say!(
+                "{} System packages failed to update. To roll back: {hint}",
+                "WARN:".yellow()
+            );
+        }
+        say!("Finished in {:.1}s.", self.elapsed.as_secs_f64());
+    }
+}